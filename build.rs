@@ -0,0 +1,35 @@
+// Generates chip8.h, the header of the C API in src/capi.rs, in the build directory. The
+// committed include/chip8.h is only rewritten with CHIP8_UPDATE_HEADER=1, and a test of
+// src/capi.rs checks that it is up to date.
+fn main() {
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    println!("cargo:rerun-if-env-changed=CHIP8_UPDATE_HEADER");
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        header: Some(
+            [
+                "/* Generated from src/capi.rs by build.rs, do not edit.",
+                " * Handles must come from chip8_create and not be given to chip8_destroy yet,",
+                " * only chip8_destroy accepts NULL. Functions returning int give 0 on success",
+                " * and -1 on error, described by chip8_last_error. */",
+            ]
+            .join("\n"),
+        ),
+        include_guard: Some("CHIP8_H".to_string()),
+        cpp_compat: true,
+        usize_is_size_t: true,
+        style: cbindgen::Style::Type,
+        documentation_style: cbindgen::DocumentationStyle::C99,
+        ..Default::default()
+    };
+    let header = cbindgen::Builder::new()
+        .with_config(config)
+        .with_src("src/capi.rs")
+        .generate()
+        .expect("Unable to generate the C header");
+    let out_dir = std::path::PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+    header.write_to_file(out_dir.join("chip8.h"));
+    if std::env::var_os("CHIP8_UPDATE_HEADER").is_some_and(|value| value == "1") {
+        header.write_to_file("include/chip8.h");
+    }
+}
@@ -0,0 +1,72 @@
+/*
+ * Runs a ROM, or a small program drawing a digit, through the C API and prints the screen.
+ * From the repository root, after cargo build --no-default-features, which builds the library
+ * without SDL2:
+ *
+ *     cc examples/c/example.c -I include -L target/debug -lchip8 -o example
+ *     LD_LIBRARY_PATH=target/debug ./example [ROM] [FRAMES]
+ */
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "chip8.h"
+
+/* LD I, 0x20A; LD V1, 2; DRW V1, V1, 5; LD ST, V1; JP 0x208, then the sprite of a 5 */
+static const uint8_t PROGRAM[] = {0xA2, 0x0A, 0x61, 0x02, 0xD1, 0x15, 0xF1, 0x18,
+                                  0x12, 0x08, 0xF0, 0x80, 0xF0, 0x10, 0xF0};
+
+static void print_screen(const uint8_t *framebuffer, int rows) {
+    for (int y = 0; y < rows; y++) {
+        for (int x = 0; x < CHIP8_SCREEN_WIDTH; x++) {
+            putchar(framebuffer[y * CHIP8_SCREEN_WIDTH + x] ? '#' : '.');
+        }
+        putchar('\n');
+    }
+}
+
+int main(int argc, char **argv) {
+    Chip8 *chip8 = chip8_create();
+    int loaded = argc > 1 ? chip8_load_rom(chip8, argv[1])
+                          : chip8_load_program(chip8, PROGRAM, sizeof PROGRAM);
+    if (loaded != 0) {
+        fprintf(stderr, "%s\n", chip8_last_error(chip8));
+        chip8_destroy(chip8);
+        return 1;
+    }
+    int frames = argc > 2 ? atoi(argv[2]) : 1;
+
+    size_t state_size = chip8_state_size();
+    uint8_t *state = malloc(state_size);
+    if (chip8_save_state(chip8, state, state_size) != 0) {
+        fprintf(stderr, "%s\n", chip8_last_error(chip8));
+        return 1;
+    }
+
+    long instructions = 0;
+    for (int frame = 0; frame < frames; frame++) {
+        int executed = chip8_run_frame(chip8);
+        if (executed < 0) {
+            fprintf(stderr, "%s\n", chip8_last_error(chip8));
+            return 1;
+        }
+        instructions += executed;
+    }
+    printf("instructions: %ld\n", instructions);
+    printf("sound: %s\n", chip8_sound_active(chip8) ? "on" : "off");
+    print_screen(chip8_framebuffer(chip8), argc > 1 ? CHIP8_SCREEN_HEIGHT : 8);
+
+    /* Back to the start, where the screen is blank */
+    if (chip8_load_state(chip8, state, state_size) != 0) {
+        fprintf(stderr, "%s\n", chip8_last_error(chip8));
+        return 1;
+    }
+    int lit = 0;
+    for (int pixel = 0; pixel < CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT; pixel++) {
+        lit += chip8_framebuffer(chip8)[pixel];
+    }
+    printf("lit after restore: %d\n", lit);
+
+    free(state);
+    chip8_destroy(chip8);
+    return 0;
+}
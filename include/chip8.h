@@ -0,0 +1,79 @@
+/* Generated from src/capi.rs by build.rs, do not edit.
+ * Handles must come from chip8_create and not be given to chip8_destroy yet,
+ * only chip8_destroy accepts NULL. Functions returning int give 0 on success
+ * and -1 on error, described by chip8_last_error. */
+
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define CHIP8_SCREEN_WIDTH 64
+
+#define CHIP8_SCREEN_HEIGHT 32
+
+#define CHIP8_NUMBER_KEYS 16
+
+// Opaque handle of an emulator
+typedef struct Chip8 Chip8;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Emulator without a program, freed by chip8_destroy
+Chip8 *chip8_create(void);
+
+// Accepts NULL
+void chip8_destroy(Chip8 *chip8);
+
+// Message of the last error, valid until the next call with the same handle
+const char *chip8_last_error(const Chip8 *chip8);
+
+// ROM file, raw or in an Octo cartridge or zip archive, with its quirks and speed from the
+// file or the ROM database
+int chip8_load_rom(Chip8 *chip8, const char *path);
+
+// Resets the emulator, then loads the program at 0x200
+int chip8_load_program(Chip8 *chip8, const uint8_t *program, size_t length);
+
+// Runs one instruction. Returns -1 on an invalid instruction, the emulator then stops until
+// a program or state is loaded.
+int chip8_step(Chip8 *chip8);
+
+// Runs the instructions of one frame, then ticks the timers.
+// Returns the number of instructions executed.
+int chip8_run_frame(Chip8 *chip8);
+
+// Speed of chip8_run_frame, set by chip8_load_rom. At most INT_MAX, the largest number of
+// instructions chip8_run_frame can return.
+int chip8_set_instructions_per_frame(Chip8 *chip8, uint32_t instructions_per_frame);
+
+// CHIP8_SCREEN_HEIGHT rows of CHIP8_SCREEN_WIDTH pixels, 1 when lit and 0 otherwise.
+// The pointer stays valid as long as the handle.
+const uint8_t *chip8_framebuffer(const Chip8 *chip8);
+
+// Keys from 0 to 15, others are ignored
+void chip8_set_key(Chip8 *chip8, uint8_t key, bool pressed);
+
+// Whether the beeper sounds, while the sound timer is not zero
+bool chip8_sound_active(const Chip8 *chip8);
+
+// Size of the buffers of chip8_save_state and chip8_load_state
+size_t chip8_state_size(void);
+
+// Writes chip8_state_size() bytes to the buffer
+int chip8_save_state(Chip8 *chip8, uint8_t *buffer, size_t size);
+
+// Restores a state written by chip8_save_state
+int chip8_load_state(Chip8 *chip8, const uint8_t *buffer, size_t size);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHIP8_H */
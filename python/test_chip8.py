@@ -0,0 +1,73 @@
+# Tests of the Python extension module. Build it and run them from the repository root with:
+#
+#     cargo build --release --no-default-features --features python
+#     cp target/release/libchip8.so python/chip8.so
+#     python3 -m unittest discover python
+
+import unittest
+
+import chip8
+
+# LD V0, 0x07; LD I, 0x300; LD [I], V0; DRW V0, V0, 1; JP 0x208
+PROGRAM = bytes([0x60, 0x07, 0xA3, 0x00, 0xF0, 0x55, 0xD0, 0x01, 0x12, 0x08])
+
+
+def emulator_with_program():
+    emulator = chip8.Emulator()
+    emulator.load_program(PROGRAM)
+    return emulator
+
+
+class EmulatorTest(unittest.TestCase):
+    def test_step_and_registers(self):
+        emulator = emulator_with_program()
+        emulator.step()
+        self.assertEqual(emulator.registers[0], 7)
+        self.assertEqual(emulator.pc, 0x202)
+        emulator.pc = 0x204
+        emulator.index = 0x310
+        emulator.step()
+        self.assertEqual(bytes(emulator.memory)[0x310], 7)
+
+    def test_memory_view(self):
+        emulator = emulator_with_program()
+        memory = memoryview(emulator.memory)
+        self.assertEqual((memory.shape, memory.format, memory.readonly), ((4096,), "B", False))
+        self.assertEqual(memory[0x200], 0x60)
+        memory[0x201] = 0x09
+        emulator.step()
+        self.assertEqual(emulator.registers[0], 9)
+
+    def test_screen_view(self):
+        emulator = emulator_with_program()
+        emulator.run_frame(4)
+        screen = memoryview(emulator.screen)
+        self.assertEqual((screen.shape, screen.readonly), ((32, 64), True))
+        # The sprite byte at 0x300 is 7, drawn at (7, 7)
+        self.assertEqual(screen.tolist()[7][11:16], [0, 1, 1, 1, 0])
+
+    def test_keys_and_states(self):
+        emulator = emulator_with_program()
+        emulator.set_key(5, True)
+        self.assertTrue(emulator.key_pressed(5))
+        state = emulator.save_state()
+        emulator.run_frame()
+        self.assertNotEqual(emulator.pc, 0x200)
+        emulator.load_state(state)
+        self.assertEqual(emulator.pc, 0x200)
+        with self.assertRaises(ValueError):
+            emulator.load_state(b"not a state")
+        with self.assertRaises(ValueError):
+            emulator.set_key(16, True)
+
+    def test_disassembler(self):
+        emulator = emulator_with_program()
+        self.assertEqual(
+            emulator.disassemble(0x200, 0x204),
+            [(0x200, 0x6007, "LD V0, 0x07"), (0x202, 0xA300, "LD I, 0x300")],
+        )
+        self.assertEqual(chip8.disassemble_instruction(0x00E0), "CLS")
+
+
+if __name__ == "__main__":
+    unittest.main()
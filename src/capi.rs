@@ -0,0 +1,332 @@
+// C API of the emulator core, declared in include/chip8.h which build.rs generates from this
+// file with its doc comments, rewritten by building with CHIP8_UPDATE_HEADER=1. Functions
+// take the handle returned by chip8_create, until it is given to chip8_destroy, never NULL
+// except chip8_destroy, and buffers of at least the given size. Those returning int give 0 on success and -1 on error,
+// described by chip8_last_error.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::emulator::{self, Emulator, PixelStatus, Snapshot, SNAPSHOT_SIZE};
+use crate::frontend::{load_headless, INSTRUCTIONS_PER_FRAME};
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+pub const CHIP8_SCREEN_WIDTH: usize = 64;
+pub const CHIP8_SCREEN_HEIGHT: usize = 32;
+pub const CHIP8_NUMBER_KEYS: usize = 16;
+
+// The header is generated from this file alone, so the sizes are repeated here
+const _: () = assert!(CHIP8_SCREEN_WIDTH == emulator::CHIP8_SCREEN_WIDTH);
+const _: () = assert!(CHIP8_SCREEN_HEIGHT == emulator::CHIP8_SCREEN_HEIGHT);
+const _: () = assert!(CHIP8_NUMBER_KEYS == emulator::CHIP8_NUMBER_KEYS);
+const _: () = assert!(std::mem::size_of::<PixelStatus>() == 1);
+
+/// Opaque handle of an emulator
+pub struct Chip8 {
+    emulator: Emulator,
+    instructions_per_frame: u32,
+    // Set when the program reached an instruction the emulator cannot run
+    crashed: bool,
+    last_error: CString,
+}
+
+impl Chip8 {
+    fn fail(&mut self, error: &str) -> c_int {
+        self.last_error = CString::new(error.replace('\0', " ")).unwrap();
+        -1
+    }
+
+    // Panics of the emulator, on instructions it cannot run, stop it instead of unwinding
+    // into the host
+    fn run(&mut self, run: impl FnOnce(&mut Emulator) -> u32) -> c_int {
+        if self.crashed {
+            return self.fail("The emulator stopped on an invalid instruction");
+        }
+        match catch_unwind(AssertUnwindSafe(|| run(&mut self.emulator))) {
+            Ok(instructions_executed) => instructions_executed as c_int,
+            Err(_) => {
+                self.crashed = true;
+                self.fail("The emulator stopped on an invalid instruction")
+            }
+        }
+    }
+
+    // chip8_run_frame returns the number of instructions executed as an int
+    fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) -> c_int {
+        if c_int::try_from(instructions_per_frame).is_err() {
+            return self.fail(&format!(
+                "{} instructions per frame is more than an int holds",
+                instructions_per_frame
+            ));
+        }
+        self.instructions_per_frame = instructions_per_frame;
+        0
+    }
+
+    // Loading goes through the ROM loader and the decoders, whose panics must not unwind
+    // into the host either
+    fn load(&mut self, load: impl FnOnce(&mut Self) -> c_int) -> c_int {
+        match catch_unwind(AssertUnwindSafe(|| load(self))) {
+            Ok(result) => result,
+            Err(_) => self.fail("The emulator failed while loading"),
+        }
+    }
+}
+
+/// Emulator without a program, freed by chip8_destroy
+#[no_mangle]
+pub extern "C" fn chip8_create() -> *mut Chip8 {
+    let mut emulator = Emulator::new();
+    emulator.trace = false;
+    Box::into_raw(Box::new(Chip8 {
+        emulator,
+        instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+        crashed: false,
+        last_error: CString::default(),
+    }))
+}
+
+/// Accepts NULL
+#[no_mangle]
+pub unsafe extern "C" fn chip8_destroy(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(unsafe { Box::from_raw(chip8) });
+    }
+}
+
+/// Message of the last error, valid until the next call with the same handle
+#[no_mangle]
+pub unsafe extern "C" fn chip8_last_error(chip8: *const Chip8) -> *const c_char {
+    unsafe { &*chip8 }.last_error.as_ptr()
+}
+
+/// ROM file, raw or in an Octo cartridge or zip archive, with its quirks and speed from the
+/// file or the ROM database
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(chip8: *mut Chip8, path: *const c_char) -> c_int {
+    let chip8 = unsafe { &mut *chip8 };
+    let Ok(path) = unsafe { CStr::from_ptr(path) }.to_str() else {
+        return chip8.fail("The path is not valid UTF-8");
+    };
+    chip8.load(|chip8| match load_headless(Path::new(path), None) {
+        Ok((mut emulator, _, instructions_per_frame)) => {
+            if chip8.set_instructions_per_frame(instructions_per_frame) != 0 {
+                return -1;
+            }
+            emulator.trace = false;
+            chip8.emulator = emulator;
+            chip8.crashed = false;
+            0
+        }
+        Err(error) => chip8.fail(&error),
+    })
+}
+
+/// Resets the emulator, then loads the program at 0x200
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_program(
+    chip8: *mut Chip8,
+    program: *const u8,
+    length: usize,
+) -> c_int {
+    let chip8 = unsafe { &mut *chip8 };
+    let program = unsafe { std::slice::from_raw_parts(program, length) };
+    if let Err(error) = emulator::check_program_size(program) {
+        return chip8.fail(&error);
+    }
+    chip8.load(|chip8| {
+        chip8.emulator.reset();
+        chip8.emulator.load_program(program);
+        chip8.crashed = false;
+        0
+    })
+}
+
+/// Runs one instruction. Returns -1 on an invalid instruction, the emulator then stops until
+/// a program or state is loaded.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(chip8: *mut Chip8) -> c_int {
+    let chip8 = unsafe { &mut *chip8 };
+    chip8
+        .run(|emulator| {
+            emulator.process_next_instruction();
+            1
+        })
+        .min(0)
+}
+
+/// Runs the instructions of one frame, then ticks the timers.
+/// Returns the number of instructions executed.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(chip8: *mut Chip8) -> c_int {
+    let chip8 = unsafe { &mut *chip8 };
+    let instructions_per_frame = chip8.instructions_per_frame;
+    chip8.run(|emulator| emulator.run_frame(instructions_per_frame))
+}
+
+/// Speed of chip8_run_frame, set by chip8_load_rom. At most INT_MAX, the largest number of
+/// instructions chip8_run_frame can return.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_instructions_per_frame(
+    chip8: *mut Chip8,
+    instructions_per_frame: u32,
+) -> c_int {
+    unsafe { &mut *chip8 }.set_instructions_per_frame(instructions_per_frame)
+}
+
+/// CHIP8_SCREEN_HEIGHT rows of CHIP8_SCREEN_WIDTH pixels, 1 when lit and 0 otherwise.
+/// The pointer stays valid as long as the handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(chip8: *const Chip8) -> *const u8 {
+    unsafe { &*chip8 }.emulator.screen.as_ptr() as *const u8
+}
+
+/// Keys from 0 to 15, others are ignored
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(chip8: *mut Chip8, key: u8, pressed: bool) {
+    if (key as usize) < CHIP8_NUMBER_KEYS {
+        unsafe { &mut *chip8 }.emulator.input_key(key, pressed);
+    }
+}
+
+/// Whether the beeper sounds, while the sound timer is not zero
+#[no_mangle]
+pub unsafe extern "C" fn chip8_sound_active(chip8: *const Chip8) -> bool {
+    unsafe { &*chip8 }.emulator.sound_clock > 0
+}
+
+/// Size of the buffers of chip8_save_state and chip8_load_state
+#[no_mangle]
+pub extern "C" fn chip8_state_size() -> usize {
+    SNAPSHOT_SIZE
+}
+
+/// Writes chip8_state_size() bytes to the buffer
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(
+    chip8: *mut Chip8,
+    buffer: *mut u8,
+    size: usize,
+) -> c_int {
+    let chip8 = unsafe { &mut *chip8 };
+    if size < SNAPSHOT_SIZE {
+        return chip8.fail("The state buffer is too small");
+    }
+    let state = chip8.emulator.snapshot().to_bytes();
+    unsafe { std::slice::from_raw_parts_mut(buffer, SNAPSHOT_SIZE) }.copy_from_slice(&state);
+    0
+}
+
+/// Restores a state written by chip8_save_state
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(
+    chip8: *mut Chip8,
+    buffer: *const u8,
+    size: usize,
+) -> c_int {
+    let chip8 = unsafe { &mut *chip8 };
+    let state = unsafe { std::slice::from_raw_parts(buffer, size) };
+    chip8.load(|chip8| match Snapshot::from_bytes(state) {
+        Ok(snapshot) => {
+            chip8.emulator.restore(&snapshot);
+            chip8.crashed = false;
+            0
+        }
+        Err(error) => chip8.fail(&error),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_and_states() {
+        // LD V0, 0x0A; LD ST, V0; LD I, 0x300; DRW V0, V0, 1; JP 0x206
+        let program = [0x60, 0x0A, 0xF0, 0x18, 0xA3, 0x00, 0xD0, 0x01, 0x12, 0x06];
+        unsafe {
+            let chip8 = chip8_create();
+            assert_eq!(
+                chip8_load_program(chip8, program.as_ptr(), program.len()),
+                0
+            );
+            assert_eq!(chip8_run_frame(chip8), 20);
+            assert!(chip8_sound_active(chip8));
+            let framebuffer = std::slice::from_raw_parts(
+                chip8_framebuffer(chip8),
+                CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT,
+            );
+            assert_eq!(framebuffer.iter().filter(|pixel| **pixel == 1).count(), 0);
+
+            let mut state = vec![0; chip8_state_size()];
+            assert_eq!(chip8_save_state(chip8, state.as_mut_ptr(), state.len()), 0);
+            assert_eq!(chip8_save_state(chip8, state.as_mut_ptr(), 10), -1);
+            assert_eq!(
+                CStr::from_ptr(chip8_last_error(chip8)).to_str(),
+                Ok("The state buffer is too small")
+            );
+            chip8_load_program(chip8, program.as_ptr(), program.len());
+            assert_eq!(chip8_load_state(chip8, state.as_ptr(), state.len()), 0);
+            assert!(chip8_sound_active(chip8));
+            chip8_destroy(chip8);
+        }
+    }
+
+    #[test]
+    fn test_oversized_rom() {
+        let path = std::env::temp_dir().join(format!("chip8_capi_{}.ch8", std::process::id()));
+        std::fs::write(&path, [0x12; 5000]).unwrap();
+        let path_string = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            let chip8 = chip8_create();
+            assert_eq!(chip8_load_rom(chip8, path_string.as_ptr()), -1);
+            assert!(CStr::from_ptr(chip8_last_error(chip8))
+                .to_str()
+                .unwrap()
+                .contains("5000 bytes"));
+            let program = [0x12; 5000];
+            assert_eq!(
+                chip8_load_program(chip8, program.as_ptr(), program.len()),
+                -1
+            );
+            chip8_destroy(chip8);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_header_up_to_date() {
+        assert!(
+            include_str!("../include/chip8.h")
+                == include_str!(concat!(env!("OUT_DIR"), "/chip8.h")),
+            "include/chip8.h is outdated, build with CHIP8_UPDATE_HEADER=1 to regenerate it"
+        );
+    }
+
+    #[test]
+    fn test_instructions_per_frame() {
+        let program = [0x12, 0x00];
+        unsafe {
+            let chip8 = chip8_create();
+            chip8_load_program(chip8, program.as_ptr(), program.len());
+            assert_eq!(chip8_set_instructions_per_frame(chip8, u32::MAX), -1);
+            assert_eq!(chip8_run_frame(chip8), INSTRUCTIONS_PER_FRAME as c_int);
+            assert_eq!(chip8_set_instructions_per_frame(chip8, 3), 0);
+            assert_eq!(chip8_run_frame(chip8), 3);
+            chip8_destroy(chip8);
+        }
+    }
+
+    #[test]
+    fn test_invalid_instruction() {
+        let program = [0x80, 0x0F];
+        unsafe {
+            let chip8 = chip8_create();
+            chip8_load_program(chip8, program.as_ptr(), program.len());
+            assert_eq!(chip8_step(chip8), -1);
+            assert_eq!(chip8_run_frame(chip8), -1);
+            chip8_destroy(chip8);
+        }
+    }
+}
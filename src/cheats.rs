@@ -0,0 +1,442 @@
+use crate::emulator::{Emulator, CHIP8_MEMORY_SIZE, CHIP8_NUMBER_REGISTERS};
+use crate::rom_loader;
+
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+// Cheat files sit next to the ROM file, like patches
+pub const CHEAT_FILE_EXTENSION: &str = "cht";
+// Number of search results listed by the "results" command
+const LISTED_RESULTS: usize = 8;
+const HELP: &str = "search, equal N, changed, unchanged, increased, decreased, results, \
+freeze TARGET [N], patch TARGET N, unfreeze TARGET, cheats, save";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatTarget {
+    Register(usize),
+    Memory(usize),
+}
+
+impl CheatTarget {
+    // A register such as "V5", or a memory address in hexadecimal such as "1F0" or "0x1F0"
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid target {:?}", text);
+        if let Some(register) = text.strip_prefix(['V', 'v']) {
+            let x = usize::from_str_radix(register, 16).map_err(|_| invalid())?;
+            return if x < CHIP8_NUMBER_REGISTERS {
+                Ok(Self::Register(x))
+            } else {
+                Err(invalid())
+            };
+        }
+        let address = text.trim_start_matches("0x").trim_start_matches("0X");
+        match usize::from_str_radix(address, 16) {
+            Ok(address) if address < CHIP8_MEMORY_SIZE => Ok(Self::Memory(address)),
+            _ => Err(invalid()),
+        }
+    }
+
+    pub fn read(&self, emulator: &Emulator) -> u8 {
+        match self {
+            Self::Register(x) => emulator.registers()[*x],
+            Self::Memory(address) => emulator.memory()[*address],
+        }
+    }
+
+    pub fn write(&self, emulator: &mut Emulator, value: u8) {
+        match self {
+            Self::Register(x) => emulator.set_register(*x, value),
+            Self::Memory(address) => emulator.write_memory(*address, value),
+        }
+    }
+}
+
+impl fmt::Display for CheatTarget {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Register(x) => write!(formatter, "V{:X}", x),
+            Self::Memory(address) => write!(formatter, "0x{:03X}", address),
+        }
+    }
+}
+
+// Values are decimal, or hexadecimal with a 0x prefix
+fn parse_value(text: &str) -> Result<u8, String> {
+    let value = match text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        Some(hexadecimal) => u8::from_str_radix(hexadecimal, 16),
+        None => text.parse(),
+    };
+    value.map_err(|_| format!("Invalid value {:?}", text))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Comparison {
+    fn matches(&self, previous: u8, current: u8) -> bool {
+        match self {
+            Self::Equal(value) => current == *value,
+            Self::Changed => current != previous,
+            Self::Unchanged => current == previous,
+            Self::Increased => current > previous,
+            Self::Decreased => current < previous,
+        }
+    }
+}
+
+// Narrows down, across frames, the registers and memory bytes holding a value such as lives
+pub struct MemorySearch {
+    // Remaining targets, with their value when last compared
+    candidates: Vec<(CheatTarget, u8)>,
+}
+
+impl MemorySearch {
+    // Every register and memory byte is a candidate at first
+    pub fn new(emulator: &Emulator) -> Self {
+        let targets = (0..CHIP8_NUMBER_REGISTERS)
+            .map(CheatTarget::Register)
+            .chain((0..CHIP8_MEMORY_SIZE).map(CheatTarget::Memory));
+        Self {
+            candidates: targets
+                .map(|target| (target, target.read(emulator)))
+                .collect(),
+        }
+    }
+
+    // Keeps only the candidates whose current value matches the comparison
+    pub fn refine(&mut self, emulator: &Emulator, comparison: Comparison) {
+        self.candidates = self
+            .candidates
+            .iter()
+            .map(|(target, previous)| (*target, *previous, target.read(emulator)))
+            .filter(|(_, previous, current)| comparison.matches(*previous, *current))
+            .map(|(target, _, current)| (target, current))
+            .collect();
+    }
+
+    pub fn results(&self) -> &[(CheatTarget, u8)] {
+        &self.candidates
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatKind {
+    // Re-written every frame
+    Freeze,
+    // Written once, when the ROM is loaded or the cheat added
+    Patch,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    pub kind: CheatKind,
+    pub target: CheatTarget,
+    pub value: u8,
+    pub description: String,
+}
+
+// One cheat per line, such as "freeze V5 3 Infinite lives" or "patch 0x2A4 0x12".
+// Empty lines and lines starting with # are ignored.
+pub fn parse_cheats(text: &str) -> Result<Vec<Cheat>, String> {
+    let mut cheats = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parse_line = || {
+            let mut words = line.split_whitespace();
+            let kind = match words.next() {
+                Some("freeze") => CheatKind::Freeze,
+                Some("patch") => CheatKind::Patch,
+                _ => return Err("expected freeze or patch".to_string()),
+            };
+            let target = CheatTarget::parse(words.next().unwrap_or_default())?;
+            let value = parse_value(words.next().unwrap_or_default())?;
+            Ok(Cheat {
+                kind,
+                target,
+                value,
+                description: words.collect::<Vec<&str>>().join(" "),
+            })
+        };
+        cheats
+            .push(parse_line().map_err(|error: String| format!("line {}: {}", index + 1, error))?);
+    }
+    Ok(cheats)
+}
+
+pub fn format_cheats(cheats: &[Cheat]) -> String {
+    cheats
+        .iter()
+        .map(|cheat| {
+            let kind = match cheat.kind {
+                CheatKind::Freeze => "freeze",
+                CheatKind::Patch => "patch",
+            };
+            format!(
+                "{} {} {} {}",
+                kind, cheat.target, cheat.value, cheat.description
+            )
+            .trim_end()
+            .to_string()
+                + "\n"
+        })
+        .collect()
+}
+
+pub struct CheatEngine {
+    pub cheats: Vec<Cheat>,
+    search: Option<MemorySearch>,
+    // Cheat file of the running ROM, written by the "save" command
+    file_path: Option<PathBuf>,
+}
+
+impl CheatEngine {
+    pub fn new() -> Self {
+        Self {
+            cheats: Vec::new(),
+            search: None,
+            file_path: None,
+        }
+    }
+
+    // Replaces the cheats with those of the cheat file next to the ROM, if there is one
+    pub fn load_for_rom(&mut self, rom_path: &Path) -> io::Result<()> {
+        let file_path = rom_loader::sidecar_file(rom_path, CHEAT_FILE_EXTENSION);
+        self.cheats = match std::fs::read_to_string(&file_path) {
+            Ok(text) => parse_cheats(&text).map_err(|error| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}: {}", file_path.display(), error),
+                )
+            })?,
+            Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error),
+        };
+        self.search = None;
+        self.file_path = Some(file_path);
+        Ok(())
+    }
+
+    // Must be called once the ROM is loaded in the emulator
+    pub fn apply_patches(&self, emulator: &mut Emulator) {
+        for cheat in &self.cheats {
+            if cheat.kind == CheatKind::Patch {
+                cheat.target.write(emulator, cheat.value);
+            }
+        }
+    }
+
+    // Must be called at the start of every emulated frame
+    pub fn apply_freezes(&self, emulator: &mut Emulator) {
+        for cheat in &self.cheats {
+            if cheat.kind == CheatKind::Freeze {
+                cheat.target.write(emulator, cheat.value);
+            }
+        }
+    }
+
+    // Runs a command typed in a console, returns the text to show back
+    pub fn execute(&mut self, emulator: &mut Emulator, command: &str) -> Result<String, String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let comparison = match words.as_slice() {
+            ["search"] => {
+                self.search = Some(MemorySearch::new(emulator));
+                return Ok(self.search_summary());
+            }
+            ["equal", value] => Comparison::Equal(parse_value(value)?),
+            ["changed"] => Comparison::Changed,
+            ["unchanged"] => Comparison::Unchanged,
+            ["increased"] => Comparison::Increased,
+            ["decreased"] => Comparison::Decreased,
+            ["results"] => return self.list_results(),
+            ["freeze", target] => {
+                let target = CheatTarget::parse(target)?;
+                return Ok(self.add(emulator, CheatKind::Freeze, target, target.read(emulator)));
+            }
+            ["freeze", target, value] => {
+                let target = CheatTarget::parse(target)?;
+                return Ok(self.add(emulator, CheatKind::Freeze, target, parse_value(value)?));
+            }
+            ["patch", target, value] => {
+                let target = CheatTarget::parse(target)?;
+                return Ok(self.add(emulator, CheatKind::Patch, target, parse_value(value)?));
+            }
+            ["unfreeze", target] => {
+                let target = CheatTarget::parse(target)?;
+                self.cheats.retain(|cheat| cheat.target != target);
+                return Ok(format!("Removed cheats on {}", target));
+            }
+            ["cheats"] => {
+                return Ok(match self.cheats.len() {
+                    0 => "No cheats".to_string(),
+                    _ => format_cheats(&self.cheats).trim_end().replace('\n', ", "),
+                })
+            }
+            ["save"] => return self.save(),
+            ["help"] | [] => return Ok(HELP.to_string()),
+            _ => return Err(format!("Unknown command {:?}, try: {}", command, HELP)),
+        };
+        match &mut self.search {
+            Some(search) => {
+                search.refine(emulator, comparison);
+                Ok(self.search_summary())
+            }
+            None => Err("Start a search first".to_string()),
+        }
+    }
+
+    fn add(
+        &mut self,
+        emulator: &mut Emulator,
+        kind: CheatKind,
+        target: CheatTarget,
+        value: u8,
+    ) -> String {
+        self.cheats.retain(|cheat| cheat.target != target);
+        target.write(emulator, value);
+        self.cheats.push(Cheat {
+            kind,
+            target,
+            value,
+            description: String::new(),
+        });
+        format!("{:?} {} = {}", kind, target, value)
+    }
+
+    fn search_summary(&self) -> String {
+        match &self.search {
+            Some(search) if search.results().len() <= LISTED_RESULTS => {
+                self.list_results().unwrap()
+            }
+            Some(search) => format!("{} candidates", search.results().len()),
+            None => "No search".to_string(),
+        }
+    }
+
+    fn list_results(&self) -> Result<String, String> {
+        let search = self.search.as_ref().ok_or("Start a search first")?;
+        if search.results().is_empty() {
+            return Ok("No candidates left".to_string());
+        }
+        let listed: Vec<String> = search
+            .results()
+            .iter()
+            .take(LISTED_RESULTS)
+            .map(|(target, value)| format!("{}={}", target, value))
+            .collect();
+        Ok(format!(
+            "{} candidates: {}",
+            search.results().len(),
+            listed.join(" ")
+        ))
+    }
+
+    fn save(&self) -> Result<String, String> {
+        let file_path = self.file_path.as_ref().ok_or("No ROM loaded")?;
+        std::fs::write(file_path, format_cheats(&self.cheats))
+            .map_err(|error| format!("{}: {}", file_path.display(), error))?;
+        Ok(format!("Cheats saved to {}", file_path.display()))
+    }
+}
+
+impl Default for CheatEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(CheatTarget::parse("V5"), Ok(CheatTarget::Register(5)));
+        assert_eq!(CheatTarget::parse("vf"), Ok(CheatTarget::Register(15)));
+        assert_eq!(CheatTarget::parse("0x1F0"), Ok(CheatTarget::Memory(0x1F0)));
+        assert_eq!(CheatTarget::parse("2a4"), Ok(CheatTarget::Memory(0x2A4)));
+        assert!(CheatTarget::parse("V10").is_err());
+        assert!(CheatTarget::parse("1000").is_err());
+    }
+
+    #[test]
+    fn test_cheat_file_round_trip() {
+        let text = "# Lives\nfreeze V5 3 Infinite lives\n\npatch 0x2A4 0x12\n";
+        let cheats = parse_cheats(text).unwrap();
+        assert_eq!(
+            cheats,
+            vec![
+                Cheat {
+                    kind: CheatKind::Freeze,
+                    target: CheatTarget::Register(5),
+                    value: 3,
+                    description: "Infinite lives".to_string(),
+                },
+                Cheat {
+                    kind: CheatKind::Patch,
+                    target: CheatTarget::Memory(0x2A4),
+                    value: 0x12,
+                    description: String::new(),
+                },
+            ]
+        );
+        assert_eq!(parse_cheats(&format_cheats(&cheats)).unwrap(), cheats);
+        assert_eq!(
+            parse_cheats("freeze V5").unwrap_err(),
+            "line 1: Invalid value \"\""
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let mut emulator = Emulator::new();
+        // V3 counts down from 5, V4 is set to 5 and never changes
+        emulator.load_program(&[0x63, 0x05, 0x64, 0x05, 0x73, 0xFF, 0x12, 0x04]);
+        emulator.process_next_instruction();
+        emulator.process_next_instruction();
+
+        let mut engine = CheatEngine::new();
+        assert!(engine.execute(&mut emulator, "equal 5").is_err());
+        engine.execute(&mut emulator, "search").unwrap();
+        engine.execute(&mut emulator, "equal 5").unwrap();
+        emulator.process_next_instruction();
+        emulator.process_next_instruction();
+        assert_eq!(
+            engine.execute(&mut emulator, "decreased"),
+            Ok("1 candidates: V3=4".to_string())
+        );
+    }
+
+    #[test]
+    fn test_freeze_and_patch() {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&[0x73, 0xFF, 0x12, 0x00]);
+        let mut engine = CheatEngine::new();
+        engine.execute(&mut emulator, "freeze V3 9").unwrap();
+        engine.execute(&mut emulator, "patch 0x300 0xAB").unwrap();
+        assert_eq!(emulator.memory()[0x300], 0xAB);
+        for _ in 0..3 {
+            engine.apply_freezes(&mut emulator);
+            emulator.process_next_instruction();
+            emulator.process_next_instruction();
+            assert_eq!(emulator.registers()[3], 8);
+        }
+        engine.execute(&mut emulator, "unfreeze V3").unwrap();
+        engine.apply_freezes(&mut emulator);
+        emulator.process_next_instruction();
+        assert_eq!(emulator.registers()[3], 7);
+        assert_eq!(
+            engine.execute(&mut emulator, "cheats"),
+            Ok("patch 0x300 171".to_string())
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use crate::disassembler::disassemble;
+use crate::emulator::CHIP8_MEMORY_SIZE;
+use crate::symbols::Symbols;
+
+use std::fmt::Write;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineStatus {
+    Executed,
+    SpriteData,
+    // Neither executed nor read as sprite data
+    Unreached,
+}
+
+// Which instructions were executed, and which bytes DXYN read as sprite data
+pub struct Coverage {
+    executions: Vec<u64>,
+    sprite_reads: Vec<u64>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            executions: vec![0; CHIP8_MEMORY_SIZE],
+            sprite_reads: vec![0; CHIP8_MEMORY_SIZE],
+        }
+    }
+
+    pub fn record_instruction(&mut self, address: usize) {
+        self.executions[address] += 1;
+    }
+
+    pub fn record_sprite(&mut self, address: usize, length: usize) {
+        let end = (address + length).min(CHIP8_MEMORY_SIZE);
+        for reads in &mut self.sprite_reads[address.min(end)..end] {
+            *reads += 1;
+        }
+    }
+
+    // Status of the two bytes at the address, as shown on a line of the disassembly.
+    // Instructions starting at an odd address still mark both lines they overlap as executed.
+    pub fn line_status(&self, address: usize) -> LineStatus {
+        let executed = |address: usize| address < CHIP8_MEMORY_SIZE && self.executions[address] > 0;
+        let read = |address: usize| address < CHIP8_MEMORY_SIZE && self.sprite_reads[address] > 0;
+        if executed(address) || executed(address + 1) || (address > 0 && executed(address - 1)) {
+            LineStatus::Executed
+        } else if read(address) || read(address + 1) {
+            LineStatus::SpriteData
+        } else {
+            LineStatus::Unreached
+        }
+    }
+
+    fn hits(&self, address: usize) -> u64 {
+        let sprite_reads = (address..(address + 2).min(CHIP8_MEMORY_SIZE))
+            .map(|address| self.sprite_reads[address])
+            .max()
+            .unwrap_or(0);
+        self.executions[address].max(sprite_reads)
+    }
+
+    // HTML report when the report file ends with .html, LCOV tracefile otherwise
+    pub fn report(
+        &self,
+        memory: &[u8],
+        start: usize,
+        end: usize,
+        title: &str,
+        report_path: &Path,
+    ) -> String {
+        let is_html = report_path.extension().is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm")
+        });
+        if is_html {
+            self.html(memory, start, end, title)
+        } else {
+            self.lcov(memory, start, end, title)
+        }
+    }
+
+    // LCOV tracefile over the disassembly of memory[start..end], one line per two bytes, so
+    // line N is the instruction at start + 2 * (N - 1). Sprite data read counts as covered.
+    pub fn lcov(&self, memory: &[u8], start: usize, end: usize, source_name: &str) -> String {
+        let lines = disassemble(memory, start, end, &Symbols::default());
+        let mut output = format!("TN:\nSF:{}\n", source_name);
+        for (index, line) in lines.iter().enumerate() {
+            writeln!(output, "DA:{},{}", index + 1, self.hits(line.address)).unwrap();
+        }
+        let covered = lines
+            .iter()
+            .filter(|line| self.line_status(line.address) != LineStatus::Unreached)
+            .count();
+        write!(
+            output,
+            "LF:{}\nLH:{}\nend_of_record\n",
+            lines.len(),
+            covered
+        )
+        .unwrap();
+        output
+    }
+
+    // Standalone HTML page of the disassembly of memory[start..end], with unreached lines
+    // highlighted and sprite data drawn next to its bytes
+    pub fn html(&self, memory: &[u8], start: usize, end: usize, title: &str) -> String {
+        let lines = disassemble(memory, start, end, &Symbols::default());
+        let count = |status: LineStatus| {
+            lines
+                .iter()
+                .filter(|line| self.line_status(line.address) == status)
+                .count()
+        };
+        let title = escape_html(title);
+        let mut output = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+<title>Coverage of {title}</title>\n<style>\n\
+body {{ font-family: monospace; }}\n\
+td {{ padding: 0 1em; white-space: pre; }}\n\
+.executed {{ background: #c8f0c8; }}\n\
+.sprite {{ background: #c8d8f8; }}\n\
+.unreached {{ background: #f8c8c8; }}\n\
+</style>\n</head>\n<body>\n<h1>Coverage of {title}</h1>\n\
+<p>{} executed, {} sprite data, {} unreached lines out of {}</p>\n<table>\n\
+<tr><th>Address</th><th>Bytes</th><th>Hits</th><th>Instruction</th><th>Sprite</th></tr>\n",
+            count(LineStatus::Executed),
+            count(LineStatus::SpriteData),
+            count(LineStatus::Unreached),
+            lines.len(),
+        );
+        for line in &lines {
+            let status = self.line_status(line.address);
+            let class = match status {
+                LineStatus::Executed => "executed",
+                LineStatus::SpriteData => "sprite",
+                LineStatus::Unreached => "unreached",
+            };
+            let sprite = match status {
+                LineStatus::SpriteData => {
+                    let [high, low] = line.raw_opcode.to_be_bytes();
+                    format!("{} {}", sprite_row(high), sprite_row(low))
+                }
+                _ => String::new(),
+            };
+            writeln!(
+                output,
+                "<tr class=\"{}\"><td>0x{:03X}</td><td>{:04X}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                class,
+                line.address,
+                line.raw_opcode,
+                self.hits(line.address),
+                line.text,
+                sprite
+            )
+            .unwrap();
+        }
+        output += "</table>\n</body>\n</html>\n";
+        output
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn sprite_row(byte: u8) -> String {
+    (0..8)
+        .rev()
+        .map(|bit| if byte & (1 << bit) != 0 { '#' } else { '.' })
+        .collect()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, CHIP8_FIRST_BYTE_ADDRESS};
+
+    // Draws the sprite at 0x208 then loops, 0x206 is never executed
+    const PROGRAM: [u8; 10] = [0xA2, 0x08, 0xD0, 0x01, 0x12, 0x04, 0x00, 0xE0, 0xF0, 0x90];
+
+    fn record() -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator.coverage = Some(Coverage::new());
+        emulator.load_program(&PROGRAM);
+        for _ in 0..4 {
+            emulator.process_next_instruction();
+        }
+        emulator
+    }
+
+    #[test]
+    fn test_line_status() {
+        let emulator = record();
+        let coverage = emulator.coverage.as_ref().unwrap();
+        let statuses: Vec<LineStatus> = (0..5)
+            .map(|line| coverage.line_status(CHIP8_FIRST_BYTE_ADDRESS + 2 * line))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                LineStatus::Executed,
+                LineStatus::Executed,
+                LineStatus::Executed,
+                LineStatus::Unreached,
+                LineStatus::SpriteData,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lcov() {
+        let emulator = record();
+        let coverage = emulator.coverage.as_ref().unwrap();
+        let lcov = coverage.lcov(emulator.memory(), CHIP8_FIRST_BYTE_ADDRESS, 0x20A, "GAME");
+        assert_eq!(
+            lcov,
+            "TN:\nSF:GAME\nDA:1,1\nDA:2,1\nDA:3,2\nDA:4,0\nDA:5,1\nLF:5\nLH:4\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn test_html() {
+        let emulator = record();
+        let coverage = emulator.coverage.as_ref().unwrap();
+        let html = coverage.html(emulator.memory(), CHIP8_FIRST_BYTE_ADDRESS, 0x20A, "<GAME>");
+        assert!(html.contains("<title>Coverage of &lt;GAME&gt;</title>"));
+        assert!(html.contains("<p>3 executed, 1 sprite data, 1 unreached lines out of 5</p>"));
+        assert!(html.contains("<tr class=\"unreached\"><td>0x206</td><td>00E0</td><td>0</td>"));
+        assert!(html.contains("<td>####.... #..#....</td>"));
+    }
+}
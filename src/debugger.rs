@@ -0,0 +1,484 @@
+use crate::cheats::CheatEngine;
+use crate::emulator::{Emulator, Snapshot};
+use crate::memory_heatmap::MemoryAccess;
+
+use std::collections::{BTreeSet, VecDeque};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // Reads and writes
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub address: usize,
+    pub length: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatchpointHit {
+    pub kind: WatchKind,
+    // First watched address touched by the access
+    pub address: usize,
+}
+
+impl Watchpoint {
+    // Instruction fetches never trigger watchpoints, only data accesses do
+    pub fn check(
+        &self,
+        access: MemoryAccess,
+        address: usize,
+        length: usize,
+    ) -> Option<WatchpointHit> {
+        let watched = matches!(
+            (self.kind, access),
+            (WatchKind::Read | WatchKind::Access, MemoryAccess::Read)
+                | (WatchKind::Write | WatchKind::Access, MemoryAccess::Write)
+        );
+        let start = address.max(self.address);
+        let end = (address + length).min(self.address + self.length);
+        if watched && start < end {
+            Some(WatchpointHit {
+                kind: self.kind,
+                address: start,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    Step,
+    Breakpoint(usize),
+    Watchpoint(WatchpointHit),
+    // Running backwards reached the oldest state kept in the history
+    HistoryStart,
+}
+
+// Instructions between two snapshots of the history
+const SNAPSHOT_PERIOD: u64 = 1024;
+// Oldest snapshots are dropped beyond this number, about a million instructions are kept
+const MAX_SNAPSHOTS: usize = 1024;
+
+// Emulator states taken every SNAPSHOT_PERIOD instructions and the key inputs in between,
+// enough to run the program again identically from any snapshot
+struct History {
+    // Positions, in instructions since the start, and the states there, oldest first
+    snapshots: VecDeque<(u64, Snapshot)>,
+    // Key inputs and the position where they were received, oldest first
+    inputs: VecDeque<(u64, Vec<(u8, bool)>)>,
+}
+
+impl History {
+    fn inputs_at(&self, position: u64) -> Option<&Vec<(u8, bool)>> {
+        let index = self.inputs.partition_point(|(at, _)| *at < position);
+        match self.inputs.get(index) {
+            Some((at, inputs)) if *at == position => Some(inputs),
+            _ => None,
+        }
+    }
+
+    // Last snapshot taken at or before the position
+    fn snapshot_before(&self, position: u64) -> Option<usize> {
+        self.snapshots
+            .partition_point(|(at, _)| *at <= position)
+            .checked_sub(1)
+    }
+
+    // Forgets everything recorded after the position, as the run goes another way from there
+    fn truncate_after(&mut self, position: u64) {
+        while self.snapshots.back().is_some_and(|(at, _)| *at > position) {
+            self.snapshots.pop_back();
+        }
+        while self.inputs.back().is_some_and(|(at, _)| *at >= position) {
+            self.inputs.pop_back();
+        }
+    }
+}
+
+// Runs the emulator one instruction at a time, for debugger frontends.
+// Timers tick every instructions_per_frame instructions, as when running frame by frame.
+// The run is recorded so that it can also go backwards, by restoring a snapshot and running
+// the instructions again with the same key inputs. The random numbers are part of snapshots.
+pub struct Debugger {
+    pub breakpoints: BTreeSet<usize>,
+    // Frozen values are written at the start of every frame, also when running again
+    pub cheats: CheatEngine,
+    instructions_per_frame: u32,
+    // Instructions run since the start, waiting for a key counts as an instruction
+    position: u64,
+    history: History,
+}
+
+impl Debugger {
+    pub fn new(instructions_per_frame: u32) -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            cheats: CheatEngine::new(),
+            instructions_per_frame,
+            position: 0,
+            history: History {
+                snapshots: VecDeque::new(),
+                inputs: VecDeque::new(),
+            },
+        }
+    }
+
+    // Returns whether a breakpoint is now set at the address
+    pub fn toggle_breakpoint(&mut self, address: usize) -> bool {
+        if self.breakpoints.remove(&address) {
+            false
+        } else {
+            self.breakpoints.insert(address)
+        }
+    }
+
+    // Must be called when the emulator state is changed from outside, such as by editing
+    // memory, as running again from older snapshots would not lead to the same state
+    pub fn forget_history(&mut self) {
+        self.history.snapshots.clear();
+        self.history.inputs.clear();
+    }
+
+    // Runs a command of the cheat console, see CheatEngine::execute. Changing the cheats
+    // changes the run, so the history is forgotten then.
+    pub fn execute_cheat(
+        &mut self,
+        emulator: &mut Emulator,
+        command: &str,
+    ) -> Result<String, String> {
+        let cheats = self.cheats.cheats.clone();
+        let result = self.cheats.execute(emulator, command);
+        if self.cheats.cheats != cheats {
+            self.forget_history();
+        }
+        result
+    }
+
+    // Runs a single instruction, nothing is run while the program waits for a key
+    pub fn step(&mut self, emulator: &mut Emulator) -> StopReason {
+        let live_inputs = emulator.recorded_inputs.replace(Vec::new());
+        let live_inputs = live_inputs.unwrap_or_default();
+        if self.history.snapshots.is_empty() {
+            // Inputs received so far are already part of the first snapshot
+            self.history
+                .snapshots
+                .push_back((self.position, emulator.snapshot()));
+        } else if !live_inputs.is_empty() {
+            self.history.truncate_after(self.position);
+            self.history.inputs.push_back((self.position, live_inputs));
+        } else if let Some(inputs) = self.history.inputs_at(self.position) {
+            // Going forward again after going backwards, with the inputs received back then
+            for (key, pressed) in inputs.clone() {
+                emulator.input_key(key, pressed);
+            }
+            emulator.recorded_inputs = Some(Vec::new());
+        }
+
+        let hit = self.execute(emulator);
+        let newest_snapshot = self.history.snapshots.back().map(|(at, _)| *at);
+        if self.position.is_multiple_of(SNAPSHOT_PERIOD) && newest_snapshot < Some(self.position) {
+            self.history
+                .snapshots
+                .push_back((self.position, emulator.snapshot()));
+            if self.history.snapshots.len() > MAX_SNAPSHOTS {
+                self.history.snapshots.pop_front();
+                let oldest = self.history.snapshots[0].0;
+                while self
+                    .history
+                    .inputs
+                    .front()
+                    .is_some_and(|(at, _)| *at < oldest)
+                {
+                    self.history.inputs.pop_front();
+                }
+            }
+        }
+        match hit {
+            Some(hit) => StopReason::Watchpoint(hit),
+            None => StopReason::Step,
+        }
+    }
+
+    // Runs until the end of the current frame, unless a breakpoint or watchpoint stops it first
+    pub fn run_frame(&mut self, emulator: &mut Emulator) -> Option<StopReason> {
+        loop {
+            let reason = self.step(emulator);
+            if reason != StopReason::Step {
+                return Some(reason);
+            }
+            if self.breakpoints.contains(&emulator.program_counter()) {
+                return Some(StopReason::Breakpoint(emulator.program_counter()));
+            }
+            if self
+                .position
+                .is_multiple_of(self.instructions_per_frame as u64)
+            {
+                return None;
+            }
+        }
+    }
+
+    // Goes back to the state before the last instruction
+    pub fn step_back(&mut self, emulator: &mut Emulator) -> StopReason {
+        let Some(target) = self.position.checked_sub(1) else {
+            return StopReason::HistoryStart;
+        };
+        match self.history.snapshot_before(target) {
+            Some(snapshot) => {
+                self.replay(emulator, snapshot, target, |_, _, _| ());
+                StopReason::Step
+            }
+            None => StopReason::HistoryStart,
+        }
+    }
+
+    // Goes back to the last time the program counter reached a breakpoint, or to the
+    // instruction that last triggered a watchpoint, with the program counter on it
+    pub fn reverse_continue(&mut self, emulator: &mut Emulator) -> StopReason {
+        let breakpoints = self.breakpoints.clone();
+        let mut end = self.position;
+        let mut snapshot = self.history.snapshot_before(end.saturating_sub(1));
+        while let Some(index) = snapshot {
+            let mut found = None;
+            self.replay(emulator, index, end, |position, emulator, hit| {
+                let program_counter = emulator.program_counter();
+                if let Some(hit) = hit {
+                    found = Some((position - 1, StopReason::Watchpoint(hit)));
+                } else if position < end && breakpoints.contains(&program_counter) {
+                    found = Some((position, StopReason::Breakpoint(program_counter)));
+                }
+            });
+            if let Some((position, reason)) = found {
+                self.replay(emulator, index, position, |_, _, _| ());
+                return reason;
+            }
+            end = self.history.snapshots[index].0;
+            snapshot = index.checked_sub(1);
+        }
+        if let Some(oldest) = self.history.snapshots.front().map(|(at, _)| *at) {
+            self.replay(emulator, 0, oldest, |_, _, _| ());
+        }
+        StopReason::HistoryStart
+    }
+
+    // Runs one instruction, applying frozen cheats at the start of every frame and ticking
+    // timers at the end
+    fn execute(&mut self, emulator: &mut Emulator) -> Option<WatchpointHit> {
+        if self
+            .position
+            .is_multiple_of(self.instructions_per_frame as u64)
+        {
+            self.cheats.apply_freezes(emulator);
+        }
+        emulator.watchpoint_hit = None;
+        if !emulator.waiting_for_key {
+            emulator.process_next_instruction();
+        }
+        self.position += 1;
+        if self
+            .position
+            .is_multiple_of(self.instructions_per_frame as u64)
+        {
+            emulator.tick_timers();
+        }
+        emulator.watchpoint_hit.take()
+    }
+
+    // Restores a snapshot, then runs the recorded instructions up to the target position.
+    // Visits the state at the snapshot then after every instruction, with any watchpoint hit.
+    // Instrumentation and tracing are suspended, as these instructions already ran once.
+    fn replay(
+        &mut self,
+        emulator: &mut Emulator,
+        snapshot: usize,
+        target: u64,
+        mut visit: impl FnMut(u64, &Emulator, Option<WatchpointHit>),
+    ) {
+        let profiler = emulator.profiler.take();
+        let coverage = emulator.coverage.take();
+        let heatmap = emulator.heatmap.take();
+        let trace = std::mem::replace(&mut emulator.trace, false);
+
+        let (position, state) = &self.history.snapshots[snapshot];
+        self.position = *position;
+        emulator.restore(state);
+        visit(self.position, emulator, None);
+        while self.position < target {
+            if let Some(inputs) = self.history.inputs_at(self.position) {
+                for (key, pressed) in inputs.clone() {
+                    emulator.input_key(key, pressed);
+                }
+            }
+            let hit = self.execute(emulator);
+            visit(self.position, emulator, hit);
+        }
+
+        emulator.recorded_inputs = Some(Vec::new());
+        emulator.profiler = profiler;
+        emulator.coverage = coverage;
+        emulator.heatmap = heatmap;
+        emulator.trace = trace;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts V0 up in a loop, storing it at 0x300 every iteration
+    const PROGRAM: [u8; 8] = [0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00];
+
+    #[test]
+    fn test_breakpoint() {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&PROGRAM);
+        let mut debugger = Debugger::new(20);
+        debugger.breakpoints.insert(0x204);
+        assert_eq!(
+            debugger.run_frame(&mut emulator),
+            Some(StopReason::Breakpoint(0x204))
+        );
+        assert_eq!(
+            debugger.run_frame(&mut emulator),
+            Some(StopReason::Breakpoint(0x204))
+        );
+        assert_eq!(emulator.registers()[0], 2);
+        debugger.breakpoints.remove(&0x204);
+        assert_eq!(debugger.run_frame(&mut emulator), None);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&PROGRAM);
+        emulator.watchpoints.push(Watchpoint {
+            kind: WatchKind::Write,
+            address: 0x300,
+            length: 1,
+        });
+        let mut debugger = Debugger::new(20);
+        assert_eq!(debugger.step(&mut emulator), StopReason::Step);
+        assert_eq!(debugger.step(&mut emulator), StopReason::Step);
+        assert_eq!(
+            debugger.step(&mut emulator),
+            StopReason::Watchpoint(WatchpointHit {
+                kind: WatchKind::Write,
+                address: 0x300
+            })
+        );
+        assert_eq!(emulator.program_counter(), 0x206);
+    }
+
+    #[test]
+    fn test_frozen_cheats() {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&PROGRAM);
+        let mut debugger = Debugger::new(20);
+        debugger.step(&mut emulator);
+        assert_eq!(
+            debugger.execute_cheat(&mut emulator, "freeze V0 0"),
+            Ok("Freeze V0 = 0".to_string())
+        );
+        assert_eq!(debugger.step_back(&mut emulator), StopReason::HistoryStart);
+
+        // V0 goes back to 0 at the start of every frame, also when running again
+        debugger.run_frame(&mut emulator);
+        assert_eq!(emulator.registers()[0], 4);
+        debugger.run_frame(&mut emulator);
+        assert_eq!(emulator.registers()[0], 5);
+        debugger.step_back(&mut emulator);
+        assert_eq!(emulator.registers()[0], 5);
+        debugger.step(&mut emulator);
+        debugger.step(&mut emulator);
+        assert_eq!(emulator.registers()[0], 1);
+    }
+
+    #[test]
+    fn test_timers_tick_every_frame() {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&[0x12, 0x00]);
+        emulator.system_clock = 5;
+        let mut debugger = Debugger::new(3);
+        debugger.step(&mut emulator);
+        debugger.step(&mut emulator);
+        assert_eq!(emulator.system_clock, 5);
+        debugger.step(&mut emulator);
+        assert_eq!(emulator.system_clock, 4);
+        assert_eq!(debugger.run_frame(&mut emulator), None);
+        assert_eq!(emulator.system_clock, 3);
+    }
+
+    #[test]
+    fn test_step_back_replays_inputs_and_random_numbers() {
+        let mut emulator = Emulator::new();
+        // Waits for a key in V1, then draws random numbers in V2 forever
+        emulator.load_program(&[0xF1, 0x0A, 0xC2, 0xFF, 0x12, 0x02]);
+        let mut debugger = Debugger::new(20);
+        debugger.step(&mut emulator);
+        debugger.step(&mut emulator);
+        emulator.input_key(5, true);
+        let mut states = Vec::new();
+        for _ in 0..3000 {
+            debugger.step(&mut emulator);
+            states.push((emulator.program_counter(), emulator.registers()[2]));
+        }
+        for expected in states.iter().rev().skip(1) {
+            assert_eq!(debugger.step_back(&mut emulator), StopReason::Step);
+            assert_eq!(
+                (emulator.program_counter(), emulator.registers()[2]),
+                *expected
+            );
+        }
+        assert_eq!(emulator.registers()[1], 5);
+
+        // Back to before the key was pressed, then to the start
+        for _ in 0..2 {
+            assert_eq!(debugger.step_back(&mut emulator), StopReason::Step);
+        }
+        assert!(emulator.waiting_for_key);
+        assert_eq!(emulator.registers()[1], 0);
+        assert_eq!(debugger.step_back(&mut emulator), StopReason::Step);
+        assert_eq!(debugger.step_back(&mut emulator), StopReason::HistoryStart);
+        for _ in 0..3 {
+            debugger.step(&mut emulator);
+        }
+        assert_eq!(emulator.registers()[1], 5);
+        assert_eq!(
+            (emulator.program_counter(), emulator.registers()[2]),
+            states[0]
+        );
+    }
+
+    #[test]
+    fn test_reverse_continue_to_breakpoint() {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&PROGRAM);
+        let mut debugger = Debugger::new(20);
+        for _ in 0..10 {
+            debugger.step(&mut emulator);
+        }
+        debugger.breakpoints.insert(0x206);
+        assert_eq!(
+            debugger.reverse_continue(&mut emulator),
+            StopReason::Breakpoint(0x206)
+        );
+        assert_eq!(emulator.registers()[0], 2);
+        assert_eq!(
+            debugger.reverse_continue(&mut emulator),
+            StopReason::Breakpoint(0x206)
+        );
+        assert_eq!(emulator.registers()[0], 1);
+        assert_eq!(
+            debugger.reverse_continue(&mut emulator),
+            StopReason::HistoryStart
+        );
+        assert_eq!(emulator.program_counter(), 0x200);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::emulator::{parse_opcode, OpCode};
+use crate::symbols::Symbols;
+
+pub struct DisassembledLine {
+    pub address: usize,
+    pub raw_opcode: u16,
+    pub text: String,
+}
+
+// Mnemonics in the usual CHIP8 assembly syntax, such as "LD V1, 0x2A" or "DRW V0, V1, 5".
+// Instructions the emulator does not run yet are still named, anything else is shown as data.
+// Addresses with a symbol are shown by name, such as "CALL draw_player".
+pub fn disassemble_instruction(raw_opcode: u16, symbols: &Symbols) -> String {
+    let address = |nnn: usize| symbols.format_address(nnn);
+    match parse_opcode(raw_opcode) {
+        Some(opcode) => match opcode {
+            OpCode::OC_0NNN(nnn) => format!("SYS {}", address(nnn as usize)),
+            OpCode::OC_00E0 => "CLS".to_string(),
+            OpCode::OC_00EE => "RET".to_string(),
+            OpCode::OC_1NNN(nnn) => format!("JP {}", address(nnn)),
+            OpCode::OC_2NNN(nnn) => format!("CALL {}", address(nnn)),
+            OpCode::OC_3XNN(x, nn) => format!("SE V{:X}, 0x{:02X}", x, nn),
+            OpCode::OC_4XNN(x, nn) => format!("SNE V{:X}, 0x{:02X}", x, nn),
+            OpCode::OC_5XY0(x, y) => format!("SE V{:X}, V{:X}", x, y),
+            OpCode::OC_6XNN(x, nn) => format!("LD V{:X}, 0x{:02X}", x, nn),
+            OpCode::OC_7XNN(x, nn) => format!("ADD V{:X}, 0x{:02X}", x, nn),
+            OpCode::OC_8XY0(x, y) => format!("LD V{:X}, V{:X}", x, y),
+            OpCode::OC_8XY1(x, y) => format!("OR V{:X}, V{:X}", x, y),
+            OpCode::OC_8XY2(x, y) => format!("AND V{:X}, V{:X}", x, y),
+            OpCode::OC_8XY3(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+            OpCode::OC_8XY4(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+            OpCode::OC_8XY5(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+            OpCode::OC_8XY6(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+            OpCode::OC_8XY7(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+            OpCode::OC_8XYE(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+            OpCode::OC_9XY0(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+            OpCode::OC_ANNN(nnn) => format!("LD I, {}", address(nnn)),
+            OpCode::OC_CXNN(x, nn) => format!("RND V{:X}, 0x{:02X}", x, nn),
+            OpCode::OC_DXYN(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            OpCode::OC_EX9E(x) => format!("SKP V{:X}", x),
+            OpCode::OC_EXA1(x) => format!("SKNP V{:X}", x),
+            OpCode::OC_FX07(x) => format!("LD V{:X}, DT", x),
+            OpCode::OC_FX0A(x) => format!("LD V{:X}, K", x),
+            OpCode::OC_FX15(x) => format!("LD DT, V{:X}", x),
+            OpCode::OC_FX18(x) => format!("LD ST, V{:X}", x),
+            OpCode::OC_FX1E(x) => format!("ADD I, V{:X}", x),
+            OpCode::OC_FX55(x) => format!("LD [I], V{:X}", x),
+            OpCode::OC_FX65(x) => format!("LD V{:X}, [I]", x),
+            OpCode::OC_FX75(x) => format!("LD R, V{:X}", x),
+            OpCode::OC_FX85(x) => format!("LD V{:X}, R", x),
+        },
+        None if raw_opcode & 0xF000 == 0xB000 => {
+            format!("JP V0, {}", address((raw_opcode & 0x0FFF) as usize))
+        }
+        None => {
+            let x = (raw_opcode & 0x0F00) >> 8;
+            match raw_opcode & 0xF0FF {
+                0xF029 => format!("LD F, V{:X}", x),
+                0xF033 => format!("LD B, V{:X}", x),
+                _ => format!("DW 0x{:04X}", raw_opcode),
+            }
+        }
+    }
+}
+
+// Linear disassembly of memory[start..end], two bytes at a time.
+// Sprite data in the middle of code is disassembled as instructions too.
+pub fn disassemble(
+    memory: &[u8],
+    start: usize,
+    end: usize,
+    symbols: &Symbols,
+) -> Vec<DisassembledLine> {
+    (start..end.min(memory.len()))
+        .step_by(2)
+        .map(|address| {
+            let low_byte = memory.get(address + 1).copied().unwrap_or(0);
+            let raw_opcode = ((memory[address] as u16) << 8) | low_byte as u16;
+            DisassembledLine {
+                address,
+                raw_opcode,
+                text: disassemble_instruction(raw_opcode, symbols),
+            }
+        })
+        .collect()
+}
+
+// Listing of the disassembly, one "ADDR  OPCODE  MNEMONIC" line per instruction,
+// with a "name:" line before every address with a symbol
+pub fn listing(memory: &[u8], start: usize, end: usize, symbols: &Symbols) -> String {
+    let mut text = String::new();
+    for line in disassemble(memory, start, end, symbols) {
+        if let Some(name) = symbols.name(line.address) {
+            text.push_str(&format!("{}:\n", name));
+        }
+        text.push_str(&format!(
+            "    {:03X}  {:04X}  {}\n",
+            line.address, line.raw_opcode, line.text
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_instruction() {
+        assert_eq!(disassemble_instruction(0x00E0, &Symbols::default()), "CLS");
+        assert_eq!(
+            disassemble_instruction(0x2A04, &Symbols::default()),
+            "CALL 0xA04"
+        );
+        assert_eq!(
+            disassemble_instruction(0x6B2A, &Symbols::default()),
+            "LD VB, 0x2A"
+        );
+        assert_eq!(
+            disassemble_instruction(0x8AB6, &Symbols::default()),
+            "SHR VA, VB"
+        );
+        assert_eq!(
+            disassemble_instruction(0xD015, &Symbols::default()),
+            "DRW V0, V1, 5"
+        );
+        assert_eq!(
+            disassemble_instruction(0xF365, &Symbols::default()),
+            "LD V3, [I]"
+        );
+        assert_eq!(
+            disassemble_instruction(0xB300, &Symbols::default()),
+            "JP V0, 0x300"
+        );
+        assert_eq!(
+            disassemble_instruction(0xF233, &Symbols::default()),
+            "LD B, V2"
+        );
+        assert_eq!(
+            disassemble_instruction(0x800F, &Symbols::default()),
+            "DW 0x800F"
+        );
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let lines = disassemble(&[0x00, 0xE0, 0x12, 0x00, 0xFF], 0, 5, &Symbols::default());
+        let texts: Vec<(usize, &str)> = lines
+            .iter()
+            .map(|line| (line.address, line.text.as_str()))
+            .collect();
+        assert_eq!(texts, vec![(0, "CLS"), (2, "JP 0x200"), (4, "DW 0xFF00")]);
+    }
+
+    #[test]
+    fn test_symbolic_addresses() {
+        let symbols = Symbols::parse_text("206 draw_player\n300 player_sprite").unwrap();
+        assert_eq!(
+            disassemble_instruction(0x2206, &symbols),
+            "CALL draw_player"
+        );
+        assert_eq!(
+            disassemble_instruction(0xA300, &symbols),
+            "LD I, player_sprite"
+        );
+        assert_eq!(disassemble_instruction(0x1208, &symbols), "JP 0x208");
+        let mut memory = vec![0; 0x20A];
+        memory[0x204..0x20A].copy_from_slice(&[0x22, 0x06, 0x00, 0xEE, 0x12, 0x08]);
+        assert_eq!(
+            listing(&memory, 0x204, 0x20A, &symbols),
+            "    204  2206  CALL draw_player\ndraw_player:\n    206  00EE  RET\n    208  1208  JP 0x208\n"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use crate::emulator::{PixelStatus, CHIP8_SCREEN_HEIGHT, CHIP8_SCREEN_WIDTH};
+
+const SCREEN_SIZE: usize = CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT;
+const DEFAULT_PERSISTENCE_FRAMES: u32 = 4;
+const MAX_PERSISTENCE_FRAMES: u32 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterMode {
+    // Pixels are drawn exactly as the emulator reports them
+    Direct,
+    // Pixels turned off fade out over a number of frames
+    Persistence,
+    // Each frame is averaged with the previous one
+    FrameBlending,
+}
+
+pub struct DisplayFilter {
+    pub mode: FilterMode,
+    pub persistence_frames: u32,
+    intensities: [u8; SCREEN_SIZE],
+    previous_frame: [bool; SCREEN_SIZE],
+    settings_changed: bool,
+}
+
+impl DisplayFilter {
+    pub fn new() -> Self {
+        Self {
+            mode: FilterMode::Direct,
+            persistence_frames: DEFAULT_PERSISTENCE_FRAMES,
+            intensities: [0; SCREEN_SIZE],
+            previous_frame: [false; SCREEN_SIZE],
+            settings_changed: true,
+        }
+    }
+
+    pub fn next_mode(&mut self) {
+        self.mode = match self.mode {
+            FilterMode::Direct => FilterMode::Persistence,
+            FilterMode::Persistence => FilterMode::FrameBlending,
+            FilterMode::FrameBlending => FilterMode::Direct,
+        };
+        self.settings_changed = true;
+    }
+
+    pub fn increase_persistence(&mut self) {
+        if self.persistence_frames < MAX_PERSISTENCE_FRAMES {
+            self.persistence_frames += 1;
+        }
+        self.settings_changed = true;
+    }
+
+    pub fn decrease_persistence(&mut self) {
+        if self.persistence_frames > 1 {
+            self.persistence_frames -= 1;
+        }
+        self.settings_changed = true;
+    }
+
+    // Whether the output would differ from the last one even with an unchanged screen,
+    // for instance because pixels are still fading out
+    pub fn needs_update(&self) -> bool {
+        self.settings_changed
+            || self
+                .intensities
+                .iter()
+                .any(|intensity| *intensity != 0 && *intensity != u8::MAX)
+    }
+
+    // Computes the intensity (0 is black, 255 is white) of every pixel for this frame.
+    // Must be called exactly once per rendered frame, as fading is frame-based.
+    pub fn apply(&mut self, screen: &[PixelStatus]) -> &[u8; SCREEN_SIZE] {
+        let fade_step = (u8::MAX as u32).div_ceil(self.persistence_frames) as u8;
+        for (i, pixel) in screen.iter().enumerate() {
+            let lit = *pixel == PixelStatus::White;
+            self.intensities[i] = match self.mode {
+                FilterMode::Direct => {
+                    if lit {
+                        u8::MAX
+                    } else {
+                        0
+                    }
+                }
+                FilterMode::Persistence => {
+                    if lit {
+                        u8::MAX
+                    } else {
+                        self.intensities[i].saturating_sub(fade_step)
+                    }
+                }
+                FilterMode::FrameBlending => match (lit, self.previous_frame[i]) {
+                    (true, true) => u8::MAX,
+                    (false, false) => 0,
+                    _ => u8::MAX / 2,
+                },
+            };
+            self.previous_frame[i] = lit;
+        }
+        self.settings_changed = false;
+        &self.intensities
+    }
+}
+
+impl Default for DisplayFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Applies the filter to a screen where only the first pixel may be lit
+    fn first_pixel_intensity(filter: &mut DisplayFilter, status: PixelStatus) -> u8 {
+        let mut screen: Vec<PixelStatus> = (0..SCREEN_SIZE).map(|_| PixelStatus::Black).collect();
+        screen[0] = status;
+        filter.apply(&screen)[0]
+    }
+
+    #[test]
+    fn test_direct() {
+        let mut filter = DisplayFilter::new();
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::White), 255);
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::Black), 0);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let mut filter = DisplayFilter::new();
+        filter.mode = FilterMode::Persistence;
+        filter.persistence_frames = 3;
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::White), 255);
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::Black), 170);
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::Black), 85);
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::White), 255);
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::Black), 170);
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::Black), 85);
+        assert!(filter.needs_update());
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::Black), 0);
+        assert!(!filter.needs_update());
+    }
+
+    #[test]
+    fn test_frame_blending() {
+        let mut filter = DisplayFilter::new();
+        filter.mode = FilterMode::FrameBlending;
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::White), 127);
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::White), 255);
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::Black), 127);
+        assert_eq!(first_pixel_intensity(&mut filter, PixelStatus::Black), 0);
+    }
+}
@@ -1,14 +1,40 @@
-const CHIP8_MEMORY_SIZE: usize = 4096;
-const CHIP8_FIRST_BYTE_ADDRESS: usize = 512;
-const CHIP8_NUMBER_REGISTERS: usize = 16;
+use crate::coverage::Coverage;
+use crate::debugger::{Watchpoint, WatchpointHit};
+use crate::memory_heatmap::{MemoryAccess, MemoryHeatmap};
+use crate::profiler::Profiler;
+use crate::symbols::Symbols;
+
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use serde::Deserialize;
+
+pub const CHIP8_MEMORY_SIZE: usize = 4096;
+pub const CHIP8_FIRST_BYTE_ADDRESS: usize = 512;
+pub const CHIP8_MAX_PROGRAM_SIZE: usize = CHIP8_MEMORY_SIZE - CHIP8_FIRST_BYTE_ADDRESS;
+pub const CHIP8_NUMBER_REGISTERS: usize = 16;
 pub const CHIP8_SCREEN_WIDTH: usize = 64;
 pub const CHIP8_SCREEN_HEIGHT: usize = 32;
 const CHIP8_CALL_STACK_MAX_DEPTH: usize = 16;
-const CHIP8_NUMBER_KEYS: usize = 16;
+pub const CHIP8_NUMBER_KEYS: usize = 16;
+pub const CHIP8_NUMBER_RPL_FLAGS: usize = 16;
+
+// Explanation of every processed instruction on the standard output, when tracing is enabled
+macro_rules! trace {
+    ($emulator:expr) => {
+        if $emulator.trace {
+            println!();
+        }
+    };
+    ($emulator:expr, $($argument:tt)*) => {
+        if $emulator.trace {
+            println!($($argument)*);
+        }
+    };
+}
 
 #[allow(non_camel_case_types)]
 #[derive(Debug)]
-enum OpCode {
+pub(crate) enum OpCode {
     OC_0NNN(u16),
     OC_00E0,
     OC_00EE,
@@ -44,9 +70,53 @@ enum OpCode {
     // FX33
     OC_FX55(usize),
     OC_FX65(usize),
+    OC_FX75(usize),
+    OC_FX85(usize),
+}
+
+impl OpCode {
+    // Pattern of the instruction, used to group instructions of the same kind
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            OpCode::OC_0NNN(_) => "0NNN",
+            OpCode::OC_00E0 => "00E0",
+            OpCode::OC_00EE => "00EE",
+            OpCode::OC_1NNN(_) => "1NNN",
+            OpCode::OC_2NNN(_) => "2NNN",
+            OpCode::OC_3XNN(_, _) => "3XNN",
+            OpCode::OC_4XNN(_, _) => "4XNN",
+            OpCode::OC_5XY0(_, _) => "5XY0",
+            OpCode::OC_6XNN(_, _) => "6XNN",
+            OpCode::OC_7XNN(_, _) => "7XNN",
+            OpCode::OC_8XY0(_, _) => "8XY0",
+            OpCode::OC_8XY1(_, _) => "8XY1",
+            OpCode::OC_8XY2(_, _) => "8XY2",
+            OpCode::OC_8XY3(_, _) => "8XY3",
+            OpCode::OC_8XY4(_, _) => "8XY4",
+            OpCode::OC_8XY5(_, _) => "8XY5",
+            OpCode::OC_8XY6(_, _) => "8XY6",
+            OpCode::OC_8XY7(_, _) => "8XY7",
+            OpCode::OC_8XYE(_, _) => "8XYE",
+            OpCode::OC_9XY0(_, _) => "9XY0",
+            OpCode::OC_ANNN(_) => "ANNN",
+            OpCode::OC_CXNN(_, _) => "CXNN",
+            OpCode::OC_DXYN(_, _, _) => "DXYN",
+            OpCode::OC_EX9E(_) => "EX9E",
+            OpCode::OC_EXA1(_) => "EXA1",
+            OpCode::OC_FX07(_) => "FX07",
+            OpCode::OC_FX0A(_) => "FX0A",
+            OpCode::OC_FX15(_) => "FX15",
+            OpCode::OC_FX18(_) => "FX18",
+            OpCode::OC_FX1E(_) => "FX1E",
+            OpCode::OC_FX55(_) => "FX55",
+            OpCode::OC_FX65(_) => "FX65",
+            OpCode::OC_FX75(_) => "FX75",
+            OpCode::OC_FX85(_) => "FX85",
+        }
+    }
 }
 
-fn parse_opcode(raw_opcode: u16) -> Option<OpCode> {
+pub(crate) fn parse_opcode(raw_opcode: u16) -> Option<OpCode> {
     // 00E0
     if raw_opcode == 0x00E0 {
         return Some(OpCode::OC_00E0);
@@ -255,21 +325,70 @@ fn parse_opcode(raw_opcode: u16) -> Option<OpCode> {
         return Some(OpCode::OC_FX65(x));
     }
 
+    // FX75
+    if raw_opcode & 0xF0FF == 0xF075 {
+        let x: usize = ((0x0F00 & raw_opcode) >> 8) as usize;
+        return Some(OpCode::OC_FX75(x));
+    }
+
+    // FX85
+    if raw_opcode & 0xF0FF == 0xF085 {
+        let x: usize = ((0x0F00 & raw_opcode) >> 8) as usize;
+        return Some(OpCode::OC_FX85(x));
+    }
+
     return None;
 }
 
-#[derive(PartialEq, Debug)]
+// Programs are loaded at 0x200 and must fit in the rest of the memory
+pub fn check_program_size(program: &[u8]) -> Result<(), String> {
+    if program.len() > CHIP8_MAX_PROGRAM_SIZE {
+        return Err(format!(
+            "The program is {} bytes long, only {} bytes fit in memory",
+            program.len(),
+            CHIP8_MAX_PROGRAM_SIZE
+        ));
+    }
+    Ok(())
+}
+
+// One byte per pixel, 0 or 1, so that the screen can be shared with other languages
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
 pub enum PixelStatus {
-    Black,
-    White,
+    Black = 0,
+    White = 1,
+}
+
+// Behaviours that differ between CHIP8 interpreters, and that games rely on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Quirks {
+    // 8XY6 and 8XYE shift VY into VX, instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    // FX55 and FX65 leave I pointing after the last register stored or loaded
+    pub load_store_increments_i: bool,
+    // 8XY1, 8XY2 and 8XY3 reset VF to 0
+    pub logic_resets_vf: bool,
+    // Sprites crossing the border of the screen wrap around instead of being clipped
+    pub wrap_sprites: bool,
+}
+
+// When the hook of Emulator::run_frame_with is called
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InstructionStep {
+    Before,
+    After,
 }
 
 pub struct Emulator {
+    pub quirks: Quirks,
     memory: [u8; CHIP8_MEMORY_SIZE],
     program_counter: usize,
     generic_registers: [u8; CHIP8_NUMBER_REGISTERS],
     memory_register: usize,
     pub screen: [PixelStatus; (CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT) as usize],
+    pub screen_changed: bool,
     call_stack: [usize; CHIP8_CALL_STACK_MAX_DEPTH],
     call_stack_depth: usize,
     keys_pressed: [bool; CHIP8_NUMBER_KEYS],
@@ -277,18 +396,211 @@ pub struct Emulator {
     pub sound_clock: u8,
     pub waiting_for_key: bool,
     register_for_key: usize,
+    // SUPER-CHIP RPL user flags, which survive between runs of a program
+    rpl_flags: [u8; CHIP8_NUMBER_RPL_FLAGS],
+    pub rpl_flags_changed: bool,
+    // Only collects statistics when enabled, as it slows down every instruction
+    pub profiler: Option<Profiler>,
+    // Records executed instructions and sprite data when enabled
+    pub coverage: Option<Coverage>,
+    // Records recent fetches, reads and writes of memory when enabled
+    pub heatmap: Option<MemoryHeatmap>,
+    // Memory ranges whose reads or writes are reported in watchpoint_hit
+    pub watchpoints: Vec<Watchpoint>,
+    pub watchpoint_hit: Option<WatchpointHit>,
+    // Prints every processed instruction, frontends drawing in the terminal turn it off
+    pub trace: bool,
+    // Names of addresses, shown in traces instead of numbers
+    pub symbols: Symbols,
+    // Random numbers of CXNN, seeded to replay a run identically
+    rng: Pcg32,
+    // Key inputs since they were last collected, when recording them to replay a run
+    pub recorded_inputs: Option<Vec<(u8, bool)>>,
+    // Address and length of the data writes since they were last collected, when recording them
+    pub memory_writes: Option<Vec<(usize, usize)>>,
+}
+
+// Everything the program can observe or change, to go back to an earlier point of a run.
+// Quirks and instrumentation are not part of it.
+#[derive(Clone)]
+pub struct Snapshot {
+    memory: [u8; CHIP8_MEMORY_SIZE],
+    program_counter: usize,
+    generic_registers: [u8; CHIP8_NUMBER_REGISTERS],
+    memory_register: usize,
+    screen: [PixelStatus; CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT],
+    call_stack: [usize; CHIP8_CALL_STACK_MAX_DEPTH],
+    call_stack_depth: usize,
+    keys_pressed: [bool; CHIP8_NUMBER_KEYS],
+    system_clock: u8,
+    sound_clock: u8,
+    waiting_for_key: bool,
+    register_for_key: usize,
+    rpl_flags: [u8; CHIP8_NUMBER_RPL_FLAGS],
+    rng: Pcg32,
+}
+
+const SNAPSHOT_MAGIC: &[u8] = b"CHIP8ST1";
+// JSON of the random generator, padded with spaces, which fits its two 20 digit numbers
+const SNAPSHOT_RNG_SIZE: usize = 64;
+// Encoded snapshots always have this size
+pub const SNAPSHOT_SIZE: usize = SNAPSHOT_MAGIC.len()
+    + CHIP8_MEMORY_SIZE
+    + 2
+    + CHIP8_NUMBER_REGISTERS
+    + 2
+    + CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT
+    + 2 * CHIP8_CALL_STACK_MAX_DEPTH
+    + 1
+    + CHIP8_NUMBER_KEYS
+    + 4
+    + CHIP8_NUMBER_RPL_FLAGS
+    + SNAPSHOT_RNG_SIZE;
+const INVALID_STATE: &str = "Invalid state";
+
+// Fields of an encoded snapshot, read one after the other
+struct StateReader<'a> {
+    rest: &'a [u8],
+}
+
+impl StateReader<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8], String> {
+        if self.rest.len() < length {
+            return Err("Truncated state".to_string());
+        }
+        let (taken, rest) = self.rest.split_at(length);
+        self.rest = rest;
+        Ok(taken)
+    }
+
+    // Addresses of instructions need room for their two bytes, others for one byte
+    fn address(&mut self, size: usize) -> Result<usize, String> {
+        let bytes = self.take(2)?;
+        let address = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+        if address > CHIP8_MEMORY_SIZE - size {
+            return Err(INVALID_STATE.to_string());
+        }
+        Ok(address)
+    }
+}
+
+impl Snapshot {
+    // Binary encoding to write states to files: the fields in declaration order, addresses
+    // as big endian u16, flags and pixels as one byte, and the random generator last as JSON.
+    // It is SNAPSHOT_SIZE bytes long.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        let address = |bytes: &mut Vec<u8>, address: usize| {
+            bytes.extend_from_slice(&(address as u16).to_be_bytes())
+        };
+        bytes.extend_from_slice(&self.memory);
+        address(&mut bytes, self.program_counter);
+        bytes.extend_from_slice(&self.generic_registers);
+        address(&mut bytes, self.memory_register);
+        bytes.extend(
+            self.screen
+                .iter()
+                .map(|pixel| (*pixel == PixelStatus::White) as u8),
+        );
+        for return_address in self.call_stack {
+            address(&mut bytes, return_address);
+        }
+        bytes.push(self.call_stack_depth as u8);
+        bytes.extend(self.keys_pressed.iter().map(|pressed| *pressed as u8));
+        bytes.push(self.system_clock);
+        bytes.push(self.sound_clock);
+        bytes.push(self.waiting_for_key as u8);
+        bytes.push(self.register_for_key as u8);
+        bytes.extend_from_slice(&self.rpl_flags);
+        let rng = serde_json::to_vec(&self.rng).unwrap();
+        bytes.extend_from_slice(&rng);
+        bytes.resize(bytes.len() + SNAPSHOT_RNG_SIZE - rng.len(), b' ');
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = StateReader {
+            rest: bytes
+                .strip_prefix(SNAPSHOT_MAGIC)
+                .ok_or("Not a CHIP8 state")?,
+        };
+        let mut snapshot = Snapshot {
+            memory: [0; CHIP8_MEMORY_SIZE],
+            program_counter: 0,
+            generic_registers: [0; CHIP8_NUMBER_REGISTERS],
+            memory_register: 0,
+            screen: [SCREEN_ARRAY_REPEAT_VALUE; CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT],
+            call_stack: [0; CHIP8_CALL_STACK_MAX_DEPTH],
+            call_stack_depth: 0,
+            keys_pressed: [false; CHIP8_NUMBER_KEYS],
+            system_clock: 0,
+            sound_clock: 0,
+            waiting_for_key: false,
+            register_for_key: 0,
+            rpl_flags: [0; CHIP8_NUMBER_RPL_FLAGS],
+            rng: Pcg32::seed_from_u64(0),
+        };
+        snapshot
+            .memory
+            .copy_from_slice(reader.take(CHIP8_MEMORY_SIZE)?);
+        snapshot.program_counter = reader.address(2)?;
+        snapshot
+            .generic_registers
+            .copy_from_slice(reader.take(CHIP8_NUMBER_REGISTERS)?);
+        snapshot.memory_register = reader.address(1)?;
+        for (pixel, byte) in snapshot
+            .screen
+            .iter_mut()
+            .zip(reader.take(CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT)?)
+        {
+            if *byte != 0 {
+                *pixel = PixelStatus::White;
+            }
+        }
+        for return_address in snapshot.call_stack.iter_mut() {
+            *return_address = reader.address(2)?;
+        }
+        snapshot.call_stack_depth = reader.take(1)?[0] as usize;
+        for (pressed, byte) in snapshot
+            .keys_pressed
+            .iter_mut()
+            .zip(reader.take(CHIP8_NUMBER_KEYS)?)
+        {
+            *pressed = *byte != 0;
+        }
+        let clocks_and_key = reader.take(4)?;
+        snapshot.system_clock = clocks_and_key[0];
+        snapshot.sound_clock = clocks_and_key[1];
+        snapshot.waiting_for_key = clocks_and_key[2] != 0;
+        snapshot.register_for_key = clocks_and_key[3] as usize;
+        snapshot
+            .rpl_flags
+            .copy_from_slice(reader.take(CHIP8_NUMBER_RPL_FLAGS)?);
+        snapshot.rng =
+            serde_json::from_slice(reader.take(SNAPSHOT_RNG_SIZE)?).map_err(|_| INVALID_STATE)?;
+
+        if snapshot.call_stack_depth > CHIP8_CALL_STACK_MAX_DEPTH
+            || snapshot.register_for_key >= CHIP8_NUMBER_REGISTERS
+            || !reader.rest.is_empty()
+        {
+            return Err(INVALID_STATE.to_string());
+        }
+        Ok(snapshot)
+    }
 }
 
 const SCREEN_ARRAY_REPEAT_VALUE: PixelStatus = PixelStatus::Black;
 impl Emulator {
     pub fn new() -> Self {
         Self {
+            quirks: Quirks::default(),
             memory: [0; CHIP8_MEMORY_SIZE],
             program_counter: CHIP8_FIRST_BYTE_ADDRESS,
             generic_registers: [0; CHIP8_NUMBER_REGISTERS],
             memory_register: 0,
             screen: [SCREEN_ARRAY_REPEAT_VALUE;
                 (CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT) as usize],
+            screen_changed: true,
             call_stack: [0; CHIP8_CALL_STACK_MAX_DEPTH],
             call_stack_depth: 0,
             keys_pressed: [false; CHIP8_NUMBER_KEYS],
@@ -296,9 +608,145 @@ impl Emulator {
             sound_clock: 0,
             waiting_for_key: false,
             register_for_key: 0,
+            rpl_flags: [0; CHIP8_NUMBER_RPL_FLAGS],
+            rpl_flags_changed: false,
+            profiler: None,
+            coverage: None,
+            heatmap: None,
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
+            trace: true,
+            symbols: Symbols::default(),
+            rng: Pcg32::from_entropy(),
+            recorded_inputs: None,
+            memory_writes: None,
+        }
+    }
+
+    // Puts back registers, memory, screen, call stack and timers in their initial state.
+    // Quirks, tracing and symbols are kept, but the program has to be loaded again afterwards.
+    // A running profiler or coverage recording starts over, as it was about the previous run.
+    pub fn reset(&mut self) {
+        let quirks = self.quirks;
+        let profiling = self.profiler.is_some();
+        let recording_coverage = self.coverage.is_some();
+        let heatmap = self.heatmap.take();
+        let watchpoints = std::mem::take(&mut self.watchpoints);
+        let trace = self.trace;
+        let symbols = std::mem::take(&mut self.symbols);
+        *self = Self::new();
+        self.quirks = quirks;
+        self.trace = trace;
+        self.symbols = symbols;
+        self.heatmap = heatmap;
+        self.watchpoints = watchpoints;
+        if profiling {
+            self.profiler = Some(Profiler::new());
+        }
+        if recording_coverage {
+            self.coverage = Some(Coverage::new());
+        }
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    pub fn set_program_counter(&mut self, address: usize) {
+        self.program_counter = address;
+    }
+
+    pub fn index_register(&self) -> usize {
+        self.memory_register
+    }
+
+    pub fn set_index_register(&mut self, address: usize) {
+        self.memory_register = address;
+    }
+
+    // Number of return addresses on the call stack
+    pub fn stack_pointer(&self) -> usize {
+        self.call_stack_depth
+    }
+
+    // Addresses of the CALL instructions being run, the innermost last
+    pub fn call_stack(&self) -> &[usize] {
+        &self.call_stack[..self.call_stack_depth]
+    }
+
+    pub fn memory(&self) -> &[u8; CHIP8_MEMORY_SIZE] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8; CHIP8_MEMORY_SIZE] {
+        &mut self.memory
+    }
+
+    pub fn write_memory(&mut self, address: usize, value: u8) {
+        self.memory[address] = value;
+    }
+
+    pub fn registers(&self) -> &[u8; CHIP8_NUMBER_REGISTERS] {
+        &self.generic_registers
+    }
+
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.generic_registers[x] = value;
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; CHIP8_NUMBER_RPL_FLAGS] {
+        &self.rpl_flags
+    }
+
+    // Restores flags saved by a previous run, without marking them as changed
+    pub fn set_rpl_flags(&mut self, flags: &[u8; CHIP8_NUMBER_RPL_FLAGS]) {
+        self.rpl_flags = *flags;
+        self.rpl_flags_changed = false;
+    }
+
+    // Makes CXNN draw the same numbers on every run with the same seed
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Pcg32::seed_from_u64(seed);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory,
+            program_counter: self.program_counter,
+            generic_registers: self.generic_registers,
+            memory_register: self.memory_register,
+            screen: self.screen,
+            call_stack: self.call_stack,
+            call_stack_depth: self.call_stack_depth,
+            keys_pressed: self.keys_pressed,
+            system_clock: self.system_clock,
+            sound_clock: self.sound_clock,
+            waiting_for_key: self.waiting_for_key,
+            register_for_key: self.register_for_key,
+            rpl_flags: self.rpl_flags,
+            rng: self.rng.clone(),
         }
     }
 
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.memory = snapshot.memory;
+        self.program_counter = snapshot.program_counter;
+        self.generic_registers = snapshot.generic_registers;
+        self.memory_register = snapshot.memory_register;
+        self.screen = snapshot.screen;
+        self.screen_changed = true;
+        self.call_stack = snapshot.call_stack;
+        self.call_stack_depth = snapshot.call_stack_depth;
+        self.keys_pressed = snapshot.keys_pressed;
+        self.system_clock = snapshot.system_clock;
+        self.sound_clock = snapshot.sound_clock;
+        self.waiting_for_key = snapshot.waiting_for_key;
+        self.register_for_key = snapshot.register_for_key;
+        self.rpl_flags = snapshot.rpl_flags;
+        self.rng = snapshot.rng.clone();
+    }
+
+    // Programs are checked with check_program_size first, the emulator panics on larger ones
     pub fn load_program(&mut self, program: &[u8]) {
         for (i, byte) in program.iter().enumerate() {
             self.memory[CHIP8_FIRST_BYTE_ADDRESS + i] = *byte
@@ -306,7 +754,14 @@ impl Emulator {
         self.program_counter = CHIP8_FIRST_BYTE_ADDRESS;
     }
 
+    pub fn key_pressed(&self, keycode: u8) -> bool {
+        self.keys_pressed[keycode as usize]
+    }
+
     pub fn input_key(&mut self, keycode: u8, keypressed: bool) {
+        if let Some(recorded_inputs) = &mut self.recorded_inputs {
+            recorded_inputs.push((keycode, keypressed));
+        }
         self.keys_pressed[keycode as usize] = keypressed;
         if self.waiting_for_key && keypressed {
             self.generic_registers[self.register_for_key] = keycode;
@@ -314,14 +769,64 @@ impl Emulator {
         }
     }
 
+    // Several cpu cycles per timer tick, returns the number of instructions executed
+    pub fn run_frame(&mut self, instructions_per_frame: u32) -> u32 {
+        self.run_frame_with(instructions_per_frame, |_, _| {})
+    }
+
+    // Same as run_frame, calling the hook before and after every instruction
+    pub fn run_frame_with(
+        &mut self,
+        instructions_per_frame: u32,
+        mut hook: impl FnMut(&mut Self, InstructionStep),
+    ) -> u32 {
+        let mut instructions_executed = 0;
+        if !self.waiting_for_key {
+            for _ in 0..instructions_per_frame {
+                hook(self, InstructionStep::Before);
+                self.process_next_instruction();
+                trace!(self);
+                instructions_executed += 1;
+                hook(self, InstructionStep::After);
+            }
+        }
+
+        self.tick_timers();
+        instructions_executed
+    }
+
+    // Timers count down once per frame, at 60Hz
+    pub fn tick_timers(&mut self) {
+        if self.system_clock > 0 {
+            self.system_clock -= 1;
+        }
+
+        if self.sound_clock > 0 {
+            self.sound_clock -= 1;
+        }
+    }
+
+    // Name of the address if it has one, its decimal value otherwise
+    fn trace_address(&self, address: usize) -> String {
+        match self.symbols.name(address) {
+            Some(name) => name.to_string(),
+            None => address.to_string(),
+        }
+    }
+
     pub fn process_next_instruction(&mut self) {
-        println!("Reading code and processing next instruction...");
+        trace!(self, "Reading code and processing next instruction...");
 
         // Read next, which is build from the next two bytes
         let opcode_first_part: u16 = self.memory[self.program_counter] as u16;
         let opcode_second_part: u16 = self.memory[self.program_counter + 1] as u16;
         let opcode_raw: u16 = (opcode_first_part << 8) + opcode_second_part;
-        println!("Bytes read, to be parsed as opcode: {:#06X}", opcode_raw);
+        self.record_memory_access(MemoryAccess::Fetch, self.program_counter, 2);
+        trace!(
+            self,
+            "Bytes read, to be parsed as opcode: {:#06X}",
+            opcode_raw
+        );
 
         // Parse what we just read
         let identified_opcode = parse_opcode(opcode_raw);
@@ -329,7 +834,13 @@ impl Emulator {
         // Process the new opcode
         match identified_opcode {
             Some(ref opcode) => {
-                println!("Identified read opcode as {:?}", opcode);
+                trace!(self, "Identified read opcode as {:?}", opcode);
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.record_instruction(self.program_counter, opcode);
+                }
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.record_instruction(self.program_counter);
+                }
                 self.process_opcode(opcode);
             }
             None => panic!("Unidentified opcode 0x{:X}!", opcode_raw),
@@ -339,18 +850,34 @@ impl Emulator {
         self.program_counter += 2;
     }
 
+    fn record_memory_access(&mut self, access: MemoryAccess, address: usize, length: usize) {
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record(access, address, length);
+        }
+        if let (MemoryAccess::Write, Some(memory_writes)) = (access, &mut self.memory_writes) {
+            memory_writes.push((address, length));
+        }
+        if self.watchpoint_hit.is_none() {
+            self.watchpoint_hit = self
+                .watchpoints
+                .iter()
+                .find_map(|watchpoint| watchpoint.check(access, address, length));
+        }
+    }
+
     fn process_opcode(&mut self, opcode: &OpCode) {
         match opcode {
             OpCode::OC_00E0 => {
                 // Clears screen
-                println!("Clearing screen");
+                trace!(self, "Clearing screen");
                 self.screen = [SCREEN_ARRAY_REPEAT_VALUE;
                     (CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT) as usize];
+                self.screen_changed = true;
             }
 
             OpCode::OC_00EE => {
                 // Jumps back in the call stack
-                println!("Jumping back in call stack");
+                trace!(self, "Jumping back in call stack");
                 self.call_stack_depth -= 1;
                 self.program_counter = self.call_stack[self.call_stack_depth];
             }
@@ -362,14 +889,18 @@ impl Emulator {
 
             OpCode::OC_1NNN(nnn) => {
                 // Next instruction will be at address NNN
-                println!("Setting pc to {}", nnn);
+                trace!(self, "Setting pc to {}", self.trace_address(*nnn));
                 self.program_counter = *nnn - 2; // TODO: increase pc in this function to avoid hack?
             }
 
             OpCode::OC_2NNN(nnn) => {
                 // Next instruction will be at address NNN.
                 // However, this time, we keep the previous pc value.
-                println!("Jumping to {} while increasing call stack", nnn);
+                trace!(
+                    self,
+                    "Jumping to {} while increasing call stack",
+                    self.trace_address(*nnn)
+                );
                 self.call_stack[self.call_stack_depth] = self.program_counter;
                 self.call_stack_depth += 1;
                 self.program_counter = *nnn - 2; // TODO: increase pc in this function to avoid hack?
@@ -377,7 +908,7 @@ impl Emulator {
 
             OpCode::OC_3XNN(x, nn) => {
                 // Next instruction will be skipped if VX == NN
-                println!("Skipping next instruction if V{:X} == {}", x, nn);
+                trace!(self, "Skipping next instruction if V{:X} == {}", x, nn);
                 if self.generic_registers[*x] == *nn {
                     self.program_counter += 2;
                 }
@@ -385,7 +916,7 @@ impl Emulator {
 
             OpCode::OC_4XNN(x, nn) => {
                 // Next instruction will be skipped if VX != NN
-                println!("Skipping next instruction if V{:X} != {}", x, nn);
+                trace!(self, "Skipping next instruction if V{:X} != {}", x, nn);
                 if self.generic_registers[*x] != *nn {
                     self.program_counter += 2;
                 }
@@ -393,7 +924,7 @@ impl Emulator {
 
             OpCode::OC_5XY0(x, y) => {
                 // Skips next instruction if VX == VY
-                println!("Skipping next instruction if V{:X} == V{:X}", x, y);
+                trace!(self, "Skipping next instruction if V{:X} == V{:X}", x, y);
                 if self.generic_registers[*x] == self.generic_registers[*y] {
                     self.program_counter += 2;
                 }
@@ -401,45 +932,56 @@ impl Emulator {
 
             OpCode::OC_6XNN(x, nn) => {
                 // Defines register VX to NN
-                println!("Setting register V{:X} to {:#X}", x, nn);
+                trace!(self, "Setting register V{:X} to {:#X}", x, nn);
                 self.generic_registers[*x] = *nn;
             }
 
             OpCode::OC_7XNN(x, nn) => {
                 // Adds NN to register VX
-                println!("Adding {:#X} to register V{:X}", nn, x);
+                trace!(self, "Adding {:#X} to register V{:X}", nn, x);
                 self.generic_registers[*x] = self.generic_registers[*x].wrapping_add(*nn);
             }
 
             OpCode::OC_8XY0(x, y) => {
                 // Set register VX to the value of register VY
-                println!("Setting register V{:X} to the value of V{:X}", x, y);
+                trace!(self, "Setting register V{:X} to the value of V{:X}", x, y);
                 self.generic_registers[*x] = self.generic_registers[*y];
             }
 
             OpCode::OC_8XY1(x, y) => {
                 // Set register VX to the value of VX | VY
-                println!("OR'ing register V{:X} with the value of V{:X}", x, y);
+                trace!(self, "OR'ing register V{:X} with the value of V{:X}", x, y);
                 self.generic_registers[*x] |= self.generic_registers[*y];
+                if self.quirks.logic_resets_vf {
+                    self.generic_registers[0xF] = 0;
+                }
             }
 
             OpCode::OC_8XY2(x, y) => {
                 // Set register VX to the value of VX & VY
-                println!("AND'ing register V{:X} with the value of V{:X}", x, y);
+                trace!(self, "AND'ing register V{:X} with the value of V{:X}", x, y);
                 self.generic_registers[*x] &= self.generic_registers[*y];
+                if self.quirks.logic_resets_vf {
+                    self.generic_registers[0xF] = 0;
+                }
             }
 
             OpCode::OC_8XY3(x, y) => {
                 // Set register VX to the value of VX ^ VY
-                println!("XOR'ing register V{:X} with the value of V{:X}", x, y);
+                trace!(self, "XOR'ing register V{:X} with the value of V{:X}", x, y);
                 self.generic_registers[*x] ^= self.generic_registers[*y];
+                if self.quirks.logic_resets_vf {
+                    self.generic_registers[0xF] = 0;
+                }
             }
 
             OpCode::OC_8XY4(x, y) => {
                 // Set register VX to the value of VX + VY, write carry in VF
-                println!(
+                trace!(
+                    self,
                     "Adding register V{:X} with the value of V{:X}, while putting carry in VF",
-                    x, y
+                    x,
+                    y
                 );
                 let (result, carry) =
                     self.generic_registers[*x].overflowing_add(self.generic_registers[*y]);
@@ -449,47 +991,53 @@ impl Emulator {
 
             OpCode::OC_8XY5(x, y) => {
                 // Set register VX to the value of VX - VY, write carry in VF
-                println!("Substracting register V{:X} with the value of V{:X}, while putting carry in VF", x, y);
+                trace!(self, "Substracting register V{:X} with the value of V{:X}, while putting carry in VF", x, y);
                 let (result, carry) =
                     self.generic_registers[*x].overflowing_sub(self.generic_registers[*y]);
                 self.generic_registers[*x] = result;
                 self.generic_registers[0xF] = if carry { 0 } else { 1 };
             }
 
-            OpCode::OC_8XY6(x, ..) => {
+            OpCode::OC_8XY6(x, y) => {
                 // Shifts VX to the right by 1 bit. VF will contain the lost bit.
-                // Somehow, Y is not used?
-                println!(
+                // Y is only used by some interpreters, as the register to shift into VX.
+                trace!(
+                    self,
                     "Shifting right register V{:X} with the lost bit written in VF",
                     x
                 );
-                self.generic_registers[0xF] = self.generic_registers[*x] & 0x01;
-                self.generic_registers[*x] >>= 1;
+                let source = if self.quirks.shift_uses_vy { *y } else { *x };
+                let value = self.generic_registers[source];
+                self.generic_registers[*x] = value >> 1;
+                self.generic_registers[0xF] = value & 0x01;
             }
 
             OpCode::OC_8XY7(x, y) => {
                 // Sets VX to VY - VX. VF is set to 0 if there is an overflow, 1 otherwise.
-                println!("Setting register V{:X} to V{:X} - V{:X} with opposite of overflow written in VF", x, y, x);
+                trace!(self, "Setting register V{:X} to V{:X} - V{:X} with opposite of overflow written in VF", x, y, x);
                 let (result, overflow) =
                     self.generic_registers[*y].overflowing_sub(self.generic_registers[*x]);
                 self.generic_registers[*x] = result;
                 self.generic_registers[0xF] = if overflow { 0 } else { 1 };
             }
 
-            OpCode::OC_8XYE(x, ..) => {
+            OpCode::OC_8XYE(x, y) => {
                 // Shifts VX to the left by 1 bit. VF will contain the lost bit.
-                // Somehow, Y is not used?
-                println!(
+                // Y is only used by some interpreters, as the register to shift into VX.
+                trace!(
+                    self,
                     "Shifting left register V{:X} with the lost bit written in VF",
                     x
                 );
-                self.generic_registers[0xF] = (self.generic_registers[*x] & 0b10000000) >> 7;
-                self.generic_registers[*x] <<= 1;
+                let source = if self.quirks.shift_uses_vy { *y } else { *x };
+                let value = self.generic_registers[source];
+                self.generic_registers[*x] = value << 1;
+                self.generic_registers[0xF] = (value & 0b10000000) >> 7;
             }
 
             OpCode::OC_9XY0(x, y) => {
                 // Skips next instruction if VX != VY
-                println!("Skipping next instruction if V{:X} != V{:X}", x, y);
+                trace!(self, "Skipping next instruction if V{:X} != V{:X}", x, y);
                 if self.generic_registers[*x] != self.generic_registers[*y] {
                     self.program_counter += 2;
                 }
@@ -497,25 +1045,35 @@ impl Emulator {
 
             OpCode::OC_ANNN(nnn) => {
                 // Set register I to NNN
-                println!("Setting I to {}", nnn);
+                trace!(self, "Setting I to {}", self.trace_address(*nnn));
                 self.memory_register = *nnn;
             }
 
             OpCode::OC_CXNN(x, nn) => {
                 // Set register VX to a random number between 0 and nn
-                println!("Setting V{:x} to a random number less than {}", x, nn);
-                self.generic_registers[*x] = rand::random::<u8>() & nn;
+                trace!(self, "Setting V{:x} to a random number less than {}", x, nn);
+                self.generic_registers[*x] = self.rng.gen::<u8>() & nn;
             }
 
             OpCode::OC_DXYN(x, y, n) => {
                 // Draw sprite with height n at coordinates (VX, VY)
-                println!(
+                trace!(
+                    self,
                     "Drawing sprite with height {} at (V{:x} = {}, V{:x} = {})",
-                    n, x, self.generic_registers[*x], y, self.generic_registers[*y]
+                    n,
+                    x,
+                    self.generic_registers[*x],
+                    y,
+                    self.generic_registers[*y]
                 );
-                let pos_x = self.generic_registers[*x] as usize;
-                let pos_y = self.generic_registers[*y] as usize;
+                // The starting position always wraps around, the rest of the sprite may not
+                let pos_x = self.generic_registers[*x] as usize % CHIP8_SCREEN_WIDTH;
+                let pos_y = self.generic_registers[*y] as usize % CHIP8_SCREEN_HEIGHT;
                 let mut any_pixel_turned_off = false;
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.record_sprite(self.memory_register, *n);
+                }
+                self.record_memory_access(MemoryAccess::Read, self.memory_register, *n);
                 for (offset_y, byte) in self.memory[self.memory_register..self.memory_register + n]
                     .iter()
                     .enumerate()
@@ -523,11 +1081,16 @@ impl Emulator {
                     for bit_index in (0..8).rev() {
                         let offset_x = 7 - bit_index;
                         let switch_pixel = (byte & (1 << bit_index)) >> bit_index == 1;
+                        let mut pixel_x = pos_x + offset_x;
+                        let mut pixel_y = pos_y + offset_y;
+                        if self.quirks.wrap_sprites {
+                            pixel_x %= CHIP8_SCREEN_WIDTH;
+                            pixel_y %= CHIP8_SCREEN_HEIGHT;
+                        } else if pixel_x >= CHIP8_SCREEN_WIDTH || pixel_y >= CHIP8_SCREEN_HEIGHT {
+                            continue;
+                        }
                         if switch_pixel {
-                            let pixel_coordinate: usize = (pos_y + offset_y)
-                                * (CHIP8_SCREEN_WIDTH as usize)
-                                + pos_x
-                                + offset_x;
+                            let pixel_coordinate: usize = pixel_y * CHIP8_SCREEN_WIDTH + pixel_x;
                             match self.screen[pixel_coordinate] {
                                 PixelStatus::Black => {
                                     self.screen[pixel_coordinate] = PixelStatus::White
@@ -541,11 +1104,16 @@ impl Emulator {
                     }
                 }
                 self.generic_registers[0xF] = if any_pixel_turned_off { 1 } else { 0 };
+                self.screen_changed = true;
             }
 
             OpCode::OC_EX9E(x) => {
                 // Skips next instruction if key indicated by VX is pressed
-                println!("Skipping next instruction if V{:X}'s key is pressed", x);
+                trace!(
+                    self,
+                    "Skipping next instruction if V{:X}'s key is pressed",
+                    x
+                );
                 if self.keys_pressed[self.generic_registers[*x] as usize] {
                     self.program_counter += 2;
                 }
@@ -553,7 +1121,11 @@ impl Emulator {
 
             OpCode::OC_EXA1(x) => {
                 // Skips next instruction if key indicated by VX is *not* pressed
-                println!("Skipping next instruction if V{:X}'s key is pressed", x);
+                trace!(
+                    self,
+                    "Skipping next instruction if V{:X}'s key is pressed",
+                    x
+                );
                 if !self.keys_pressed[self.generic_registers[*x] as usize] {
                     self.program_counter += 2;
                 }
@@ -561,32 +1133,45 @@ impl Emulator {
 
             OpCode::OC_FX07(x) => {
                 // Sets VX to the current value of the system clock
-                println!("Setting V{:X} to the current value of system clock", x);
+                trace!(
+                    self,
+                    "Setting V{:X} to the current value of system clock",
+                    x
+                );
                 self.generic_registers[*x] = self.system_clock;
             }
 
             OpCode::OC_FX0A(x) => {
                 // Request for a key to be put in a certain buffer
-                println!("Requesting next key to be stored in V{:X}", x);
+                trace!(self, "Requesting next key to be stored in V{:X}", x);
                 self.waiting_for_key = true;
                 self.register_for_key = *x;
             }
 
             OpCode::OC_FX18(x) => {
                 // Sets the sound clock to the current value of VX
-                println!("Setting the sound clock to the current value of V{:X}", x);
+                trace!(
+                    self,
+                    "Setting the sound clock to the current value of V{:X}",
+                    x
+                );
                 self.sound_clock = self.generic_registers[*x];
             }
 
             OpCode::OC_FX15(x) => {
                 // Sets the system clock to the current value of VX
-                println!("Setting the system clock to the current value of V{:X}", x);
+                trace!(
+                    self,
+                    "Setting the system clock to the current value of V{:X}",
+                    x
+                );
                 self.system_clock = self.generic_registers[*x];
             }
 
             OpCode::OC_FX1E(x) => {
                 // Add VX to I, taking into account overflow. Writes overflow in VF.
-                println!(
+                trace!(
+                    self,
                     "Adding V{:X} to I, writing overflow in VF in memory at I",
                     x
                 );
@@ -601,9 +1186,11 @@ impl Emulator {
 
             OpCode::OC_FX55(x) => {
                 // Load bytes in V0, ..., VX in memory at I
-                println!("Loading V0, ..., V{:X} in memory at I", x);
+                trace!(self, "Loading V0, ..., V{:X} in memory at I", x);
+                self.record_memory_access(MemoryAccess::Write, self.memory_register, *x + 1);
                 for i in 0..=*x {
-                    println!(
+                    trace!(
+                        self,
                         "Loading V{:x} {:b} at {:x}",
                         i,
                         self.generic_registers[i],
@@ -611,19 +1198,45 @@ impl Emulator {
                     );
                     self.memory[self.memory_register + i] = self.generic_registers[i];
                 }
+                if self.quirks.load_store_increments_i {
+                    self.memory_register += *x + 1;
+                }
             }
 
             OpCode::OC_FX65(x) => {
                 // Load bytes in memory at I into V0, ..., VX
-                println!("Loading bytes from I into V0, ..., V{:X}", x);
+                trace!(self, "Loading bytes from I into V0, ..., V{:X}", x);
+                self.record_memory_access(MemoryAccess::Read, self.memory_register, *x + 1);
                 for i in 0..=*x {
                     self.generic_registers[i] = self.memory[self.memory_register + i];
                 }
+                if self.quirks.load_store_increments_i {
+                    self.memory_register += *x + 1;
+                }
+            }
+
+            OpCode::OC_FX75(x) => {
+                // Store V0, ..., VX in the RPL user flags
+                trace!(self, "Storing V0, ..., V{:X} in RPL user flags", x);
+                self.rpl_flags[..=*x].copy_from_slice(&self.generic_registers[..=*x]);
+                self.rpl_flags_changed = true;
+            }
+
+            OpCode::OC_FX85(x) => {
+                // Load the RPL user flags into V0, ..., VX
+                trace!(self, "Loading RPL user flags into V0, ..., V{:X}", x);
+                self.generic_registers[..=*x].copy_from_slice(&self.rpl_flags[..=*x]);
             }
         }
     }
 }
 
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -636,6 +1249,76 @@ mod tests {
         emulator.process_next_instruction();
     }
 
+    #[test]
+    fn test_reset() {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&[0x6A, 0x15, 0x22, 0x00]);
+        emulator.process_next_instruction();
+        emulator.process_next_instruction();
+        emulator.screen[0x10] = PixelStatus::White;
+        emulator.system_clock = 10;
+        emulator.quirks.wrap_sprites = true;
+        emulator.reset();
+        assert!(emulator.quirks.wrap_sprites);
+        assert_eq!(emulator.generic_registers[0xA], 0);
+        assert_eq!(emulator.call_stack_depth, 0);
+        assert_eq!(emulator.program_counter, CHIP8_FIRST_BYTE_ADDRESS);
+        assert_eq!(emulator.memory[CHIP8_FIRST_BYTE_ADDRESS], 0);
+        assert_eq!(emulator.screen[0x10], PixelStatus::Black);
+        assert_eq!(emulator.system_clock, 0);
+    }
+
+    #[test]
+    fn test_snapshot_bytes() {
+        let mut emulator = Emulator::new();
+        emulator.trace = false;
+        emulator.seed_rng(7);
+        emulator.load_program(&[0x6A, 0x15, 0x22, 0x06, 0x00, 0x00, 0xC0, 0xFF]);
+        for _ in 0..2 {
+            emulator.process_next_instruction();
+        }
+        emulator.screen[0x10] = PixelStatus::White;
+        emulator.input_key(4, true);
+        let bytes = emulator.snapshot().to_bytes();
+        assert_eq!(bytes.len(), SNAPSHOT_SIZE);
+
+        let mut restored = Emulator::new();
+        restored.trace = false;
+        restored.restore(&Snapshot::from_bytes(&bytes).unwrap());
+        assert_eq!(restored.generic_registers, emulator.generic_registers);
+        assert_eq!(restored.call_stack(), &[0x202]);
+        assert_eq!(restored.program_counter, emulator.program_counter);
+        assert_eq!(restored.screen[0x10], PixelStatus::White);
+        assert!(restored.key_pressed(4));
+        emulator.process_next_instruction();
+        restored.process_next_instruction();
+        assert_eq!(restored.generic_registers[0], emulator.generic_registers[0]);
+
+        assert!(Snapshot::from_bytes(&bytes[..100]).is_err());
+        assert!(Snapshot::from_bytes(b"not a state").is_err());
+
+        // Program counter, memory register and a return address out of memory
+        let program_counter = SNAPSHOT_MAGIC.len() + CHIP8_MEMORY_SIZE;
+        let memory_register = program_counter + 2 + CHIP8_NUMBER_REGISTERS;
+        let call_stack = memory_register + 2 + CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT;
+        for (offset, address) in [
+            (program_counter, 0x0FFF),
+            (memory_register, 0x1000),
+            (call_stack, 0x0FFF),
+            (call_stack + 2, 0xFFFF),
+        ] {
+            let mut invalid = bytes.clone();
+            invalid[offset..offset + 2].copy_from_slice(&(address as u16).to_be_bytes());
+            assert_eq!(
+                Snapshot::from_bytes(&invalid).err().as_deref(),
+                Some(INVALID_STATE)
+            );
+        }
+        let mut valid = bytes.clone();
+        valid[memory_register..memory_register + 2].copy_from_slice(&0x0FFFu16.to_be_bytes());
+        assert!(Snapshot::from_bytes(&valid).is_ok());
+    }
+
     #[test]
     #[allow(non_snake_case)]
     #[should_panic(expected = "OpCode 0NNN not implemented!")]
@@ -651,8 +1334,10 @@ mod tests {
         let mut emulator = Emulator::new();
         emulator.load_program(&[0x00, 0xE0]);
         emulator.screen[0x10] = PixelStatus::White;
+        emulator.screen_changed = false;
         emulator.process_next_instruction();
         assert_eq!(emulator.screen[0x10], PixelStatus::Black);
+        assert!(emulator.screen_changed);
     }
 
     #[test]
@@ -999,8 +1684,10 @@ mod tests {
         emulator.memory_register = 0x300;
         emulator.memory[0x300] = 0b10101010;
         emulator.memory[0x301] = 0b11001100;
+        emulator.screen_changed = false;
 
         emulator.process_next_instruction();
+        assert!(emulator.screen_changed);
 
         for j in 0..CHIP8_SCREEN_HEIGHT {
             println!(
@@ -1227,4 +1914,101 @@ mod tests {
         assert_eq!(emulator.generic_registers[0x1], 0b11001100);
         assert_eq!(emulator.program_counter, CHIP8_FIRST_BYTE_ADDRESS + 2);
     }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn test_opcode_FX75() {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&[0x60, 0x12, 0x61, 0x34, 0xF1, 0x75]);
+        emulator.process_next_instruction();
+        emulator.process_next_instruction();
+        assert!(!emulator.rpl_flags_changed);
+        emulator.process_next_instruction();
+        assert_eq!(emulator.rpl_flags()[..3], [0x12, 0x34, 0x00]);
+        assert!(emulator.rpl_flags_changed);
+        assert_eq!(emulator.program_counter, CHIP8_FIRST_BYTE_ADDRESS + 6);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn test_opcode_FX85() {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&[0xF1, 0x85]);
+        let mut flags = [0; CHIP8_NUMBER_RPL_FLAGS];
+        flags[0] = 0x56;
+        flags[1] = 0x78;
+        flags[2] = 0x9A;
+        emulator.set_rpl_flags(&flags);
+        emulator.process_next_instruction();
+        assert_eq!(emulator.generic_registers[..3], [0x56, 0x78, 0x00]);
+        assert!(!emulator.rpl_flags_changed);
+        assert_eq!(emulator.program_counter, CHIP8_FIRST_BYTE_ADDRESS + 2);
+    }
+
+    #[test]
+    fn test_quirk_shift_uses_vy() {
+        let mut emulator = Emulator::new();
+        emulator.quirks.shift_uses_vy = true;
+        emulator.load_program(&[0x6A, 0x01, 0x6B, 0b0110, 0x8A, 0xB6, 0x8A, 0xBE]);
+        emulator.process_next_instruction();
+        emulator.process_next_instruction();
+        emulator.process_next_instruction();
+        assert_eq!(emulator.generic_registers[0xA], 0b0011);
+        assert_eq!(emulator.generic_registers[0xF], 0x00);
+        emulator.process_next_instruction();
+        assert_eq!(emulator.generic_registers[0xA], 0b1100);
+        assert_eq!(emulator.generic_registers[0xB], 0b0110);
+    }
+
+    #[test]
+    fn test_quirk_load_store_increments_i() {
+        let mut emulator = Emulator::new();
+        emulator.quirks.load_store_increments_i = true;
+        emulator.load_program(&[0xA3, 0x00, 0xF1, 0x55, 0xF2, 0x65]);
+        emulator.process_next_instruction();
+        emulator.process_next_instruction();
+        assert_eq!(emulator.memory_register, 0x302);
+        emulator.process_next_instruction();
+        assert_eq!(emulator.memory_register, 0x305);
+    }
+
+    #[test]
+    fn test_quirk_logic_resets_vf() {
+        let mut emulator = Emulator::new();
+        emulator.quirks.logic_resets_vf = true;
+        emulator.load_program(&[0x6F, 0x05, 0x8A, 0xB1]);
+        emulator.process_next_instruction();
+        emulator.process_next_instruction();
+        assert_eq!(emulator.generic_registers[0xF], 0x00);
+    }
+
+    #[test]
+    fn test_quirk_wrap_sprites() {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&[0xD0, 0x11, 0xD0, 0x11]);
+        emulator.generic_registers[0] = (CHIP8_SCREEN_WIDTH - 4) as u8;
+        emulator.generic_registers[1] = (CHIP8_SCREEN_HEIGHT - 1) as u8;
+        emulator.memory_register = 0x300;
+        emulator.memory[0x300] = 0b11111111;
+
+        // Clipped by default
+        emulator.process_next_instruction();
+        let last_line = (CHIP8_SCREEN_HEIGHT - 1) * CHIP8_SCREEN_WIDTH;
+        assert_eq!(emulator.screen[last_line - 1], PixelStatus::Black);
+        assert_eq!(emulator.screen[last_line], PixelStatus::Black);
+        assert_eq!(
+            emulator.screen[last_line + CHIP8_SCREEN_WIDTH - 1],
+            PixelStatus::White
+        );
+
+        emulator.quirks.wrap_sprites = true;
+        emulator.process_next_instruction();
+        assert_eq!(emulator.screen[last_line], PixelStatus::White);
+        assert_eq!(emulator.screen[last_line + 3], PixelStatus::White);
+        assert_eq!(emulator.screen[last_line + 4], PixelStatus::Black);
+        assert_eq!(
+            emulator.screen[last_line + CHIP8_SCREEN_WIDTH - 1],
+            PixelStatus::Black
+        );
+    }
 }
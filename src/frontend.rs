@@ -0,0 +1,274 @@
+use crate::cheats::CheatEngine;
+use crate::coverage::Coverage;
+use crate::emulator;
+use crate::profiler::Profiler;
+use crate::rom_database::{Palette, RomDatabase};
+use crate::rom_loader;
+use crate::rpl_flags::{DirectoryStore, RplFlags};
+use crate::scripting::Script;
+use crate::speed_control::FrameBudget;
+use crate::symbols::Symbols;
+
+use emulator::Quirks;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const INSTRUCTIONS_PER_FRAME: u32 = 20;
+pub const FRAME_DURATION: Duration = Duration::new(0, 1_000_000_000u32 / 60);
+pub const ROM_WATCH_PERIOD_IN_FRAMES: u32 = 30;
+
+pub struct Options {
+    pub rom_path: PathBuf,
+    // Restart the emulator whenever the ROM file changes on disk
+    pub watch_rom: bool,
+    // User overrides of the settings found in the ROM database
+    pub tick_rate: Option<u32>,
+    pub quirks: Option<Quirks>,
+    pub palette: Option<Palette>,
+    // Additional ROM database file, on top of the bundled one
+    pub database_path: Option<PathBuf>,
+    // Where SUPER-CHIP RPL user flags are persisted, and whether only when leaving a ROM
+    pub rpl_flags_directory: Option<PathBuf>,
+    pub save_flags_on_exit: bool,
+    // File where the profiler report is written at exit and on demand, enables the profiler
+    pub profile_path: Option<PathBuf>,
+    // File where the coverage report is written at exit, enables coverage recording
+    pub coverage_path: Option<PathBuf>,
+    // Run in the terminal instead of a window, drawing pixels with braille dots or half blocks
+    pub terminal: bool,
+    pub braille: bool,
+    // Print every instruction run, with the names of addresses shown in traces and the debugger
+    pub trace: bool,
+    pub symbols: Symbols,
+    // Rhai script driving the emulator, loaded once the ROM is
+    pub script_path: Option<PathBuf>,
+}
+
+// Settings of the running ROM, from the ROM database and the user overrides
+pub struct RomSettings {
+    pub title: String,
+    pub instructions_per_frame: u32,
+    pub palette: Palette,
+    pub key_hints: Option<String>,
+    pub patch_path: Option<PathBuf>,
+    pub program_size: usize,
+}
+
+// Emulator with the instrumentation asked for in the options
+pub fn new_emulator(options: &Options) -> emulator::Emulator {
+    let mut emulator = emulator::Emulator::new();
+    emulator.trace = options.trace;
+    emulator.symbols = options.symbols.clone();
+    if options.profile_path.is_some() {
+        emulator.profiler = Some(Profiler::new());
+    }
+    if options.coverage_path.is_some() {
+        emulator.coverage = Some(Coverage::new());
+    }
+    emulator
+}
+
+pub fn load_script(
+    emulator: &mut emulator::Emulator,
+    options: &Options,
+) -> Result<Option<Script>, String> {
+    options
+        .script_path
+        .as_ref()
+        .map(|script_path| Script::load(script_path, emulator))
+        .transpose()
+}
+
+// Bundled database, with the entries of the database file of the options
+pub fn open_rom_database(options: &Options) -> Result<RomDatabase, String> {
+    let mut rom_database = RomDatabase::bundled();
+    if let Some(database_path) = &options.database_path {
+        rom_database
+            .merge_file(database_path)
+            .map_err(|error| format!("{}: {}", database_path.display(), error))?;
+    }
+    Ok(rom_database)
+}
+
+pub fn open_rpl_flags(options: &Options) -> RplFlags {
+    let rpl_flags_directory = options
+        .rpl_flags_directory
+        .clone()
+        .unwrap_or_else(DirectoryStore::default_directory);
+    RplFlags::new(
+        Box::new(DirectoryStore::new(rpl_flags_directory)),
+        !options.save_flags_on_exit,
+    )
+}
+
+// Emulator with the ROM loaded and its quirks from the ROM file or database, for commands
+// running without window. Also returns the program and the number of instructions per frame.
+pub fn load_headless(
+    rom_path: &Path,
+    tick_rate: Option<u32>,
+) -> Result<(emulator::Emulator, Vec<u8>, u32), String> {
+    let rom = rom_loader::load_rom(rom_path).map_err(|error| error.to_string())?;
+    let database = RomDatabase::bundled();
+    let metadata = database.lookup(&rom.program);
+    let mut emulator = emulator::Emulator::new();
+    emulator.quirks = rom
+        .options
+        .quirks
+        .or(metadata.map(|metadata| metadata.quirks))
+        .unwrap_or_default();
+    emulator.load_program(&rom.program);
+    let instructions_per_frame = tick_rate
+        .or(rom.options.tick_rate)
+        .or(metadata.and_then(|metadata| metadata.tick_rate))
+        .unwrap_or(INSTRUCTIONS_PER_FRAME);
+    Ok((emulator, rom.program, instructions_per_frame))
+}
+
+// Resets the emulator, then loads the ROM from disk again with its settings.
+// User overrides come first, then the options embedded in the ROM file, then the database.
+// The RPL user flags of the previous ROM are saved, and those of the new one restored.
+// Cheats are loaded from the cheat file of the ROM, and its patch codes applied.
+pub fn reload_rom(
+    emulator: &mut emulator::Emulator,
+    rom_path: &Path,
+    database: &RomDatabase,
+    rpl_flags: &mut RplFlags,
+    cheats: &mut CheatEngine,
+    options: &Options,
+) -> std::io::Result<RomSettings> {
+    let rom = rom_loader::load_rom(rom_path)?;
+    let metadata = database.lookup(&rom.program);
+    rpl_flags.save(emulator)?;
+    emulator.reset();
+    emulator.quirks = options
+        .quirks
+        .or(rom.options.quirks)
+        .or(metadata.map(|metadata| metadata.quirks))
+        .unwrap_or_default();
+    emulator.load_program(rom.program.as_slice());
+    rpl_flags.restore(emulator, &rom.program)?;
+    cheats.load_for_rom(rom_path)?;
+    cheats.apply_patches(emulator);
+
+    let title = match metadata {
+        Some(metadata) => metadata.title.clone(),
+        None => rom_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    };
+    Ok(RomSettings {
+        title,
+        instructions_per_frame: options
+            .tick_rate
+            .or(rom.options.tick_rate)
+            .or(metadata.and_then(|metadata| metadata.tick_rate))
+            .unwrap_or(INSTRUCTIONS_PER_FRAME),
+        palette: options
+            .palette
+            .or(rom.options.palette)
+            .or(metadata.and_then(|metadata| metadata.palette))
+            .unwrap_or_default(),
+        key_hints: metadata.and_then(|metadata| metadata.key_hints.clone()),
+        patch_path: rom.patch_path,
+        program_size: rom.program.len(),
+    })
+}
+
+// Profiler tables followed by the disassembly of the program annotated with hit counts
+pub fn write_profile(
+    emulator: &emulator::Emulator,
+    settings: &RomSettings,
+    path: &Path,
+) -> std::io::Result<()> {
+    let Some(profiler) = &emulator.profiler else {
+        return Ok(());
+    };
+    let program_start = emulator::CHIP8_FIRST_BYTE_ADDRESS;
+    let report = format!(
+        "Profile of {}\n\n{}\nAnnotated disassembly\n{}",
+        settings.title,
+        profiler.report(emulator.memory()),
+        profiler.annotated_disassembly(
+            emulator.memory(),
+            program_start,
+            program_start + settings.program_size
+        )
+    );
+    std::fs::write(path, report)
+}
+
+// Frozen cheats are written before every emulated frame, which runs the callbacks of the script
+pub fn run_emulated_frame(
+    emulator: &mut emulator::Emulator,
+    cheats: &CheatEngine,
+    script: &mut Option<Script>,
+    instructions_per_frame: u32,
+) -> u32 {
+    cheats.apply_freezes(emulator);
+    match script {
+        Some(script) => script.run_frame(emulator, instructions_per_frame),
+        None => emulator.run_frame(instructions_per_frame),
+    }
+}
+
+// Runs the emulated frames of one real frame started at frame_start, returns the number of
+// instructions executed
+pub fn run_frame_budget(
+    emulator: &mut emulator::Emulator,
+    cheats: &CheatEngine,
+    script: &mut Option<Script>,
+    frame_budget: FrameBudget,
+    instructions_per_frame: u32,
+    frame_start: Instant,
+) -> u32 {
+    let mut instructions_executed = 0;
+    match frame_budget {
+        FrameBudget::Frames(frames) => {
+            for _ in 0..frames {
+                instructions_executed +=
+                    run_emulated_frame(emulator, cheats, script, instructions_per_frame);
+            }
+        }
+        FrameBudget::Uncapped => {
+            // Keep some time of the real frame for events and rendering
+            while frame_start.elapsed() < FRAME_DURATION * 3 / 4 {
+                instructions_executed +=
+                    run_emulated_frame(emulator, cheats, script, instructions_per_frame);
+            }
+        }
+    }
+    instructions_executed
+}
+
+// RPL user flags, profile and coverage report, written when the frontend exits
+pub fn save_at_exit(
+    emulator: &mut emulator::Emulator,
+    rpl_flags: &mut RplFlags,
+    settings: &RomSettings,
+    options: &Options,
+) {
+    if let Err(error) = rpl_flags.save(emulator) {
+        eprintln!("Cannot save RPL user flags: {}", error);
+    }
+    if let Some(profile_path) = &options.profile_path {
+        if let Err(error) = write_profile(emulator, settings, profile_path) {
+            eprintln!("Cannot write profile: {}", error);
+        }
+    }
+    if let (Some(coverage), Some(coverage_path)) = (&emulator.coverage, &options.coverage_path) {
+        let program_start = emulator::CHIP8_FIRST_BYTE_ADDRESS;
+        let program_end = program_start + settings.program_size;
+        let report = coverage.report(
+            emulator.memory(),
+            program_start,
+            program_end,
+            &settings.title,
+            coverage_path,
+        );
+        if let Err(error) = std::fs::write(coverage_path, report) {
+            eprintln!("Cannot write coverage report: {}", error);
+        }
+    }
+}
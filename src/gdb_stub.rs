@@ -0,0 +1,594 @@
+use crate::debugger::{Debugger, StopReason, WatchKind, Watchpoint};
+use crate::emulator::{Emulator, CHIP8_MEMORY_SIZE, CHIP8_NUMBER_REGISTERS};
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+const FRAME_DURATION: Duration = Duration::new(0, 1_000_000_000u32 / 60);
+const INTERRUPT: u8 = 0x03;
+// Stop signals reported to the debugger
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+// Registers as numbered by the debugger: V0 to VF, then I, PC, SP, DT and ST
+const REGISTER_I: usize = CHIP8_NUMBER_REGISTERS;
+const REGISTER_PC: usize = REGISTER_I + 1;
+const REGISTER_SP: usize = REGISTER_I + 2;
+const REGISTER_DT: usize = REGISTER_I + 3;
+const REGISTER_ST: usize = REGISTER_I + 4;
+const REGISTER_COUNT: usize = REGISTER_I + 5;
+
+// Describes the registers, as there is no CHIP8 architecture known to GDB
+const TARGET_XML: &str = "<?xml version=\"1.0\"?>\
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+<target version=\"1.0\"><feature name=\"org.chip8.core\">\
+<reg name=\"v0\" bitsize=\"8\" regnum=\"0\"/><reg name=\"v1\" bitsize=\"8\"/>\
+<reg name=\"v2\" bitsize=\"8\"/><reg name=\"v3\" bitsize=\"8\"/>\
+<reg name=\"v4\" bitsize=\"8\"/><reg name=\"v5\" bitsize=\"8\"/>\
+<reg name=\"v6\" bitsize=\"8\"/><reg name=\"v7\" bitsize=\"8\"/>\
+<reg name=\"v8\" bitsize=\"8\"/><reg name=\"v9\" bitsize=\"8\"/>\
+<reg name=\"va\" bitsize=\"8\"/><reg name=\"vb\" bitsize=\"8\"/>\
+<reg name=\"vc\" bitsize=\"8\"/><reg name=\"vd\" bitsize=\"8\"/>\
+<reg name=\"ve\" bitsize=\"8\"/><reg name=\"vf\" bitsize=\"8\"/>\
+<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\
+<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\
+<reg name=\"sp\" bitsize=\"8\"/><reg name=\"dt\" bitsize=\"8\"/><reg name=\"st\" bitsize=\"8\"/>\
+</feature></target>";
+
+enum Packet {
+    Command(String),
+    // Ctrl-C sent by the debugger while the program runs
+    Interrupt,
+}
+
+// GDB remote serial protocol server debugging a single emulator
+pub struct GdbStub {
+    pub emulator: Emulator,
+    debugger: Debugger,
+}
+
+impl GdbStub {
+    // Traces would flood the output of the stub while the program runs
+    pub fn new(mut emulator: Emulator, instructions_per_frame: u32) -> Self {
+        emulator.trace = false;
+        Self {
+            emulator,
+            debugger: Debugger::new(instructions_per_frame),
+        }
+    }
+
+    // Serves debugger connections one after the other, until one kills the program
+    pub fn serve(&mut self, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            if !self.serve_connection(stream?)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns false once the debugger killed the program
+    fn serve_connection(&mut self, mut stream: TcpStream) -> io::Result<bool> {
+        stream.set_nodelay(true)?;
+        loop {
+            let command = match read_packet(&mut stream)? {
+                Some(Packet::Command(command)) => command,
+                Some(Packet::Interrupt) => {
+                    write_packet(&mut stream, &format!("S{:02x}", SIGINT))?;
+                    continue;
+                }
+                None => return Ok(true),
+            };
+            let reply = match command.as_bytes().first() {
+                Some(b'c') => match self.jump_to(&command[1..]) {
+                    Ok(()) => self.continue_execution(&mut stream)?,
+                    Err(error) => error.to_string(),
+                },
+                Some(b's') => match self.jump_to(&command[1..]) {
+                    Ok(()) => stop_reply(self.debugger.step(&mut self.emulator)),
+                    Err(error) => error.to_string(),
+                },
+                Some(b'b') => match &command[1..] {
+                    "s" => stop_reply(self.debugger.step_back(&mut self.emulator)),
+                    "c" => stop_reply(self.debugger.reverse_continue(&mut self.emulator)),
+                    _ => String::new(),
+                },
+                Some(b'k') => return Ok(false),
+                Some(b'D') => {
+                    write_packet(&mut stream, "OK")?;
+                    return Ok(true);
+                }
+                _ => self.handle_command(&command),
+            };
+            write_packet(&mut stream, &reply)?;
+        }
+    }
+
+    // Optional address to resume at, as in "c200" or "s200"
+    fn jump_to(&mut self, address: &str) -> Result<(), ProtocolError> {
+        if address.is_empty() {
+            return Ok(());
+        }
+        self.emulator
+            .set_program_counter(parse_program_counter(parse_hex(address)?)?);
+        self.debugger.forget_history();
+        Ok(())
+    }
+
+    // Runs frame by frame at the normal speed, so timers behave, until something stops it
+    fn continue_execution(&mut self, stream: &mut TcpStream) -> io::Result<String> {
+        loop {
+            let frame_start = Instant::now();
+            if let Some(reason) = self.debugger.run_frame(&mut self.emulator) {
+                return Ok(stop_reply(reason));
+            }
+            if interrupt_requested(stream)? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+            std::thread::sleep(FRAME_DURATION.saturating_sub(frame_start.elapsed()));
+        }
+    }
+
+    // Commands that neither run the program nor end the connection
+    fn handle_command(&mut self, command: &str) -> String {
+        let (name, arguments) = command.split_at(1.min(command.len()));
+        let result = match name {
+            "?" => Ok(format!("S{:02x}", SIGTRAP)),
+            "g" => Ok((0..REGISTER_COUNT)
+                .map(|register| self.read_register(register))
+                .collect()),
+            "G" => self.write_registers(arguments),
+            "p" => parse_hex(arguments).map(|register| self.read_register(register)),
+            "P" => self.write_register(arguments),
+            "m" => self.read_memory(arguments),
+            "M" => self.write_memory(arguments),
+            "Z" => self.set_stop_point(arguments, true),
+            "z" => self.set_stop_point(arguments, false),
+            "H" => Ok("OK".to_string()),
+            "q" => Ok(self.query(arguments)),
+            // Anything else is reported as unsupported
+            _ => Ok(String::new()),
+        };
+        result.unwrap_or_else(|error| error.to_string())
+    }
+
+    fn query(&self, query: &str) -> String {
+        match query {
+            _ if query.starts_with("Supported") => {
+                "PacketSize=1000;qXfer:features:read+;ReverseStep+;ReverseContinue+".to_string()
+            }
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => match query.strip_prefix("Xfer:features:read:target.xml:") {
+                Some(range) => {
+                    xfer_chunk(TARGET_XML, range).unwrap_or_else(|error| error.to_string())
+                }
+                None => String::new(),
+            },
+        }
+    }
+
+    // Little-endian hexadecimal, with the size of the register
+    fn read_register(&self, register: usize) -> String {
+        let emulator = &self.emulator;
+        match register {
+            _ if register < CHIP8_NUMBER_REGISTERS => {
+                format!("{:02x}", emulator.registers()[register])
+            }
+            REGISTER_I => hex_u16(emulator.index_register() as u16),
+            REGISTER_PC => hex_u16(emulator.program_counter() as u16),
+            REGISTER_SP => format!("{:02x}", emulator.stack_pointer()),
+            REGISTER_DT => format!("{:02x}", emulator.system_clock),
+            REGISTER_ST => format!("{:02x}", emulator.sound_clock),
+            _ => "xx".to_string(),
+        }
+    }
+
+    fn set_register(&mut self, register: usize, bytes: &[u8]) -> Result<(), ProtocolError> {
+        let value = bytes
+            .iter()
+            .rev()
+            .fold(0usize, |value, byte| (value << 8) | *byte as usize);
+        match register {
+            _ if register < CHIP8_NUMBER_REGISTERS => {
+                self.emulator.set_register(register, value as u8)
+            }
+            REGISTER_I if value < CHIP8_MEMORY_SIZE => self.emulator.set_index_register(value),
+            REGISTER_PC => self
+                .emulator
+                .set_program_counter(parse_program_counter(value)?),
+            // The call stack is managed by the program only
+            REGISTER_SP => (),
+            REGISTER_DT => self.emulator.system_clock = value as u8,
+            REGISTER_ST => self.emulator.sound_clock = value as u8,
+            _ => return Err(ProtocolError::InvalidArgument),
+        }
+        Ok(())
+    }
+
+    fn write_registers(&mut self, values: &str) -> Result<String, ProtocolError> {
+        let bytes = decode_hex(values)?;
+        // Registers are all written or none is
+        let snapshot = self.emulator.snapshot();
+        let mut offset = 0;
+        for register in 0..REGISTER_COUNT {
+            let size = if register == REGISTER_I || register == REGISTER_PC {
+                2
+            } else {
+                1
+            };
+            let written = bytes
+                .get(offset..offset + size)
+                .ok_or(ProtocolError::InvalidArgument)
+                .and_then(|value| self.set_register(register, value));
+            if let Err(error) = written {
+                self.emulator.restore(&snapshot);
+                return Err(error);
+            }
+            offset += size;
+        }
+        self.debugger.forget_history();
+        Ok("OK".to_string())
+    }
+
+    // "P" packets such as "P11=0002" to set PC to 0x200
+    fn write_register(&mut self, arguments: &str) -> Result<String, ProtocolError> {
+        let (register, value) = arguments
+            .split_once('=')
+            .ok_or(ProtocolError::InvalidArgument)?;
+        self.set_register(parse_hex(register)?, &decode_hex(value)?)?;
+        self.debugger.forget_history();
+        Ok("OK".to_string())
+    }
+
+    fn read_memory(&self, arguments: &str) -> Result<String, ProtocolError> {
+        let (address, length) = parse_range(arguments)?;
+        Ok(self.emulator.memory()[address..address + length]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect())
+    }
+
+    // "M" packets such as "M300,2:abcd"
+    fn write_memory(&mut self, arguments: &str) -> Result<String, ProtocolError> {
+        let (range, data) = arguments
+            .split_once(':')
+            .ok_or(ProtocolError::InvalidArgument)?;
+        let (address, length) = parse_range(range)?;
+        let bytes = decode_hex(data)?;
+        if bytes.len() != length {
+            return Err(ProtocolError::InvalidArgument);
+        }
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.emulator.write_memory(address + offset, *byte);
+        }
+        self.debugger.forget_history();
+        Ok("OK".to_string())
+    }
+
+    // "Z" and "z" packets such as "Z0,204,2" for a breakpoint or "Z2,300,1" for a watchpoint
+    fn set_stop_point(&mut self, arguments: &str, insert: bool) -> Result<String, ProtocolError> {
+        let mut fields = arguments.splitn(2, ',');
+        let kind = fields.next().unwrap_or_default();
+        let (address, length) = parse_range(fields.next().unwrap_or_default())?;
+        let watch_kind = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.debugger.breakpoints.insert(address);
+                } else {
+                    self.debugger.breakpoints.remove(&address);
+                }
+                return Ok("OK".to_string());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Ok(String::new()),
+        };
+        let watchpoint = Watchpoint {
+            kind: watch_kind,
+            address,
+            length,
+        };
+        if insert {
+            self.emulator.watchpoints.push(watchpoint);
+        } else {
+            self.emulator
+                .watchpoints
+                .retain(|existing| *existing != watchpoint);
+        }
+        Ok("OK".to_string())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ProtocolError {
+    InvalidArgument,
+    OutOfMemory,
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProtocolError::InvalidArgument => write!(formatter, "E01"),
+            ProtocolError::OutOfMemory => write!(formatter, "E02"),
+        }
+    }
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Step | StopReason::Breakpoint(_) => format!("S{:02x}", SIGTRAP),
+        StopReason::Watchpoint(hit) => {
+            let name = match hit.kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, name, hit.address)
+        }
+        StopReason::HistoryStart => format!("T{:02x}replaylog:begin;", SIGTRAP),
+    }
+}
+
+fn hex_u16(value: u16) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Result<usize, ProtocolError> {
+    usize::from_str_radix(text, 16).map_err(|_| ProtocolError::InvalidArgument)
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>, ProtocolError> {
+    if !text.len().is_multiple_of(2) {
+        return Err(ProtocolError::InvalidArgument);
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| {
+            text.get(index..index + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or(ProtocolError::InvalidArgument)
+        })
+        .collect()
+}
+
+// "ADDRESS,LENGTH" in hexadecimal, which must lie in memory
+fn parse_range(text: &str) -> Result<(usize, usize), ProtocolError> {
+    let (address, length) = text.split_once(',').ok_or(ProtocolError::InvalidArgument)?;
+    let (address, length) = (parse_hex(address)?, parse_hex(length)?);
+    match address.checked_add(length) {
+        Some(end) if end <= CHIP8_MEMORY_SIZE => Ok((address, length)),
+        _ => Err(ProtocolError::OutOfMemory),
+    }
+}
+
+// Instructions are two bytes, which must both lie in memory
+fn parse_program_counter(address: usize) -> Result<usize, ProtocolError> {
+    if address >= CHIP8_MEMORY_SIZE - 1 {
+        return Err(ProtocolError::InvalidArgument);
+    }
+    Ok(address)
+}
+
+// "OFFSET,LENGTH" part of a qXfer read, "m" is followed by more data and "l" ends it
+fn xfer_chunk(document: &str, range: &str) -> Result<String, ProtocolError> {
+    let (offset, length) = range
+        .split_once(',')
+        .ok_or(ProtocolError::InvalidArgument)?;
+    let (offset, length) = (parse_hex(offset)?, parse_hex(length)?);
+    let chunk = document
+        .get(offset.min(document.len())..)
+        .unwrap_or_default();
+    if chunk.len() > length {
+        Ok(format!("m{}", &chunk[..length]))
+    } else {
+        Ok(format!("l{}", chunk))
+    }
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte))
+}
+
+fn write_packet(stream: &mut TcpStream, data: &str) -> io::Result<()> {
+    write!(stream, "${}#{:02x}", data, checksum(data))?;
+    stream.flush()
+}
+
+fn read_byte(stream: &mut TcpStream) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+// Reads the next packet and acknowledges it, None once the debugger disconnected
+fn read_packet(stream: &mut TcpStream) -> io::Result<Option<Packet>> {
+    loop {
+        match read_byte(stream)? {
+            None => return Ok(None),
+            Some(INTERRUPT) => return Ok(Some(Packet::Interrupt)),
+            Some(b'$') => (),
+            // Acknowledgements and noise between packets
+            Some(_) => continue,
+        }
+        let mut data = Vec::new();
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+        let mut sent_checksum = [0; 2];
+        stream.read_exact(&mut sent_checksum)?;
+        let data = String::from_utf8_lossy(&data).into_owned();
+        let valid = std::str::from_utf8(&sent_checksum)
+            .ok()
+            .and_then(|text| u8::from_str_radix(text, 16).ok())
+            == Some(checksum(&data));
+        if valid {
+            stream.write_all(b"+")?;
+            return Ok(Some(Packet::Command(data)));
+        }
+        stream.write_all(b"-")?;
+    }
+}
+
+// Checks, without waiting, whether the debugger sent Ctrl-C
+fn interrupt_requested(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0];
+    let result = match stream.read(&mut byte) {
+        Ok(1) => Ok(byte[0] == INTERRUPT),
+        Ok(_) => Ok(false),
+        Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(error) => Err(error),
+    };
+    stream.set_nonblocking(false)?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    // Counts V0 up in a loop, storing it at 0x300 every iteration
+    const PROGRAM: [u8; 8] = [0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00];
+
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn connect(address: SocketAddr) -> Self {
+            Self {
+                stream: TcpStream::connect(address).unwrap(),
+            }
+        }
+
+        // Sends a packet, checks the acknowledgement and returns the reply
+        fn request(&mut self, data: &str) -> String {
+            write_packet(&mut self.stream, data).unwrap();
+            let mut acknowledgement = [0];
+            self.stream.read_exact(&mut acknowledgement).unwrap();
+            assert_eq!(acknowledgement[0], b'+');
+            match read_packet(&mut self.stream).unwrap() {
+                Some(Packet::Command(reply)) => reply,
+                _ => panic!("No reply to {}", data),
+            }
+        }
+
+        // The kill packet has no reply
+        fn kill(&mut self) {
+            write_packet(&mut self.stream, "k").unwrap();
+            let mut acknowledgement = [0];
+            self.stream.read_exact(&mut acknowledgement).unwrap();
+        }
+    }
+
+    fn start_server() -> (SocketAddr, std::thread::JoinHandle<Emulator>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut emulator = Emulator::new();
+            emulator.load_program(&PROGRAM);
+            let mut stub = GdbStub::new(emulator, 20);
+            stub.serve(listener).unwrap();
+            stub.emulator
+        });
+        (address, server)
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let (address, server) = start_server();
+        let mut client = Client::connect(address);
+        assert!(client
+            .request("qSupported:xmlRegisters=i386")
+            .contains("qXfer"));
+        assert_eq!(client.request("?"), "S05");
+        // V0 to VF, I, PC = 0x200 in little-endian, SP, DT and ST
+        assert_eq!(
+            client.request("g"),
+            format!("{}0000{}000000", "00".repeat(16), "0002")
+        );
+        assert_eq!(client.request("m200,4"), "7001a300");
+        assert_eq!(client.request("M300,2:abcd"), "OK");
+        assert_eq!(client.request("m300,2"), "abcd");
+        assert_eq!(client.request("mfff,2"), "E02");
+        assert_eq!(client.request("mffffffffffffffff,1"), "E02");
+        assert_eq!(client.request("P11=ffff"), "E01");
+        assert_eq!(client.request("P10=0010"), "E01");
+        assert_eq!(client.request("cffff"), "E01");
+        assert_eq!(client.request("sfff"), "E01");
+        assert_eq!(
+            client.request(&format!("G{}0000{}000000", "01".repeat(16), "ffff")),
+            "E01"
+        );
+        assert_eq!(client.request("p0"), "00");
+        assert_eq!(client.request("P5=2a"), "OK");
+        assert_eq!(client.request("p5"), "2a");
+        assert_eq!(client.request("P10=0003"), "OK");
+        assert_eq!(client.request("p10"), "0003");
+        assert!(client
+            .request("qXfer:features:read:target.xml:0,20")
+            .starts_with("m<?xml"));
+        client.kill();
+        let emulator = server.join().unwrap();
+        assert_eq!(emulator.registers()[5], 0x2A);
+        assert_eq!(emulator.index_register(), 0x300);
+        assert!(!emulator.trace);
+    }
+
+    #[test]
+    fn test_breakpoints_watchpoints_and_steps() {
+        let (address, server) = start_server();
+        let mut client = Client::connect(address);
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p11"), "0202");
+        assert_eq!(client.request("Z0,206,2"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p11"), "0602");
+        assert_eq!(client.request("z0,206,2"), "OK");
+        assert_eq!(client.request("Z2,300,1"), "OK");
+        assert_eq!(client.request("c"), "T05watch:300;");
+        assert_eq!(client.request("m300,1"), "02");
+        assert_eq!(client.request("p11"), "0602");
+        client.kill();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_reverse_execution() {
+        let (address, server) = start_server();
+        let mut client = Client::connect(address);
+        assert!(client.request("qSupported").contains("ReverseContinue+"));
+        assert_eq!(client.request("Z2,300,1"), "OK");
+        assert_eq!(client.request("c"), "T05watch:300;");
+        assert_eq!(client.request("c"), "T05watch:300;");
+        assert_eq!(client.request("m300,1"), "02");
+        // Back on the instruction that wrote the value, then on the one before
+        assert_eq!(client.request("bc"), "T05watch:300;");
+        assert_eq!(client.request("p11"), "0402");
+        assert_eq!(client.request("m300,1"), "01");
+        assert_eq!(client.request("bs"), "S05");
+        assert_eq!(client.request("p11"), "0202");
+        assert_eq!(client.request("bc"), "T05watch:300;");
+        assert_eq!(client.request("m300,1"), "00");
+        assert_eq!(client.request("bc"), "T05replaylog:begin;");
+        assert_eq!(client.request("p11"), "0002");
+        client.kill();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+use crate::emulator::{
+    Emulator, PixelStatus, Snapshot, CHIP8_NUMBER_KEYS, CHIP8_SCREEN_HEIGHT, CHIP8_SCREEN_WIDTH,
+};
+use crate::frontend::load_headless;
+
+use std::path::Path;
+use std::sync::Arc;
+
+// Frames run by every step, the agent only sees the screen of the last one
+pub const DEFAULT_FRAME_SKIP: u32 = 4;
+
+pub type Observation = [PixelStatus; CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT];
+// Reward of a step from the memory before and after it, such as the increase of the score
+pub type RewardFunction = Arc<dyn Fn(&[u8], &[u8]) -> f64 + Send + Sync>;
+// Whether the episode is over from the memory, such as when no lives are left
+pub type DoneFunction = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+pub struct Step {
+    pub observation: Observation,
+    pub reward: f64,
+    pub done: bool,
+}
+
+// Everything needed to come back to a point of an episode, in the same environment or a clone
+#[derive(Clone)]
+pub struct EnvState {
+    snapshot: Snapshot,
+    frames: u64,
+}
+
+// Gym-style environment to train agents on a ROM. Actions are masks of the keys held during
+// the step, bit N for key N. Environments are Send, each one runs in its own thread.
+pub struct Env {
+    emulator: Emulator,
+    initial: Snapshot,
+    frames: u64,
+    pub instructions_per_frame: u32,
+    pub frame_skip: u32,
+    // Episodes end after that many frames when set, on top of the done function
+    pub max_frames: Option<u64>,
+    pub reward: RewardFunction,
+    pub done: DoneFunction,
+}
+
+impl Env {
+    // The emulator has its program loaded and its quirks set, episodes start from its state.
+    // There is no reward and episodes never end until the functions are set.
+    pub fn new(mut emulator: Emulator, instructions_per_frame: u32) -> Self {
+        emulator.trace = false;
+        Self {
+            initial: emulator.snapshot(),
+            emulator,
+            frames: 0,
+            instructions_per_frame,
+            frame_skip: DEFAULT_FRAME_SKIP,
+            max_frames: None,
+            reward: Arc::new(|_, _| 0.0),
+            done: Arc::new(|_| false),
+        }
+    }
+
+    // ROM with its quirks and speed from the ROM file or database
+    pub fn load(rom_path: &Path) -> Result<Self, String> {
+        let (emulator, _, instructions_per_frame) = load_headless(rom_path, None)?;
+        Ok(Self::new(emulator, instructions_per_frame))
+    }
+
+    // Starts a new episode. Random numbers come from the seed, or are different every time.
+    pub fn reset(&mut self, seed: Option<u64>) -> Observation {
+        self.emulator.restore(&self.initial);
+        self.emulator.seed_rng(seed.unwrap_or_else(rand::random));
+        self.frames = 0;
+        self.observation()
+    }
+
+    // Holds the keys of the action for frame_skip frames
+    pub fn step(&mut self, action: u16) -> Step {
+        for key in 0..CHIP8_NUMBER_KEYS as u8 {
+            let pressed = action & (1 << key) != 0;
+            if self.emulator.key_pressed(key) != pressed {
+                self.emulator.input_key(key, pressed);
+            }
+        }
+        let previous_memory = *self.emulator.memory();
+        for _ in 0..self.frame_skip {
+            self.emulator.run_frame(self.instructions_per_frame);
+        }
+        self.frames += self.frame_skip as u64;
+
+        let memory = self.emulator.memory();
+        Step {
+            observation: self.observation(),
+            reward: (self.reward)(&previous_memory, memory),
+            done: (self.done)(memory)
+                || self
+                    .max_frames
+                    .is_some_and(|max_frames| self.frames >= max_frames),
+        }
+    }
+
+    pub fn observation(&self) -> Observation {
+        self.emulator.screen
+    }
+
+    // Frames run since the start of the episode
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn state(&self) -> EnvState {
+        EnvState {
+            snapshot: self.emulator.snapshot(),
+            frames: self.frames,
+        }
+    }
+
+    pub fn restore(&mut self, state: &EnvState) {
+        self.emulator.restore(&state.snapshot);
+        self.frames = state.frames;
+    }
+}
+
+// Same episode at the same point, which then goes on independently
+impl Clone for Env {
+    fn clone(&self) -> Self {
+        let mut emulator = Emulator::new();
+        emulator.quirks = self.emulator.quirks;
+        emulator.trace = false;
+        emulator.restore(&self.emulator.snapshot());
+        Self {
+            emulator,
+            initial: self.initial.clone(),
+            frames: self.frames,
+            instructions_per_frame: self.instructions_per_frame,
+            frame_skip: self.frame_skip,
+            max_frames: self.max_frames,
+            reward: Arc::clone(&self.reward),
+            done: Arc::clone(&self.done),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_5: u16 = 1 << 5;
+
+    // Counts at 0x300 the loops run while key 5 is held, with a random number in V2:
+    // LD I, 0x300; LD V1, 5; SKNP V1; ADD V0, 1; LD [I], V0; RND V2, 0xFF; JP 0x202
+    const PROGRAM: [u8; 14] = [
+        0xA3, 0x00, 0x61, 0x05, 0xE1, 0xA1, 0x70, 0x01, 0xF0, 0x55, 0xC2, 0xFF, 0x12, 0x02,
+    ];
+
+    fn env() -> Env {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&PROGRAM);
+        let mut env = Env::new(emulator, 12);
+        env.frame_skip = 2;
+        env.reward = Arc::new(|previous, memory| memory[0x300] as f64 - previous[0x300] as f64);
+        env.done = Arc::new(|memory| memory[0x300] >= 10);
+        env
+    }
+
+    #[test]
+    fn test_step() {
+        let mut env = env();
+        let observation = env.reset(Some(1));
+        assert!(observation.iter().all(|pixel| *pixel == PixelStatus::Black));
+
+        let step = env.step(0);
+        assert_eq!(step.reward, 0.0);
+        assert!(!step.done);
+        assert_eq!(env.frames(), 2);
+
+        let step = env.step(KEY_5);
+        assert_eq!(step.reward, 4.0);
+        assert!(env.emulator().key_pressed(5));
+        let step = env.step(KEY_5);
+        assert_eq!(step.reward, 4.0);
+        assert!(!step.done);
+        assert!(env.step(KEY_5).done);
+
+        env.max_frames = Some(4);
+        env.reset(Some(1));
+        assert!(!env.step(0).done);
+        assert!(env.step(0).done);
+    }
+
+    #[test]
+    fn test_state_and_clone() {
+        let mut env = env();
+        env.reset(Some(2));
+        env.step(KEY_5);
+        let state = env.state();
+        let mut clone = env.clone();
+
+        let registers = {
+            env.step(KEY_5);
+            *env.emulator().registers()
+        };
+        clone.step(KEY_5);
+        assert_eq!(*clone.emulator().registers(), registers);
+
+        env.step(0);
+        env.restore(&state);
+        assert_eq!(env.frames(), 2);
+        env.step(KEY_5);
+        assert_eq!(*env.emulator().registers(), registers);
+    }
+
+    #[test]
+    fn test_parallel_environments() {
+        let env = env();
+        let rewards: Vec<Vec<f64>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    let mut env = env.clone();
+                    scope.spawn(move || {
+                        env.reset(Some(3));
+                        (0..8)
+                            .map(|step| env.step(if step % 2 == 0 { KEY_5 } else { 0 }).reward)
+                            .collect()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect()
+        });
+        assert_eq!(rewards[0], [4.0, 0.0, 4.0, 0.0, 3.0, 1.0, 4.0, 0.0]);
+        assert!(rewards
+            .iter()
+            .all(|worker_rewards| *worker_rewards == rewards[0]));
+    }
+}
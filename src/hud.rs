@@ -0,0 +1,214 @@
+extern crate sdl2;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+use std::time::{Duration, Instant};
+
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+const STATISTICS_PERIOD: Duration = Duration::from_millis(500);
+const MESSAGE_DURATION: Duration = Duration::from_secs(3);
+
+// Built-in 3x5 font, one byte per row with the 3 lowest bits used.
+// Lowercase letters are drawn as uppercase, unknown characters as '?'.
+fn glyph(character: char) -> [u8; GLYPH_HEIGHT as usize] {
+    match character.to_ascii_uppercase() {
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        ';' => [0b000, 0b010, 0b000, 0b010, 0b100],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '*' => [0b101, 0b010, 0b101, 0b000, 0b000],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '"' => [0b101, 0b101, 0b000, 0b000, 0b000],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+// Draws text with its top-left corner at (x, y), each font pixel being a square of size scale
+pub fn draw_text(canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, scale: u32) {
+    let mut pixels = Vec::new();
+    for (index, character) in text.chars().enumerate() {
+        let glyph_x = x + (index as u32 * (GLYPH_WIDTH + 1) * scale) as i32;
+        for (row, bits) in glyph(character).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                    pixels.push(Rect::new(
+                        glyph_x + (column * scale) as i32,
+                        y + (row as u32 * scale) as i32,
+                        scale,
+                        scale,
+                    ));
+                }
+            }
+        }
+    }
+    canvas.fill_rects(&pixels).unwrap();
+}
+
+// Size in pixels of a text drawn with draw_text
+pub fn text_size(text: &str, scale: u32) -> (u32, u32) {
+    let length = text.chars().count() as u32;
+    (
+        (length * (GLYPH_WIDTH + 1)).saturating_sub(1) * scale,
+        GLYPH_HEIGHT * scale,
+    )
+}
+
+pub struct Hud {
+    pub visible: bool,
+    frames: u32,
+    instructions: u64,
+    period_start: Instant,
+    frames_per_second: f32,
+    instructions_per_second: f32,
+    message: Option<(String, Instant)>,
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            frames: 0,
+            instructions: 0,
+            period_start: Instant::now(),
+            frames_per_second: 0.0,
+            instructions_per_second: 0.0,
+            message: None,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    // Must be called once per rendered frame, with the number of instructions run during it
+    pub fn record_frame(&mut self, instructions_executed: u32) {
+        self.frames += 1;
+        self.instructions += instructions_executed as u64;
+        let elapsed = self.period_start.elapsed();
+        if elapsed >= STATISTICS_PERIOD {
+            let seconds = elapsed.as_secs_f32();
+            self.frames_per_second = self.frames as f32 / seconds;
+            self.instructions_per_second = self.instructions as f32 / seconds;
+            self.frames = 0;
+            self.instructions = 0;
+            self.period_start = Instant::now();
+        }
+    }
+
+    // Messages are shown for a few seconds, even when the rest of the HUD is hidden
+    pub fn show_message(&mut self, message: String) {
+        self.message = Some((message, Instant::now()));
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, indicator: Option<&str>) {
+        if let Some((_, shown_at)) = &self.message {
+            if shown_at.elapsed() > MESSAGE_DURATION {
+                self.message = None;
+            }
+        }
+
+        let (_, output_height) = canvas.output_size().unwrap();
+        let scale = (output_height / 160).max(1);
+        let margin = (2 * scale) as i32;
+
+        let mut top_lines = Vec::new();
+        if self.visible {
+            top_lines.push(format!(
+                "FPS {:.1}  IPS {:.0}",
+                self.frames_per_second, self.instructions_per_second
+            ));
+        }
+        if let Some(indicator) = indicator {
+            top_lines.push(indicator.to_string());
+        }
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        let line_height = (GLYPH_HEIGHT + 2) * scale;
+        for (index, line) in top_lines.iter().enumerate() {
+            let y = margin + (index as u32 * line_height) as i32;
+            draw_boxed_text(canvas, line, margin, y, scale);
+        }
+        if let Some((message, _)) = &self.message {
+            let (_, height) = text_size(message, scale);
+            let y = output_height as i32 - height as i32 - margin;
+            draw_boxed_text(canvas, message, margin, y, scale);
+        }
+        canvas.set_blend_mode(BlendMode::None);
+    }
+}
+
+impl Default for Hud {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Text drawn over a translucent background, to stay readable over the game pixels
+fn draw_boxed_text(canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, scale: u32) {
+    let (width, height) = text_size(text, scale);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    canvas
+        .fill_rect(Rect::new(
+            x - scale as i32,
+            y - scale as i32,
+            width + 2 * scale,
+            height + 2 * scale,
+        ))
+        .unwrap();
+    canvas.set_draw_color(Color::RGB(255, 200, 0));
+    draw_text(canvas, text, x, y, scale);
+}
@@ -0,0 +1,35 @@
+// Emulator core, debugging tools and frontends, which the chip8 command puts together
+
+pub mod capi;
+pub mod cheats;
+pub mod coverage;
+pub mod debugger;
+pub mod disassembler;
+#[cfg(feature = "sdl")]
+pub mod display_filter;
+pub mod emulator;
+pub mod frontend;
+pub mod gdb_stub;
+pub mod gym;
+#[cfg(feature = "sdl")]
+pub mod hud;
+pub mod libretro;
+pub mod memory_heatmap;
+pub mod octo;
+pub mod patch;
+pub mod profiler;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod rom;
+#[cfg(feature = "sdl")]
+pub mod rom_browser;
+pub mod rom_database;
+pub mod rom_loader;
+pub mod rpl_flags;
+pub mod scripting;
+pub mod speed_control;
+pub mod symbols;
+pub mod tui;
+pub mod tui_debugger;
+#[cfg(feature = "sdl")]
+pub mod ui;
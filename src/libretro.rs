@@ -0,0 +1,581 @@
+// libretro core, in the same shared library as the C API. Frontends such as RetroArch load
+// target/release/libchip8.so, built with --no-default-features so that it does not need SDL2,
+// once it is copied to chip8_libretro.so. RetroPad buttons hold the 16 keys, and core options
+// choose the quirks and speed instead of the ROM database.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::emulator::{
+    Emulator, PixelStatus, Quirks, Snapshot, CHIP8_MEMORY_SIZE, CHIP8_SCREEN_HEIGHT,
+    CHIP8_SCREEN_WIDTH, SNAPSHOT_SIZE,
+};
+use crate::frontend::load_headless;
+use crate::rom_database::RomDatabase;
+
+use std::ffi::{c_char, c_uint, c_void, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Mutex;
+
+const API_VERSION: c_uint = 1;
+
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+const ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+
+const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+const DEVICE_JOYPAD: c_uint = 1;
+const MEMORY_SYSTEM_RAM: c_uint = 2;
+const REGION_NTSC: c_uint = 0;
+
+const FRAMES_PER_SECOND: f64 = 60.0;
+const SAMPLE_RATE: f64 = 44100.0;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAMES_PER_SECOND) as usize;
+const BEEP_FREQUENCY: f32 = 440.0;
+const BEEP_VOLUME: i16 = 0x2000;
+
+const QUIRKS_OPTION: &CStr = c"chip8_quirks";
+const SPEED_OPTION: &CStr = c"chip8_speed";
+
+// Key held by each RetroPad button, indexed by button id: B, Y, Select, Start, Up, Down, Left,
+// Right, A, X, L, R, L2, R2, L3 and R3. Directions are on 2, 4, 6 and 8 like most games.
+const BUTTON_KEYS: [u8; 16] = [
+    0x0, 0x1, 0xA, 0xB, 0x2, 0x8, 0x4, 0x6, 0x5, 0x3, 0x7, 0x9, 0xC, 0xD, 0xE, 0xF,
+];
+const KEY_NAMES: [&CStr; 16] = [
+    c"Key 0", c"Key 1", c"Key 2", c"Key 3", c"Key 4", c"Key 5", c"Key 6", c"Key 7", c"Key 8",
+    c"Key 9", c"Key A", c"Key B", c"Key C", c"Key D", c"Key E", c"Key F",
+];
+
+type EnvironmentCallback = unsafe extern "C" fn(command: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshCallback =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleCallback = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchCallback = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollCallback = unsafe extern "C" fn();
+type InputStateCallback =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    geometry: GameGeometry,
+    timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[repr(C)]
+struct Variable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+#[repr(C)]
+struct InputDescriptor {
+    port: c_uint,
+    device: c_uint,
+    index: c_uint,
+    id: c_uint,
+    description: *const c_char,
+}
+
+// Quirks of the interpreters games were written for, None to keep those of the ROM
+fn quirk_preset(name: &str) -> Option<Quirks> {
+    match name {
+        "vip" => Some(Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            logic_resets_vf: true,
+            wrap_sprites: false,
+        }),
+        "superchip" => Some(Quirks::default()),
+        "xochip" => Some(Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            logic_resets_vf: false,
+            wrap_sprites: true,
+        }),
+        _ => None,
+    }
+}
+
+// Square wave while the sound timer is not zero, in interleaved stereo samples
+struct Beeper {
+    phase: f32,
+}
+
+impl Beeper {
+    fn fill(&mut self, sounding: bool, samples: &mut [i16]) {
+        if !sounding {
+            samples.fill(0);
+            return;
+        }
+        for frame in samples.chunks_mut(2) {
+            frame.fill(if self.phase <= 0.5 {
+                BEEP_VOLUME
+            } else {
+                -BEEP_VOLUME
+            });
+            self.phase = (self.phase + BEEP_FREQUENCY / SAMPLE_RATE as f32) % 1.0;
+        }
+    }
+}
+
+struct Game {
+    emulator: Emulator,
+    initial: Snapshot,
+    // Settings of the ROM file or database, used when the options are on auto
+    rom_quirks: Quirks,
+    rom_instructions_per_frame: u32,
+    instructions_per_frame: u32,
+    // Background and foreground in XRGB8888
+    colors: [u32; 2],
+    video: [u32; CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT],
+    beeper: Beeper,
+    samples: [i16; SAMPLES_PER_FRAME * 2],
+    // Set when the program reached an instruction the emulator cannot run
+    crashed: bool,
+}
+
+// Frontends call the core from a single thread, one game at a time
+struct Core {
+    environment: Option<EnvironmentCallback>,
+    video_refresh: Option<VideoRefreshCallback>,
+    audio_sample_batch: Option<AudioSampleBatchCallback>,
+    input_poll: Option<InputPollCallback>,
+    input_state: Option<InputStateCallback>,
+    game: Option<Box<Game>>,
+}
+
+static CORE: Mutex<Core> = Mutex::new(Core {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+    game: None,
+});
+
+fn core() -> std::sync::MutexGuard<'static, Core> {
+    CORE.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+fn xrgb(color: [u8; 3]) -> u32 {
+    u32::from_be_bytes([0, color[0], color[1], color[2]])
+}
+
+impl Core {
+    fn call_environment(&self, command: c_uint, data: *mut c_void) -> bool {
+        self.environment
+            .is_some_and(|environment| unsafe { environment(command, data) })
+    }
+
+    fn variable(&self, key: &CStr) -> Option<String> {
+        let mut variable = Variable {
+            key: key.as_ptr(),
+            value: std::ptr::null(),
+        };
+        if !self.call_environment(
+            ENVIRONMENT_GET_VARIABLE,
+            &mut variable as *mut Variable as *mut c_void,
+        ) || variable.value.is_null()
+        {
+            return None;
+        }
+        Some(
+            unsafe { CStr::from_ptr(variable.value) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+
+    fn apply_options(&mut self) {
+        let quirks = self.variable(QUIRKS_OPTION);
+        let speed = self.variable(SPEED_OPTION);
+        if let Some(game) = &mut self.game {
+            game.emulator.quirks = quirks
+                .as_deref()
+                .and_then(quirk_preset)
+                .unwrap_or(game.rom_quirks);
+            game.instructions_per_frame = speed
+                .and_then(|speed| speed.parse().ok())
+                .unwrap_or(game.rom_instructions_per_frame);
+        }
+    }
+
+    fn options_updated(&self) -> bool {
+        let mut updated = false;
+        self.call_environment(
+            ENVIRONMENT_GET_VARIABLE_UPDATE,
+            &mut updated as *mut bool as *mut c_void,
+        ) && updated
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    unsafe {
+        *info = SystemInfo {
+            library_name: c"CHIP-8".as_ptr(),
+            library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+            valid_extensions: c"ch8|c8|sc8|xo8|gif|zip".as_ptr(),
+            // ROMs go through the same loader as the other frontends
+            need_fullpath: true,
+            block_extract: true,
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    unsafe {
+        *info = SystemAvInfo {
+            geometry: GameGeometry {
+                base_width: CHIP8_SCREEN_WIDTH as c_uint,
+                base_height: CHIP8_SCREEN_HEIGHT as c_uint,
+                max_width: CHIP8_SCREEN_WIDTH as c_uint,
+                max_height: CHIP8_SCREEN_HEIGHT as c_uint,
+                aspect_ratio: CHIP8_SCREEN_WIDTH as f32 / CHIP8_SCREEN_HEIGHT as f32,
+            },
+            timing: SystemTiming {
+                fps: FRAMES_PER_SECOND,
+                sample_rate: SAMPLE_RATE,
+            },
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(environment: Option<EnvironmentCallback>) {
+    let mut core = core();
+    core.environment = environment;
+    // The first value is the default
+    let mut variables = [
+        Variable {
+            key: QUIRKS_OPTION.as_ptr(),
+            value: c"Quirks; auto|vip|superchip|xochip".as_ptr(),
+        },
+        Variable {
+            key: SPEED_OPTION.as_ptr(),
+            value: c"Instructions per frame; auto|7|10|15|20|30|50|100|200|500|1000".as_ptr(),
+        },
+        Variable {
+            key: std::ptr::null(),
+            value: std::ptr::null(),
+        },
+    ];
+    core.call_environment(
+        ENVIRONMENT_SET_VARIABLES,
+        variables.as_mut_ptr() as *mut c_void,
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: Option<VideoRefreshCallback>) {
+    core().video_refresh = video_refresh;
+}
+
+// Samples all go through the batch callback
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_audio_sample: Option<AudioSampleCallback>) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(
+    audio_sample_batch: Option<AudioSampleBatchCallback>,
+) {
+    core().audio_sample_batch = audio_sample_batch;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: Option<InputPollCallback>) {
+    core().input_poll = input_poll;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: Option<InputStateCallback>) {
+    core().input_state = input_state;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    core().game = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(info: *const GameInfo) -> bool {
+    let mut core = core();
+    if info.is_null() || unsafe { (*info).path.is_null() } {
+        return false;
+    }
+    let path = unsafe { CStr::from_ptr((*info).path) }.to_string_lossy();
+    // Panics of the ROM loader and decoders must not unwind into the frontend either
+    let Ok(Ok((mut emulator, program, instructions_per_frame))) =
+        catch_unwind(|| load_headless(Path::new(path.as_ref()), None))
+    else {
+        return false;
+    };
+    let mut pixel_format = PIXEL_FORMAT_XRGB8888;
+    if !core.call_environment(
+        ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut pixel_format as *mut c_uint as *mut c_void,
+    ) {
+        return false;
+    }
+    let mut descriptors: Vec<InputDescriptor> = (0..BUTTON_KEYS.len())
+        .map(|id| InputDescriptor {
+            port: 0,
+            device: DEVICE_JOYPAD,
+            index: 0,
+            id: id as c_uint,
+            description: KEY_NAMES[BUTTON_KEYS[id] as usize].as_ptr(),
+        })
+        .chain(std::iter::once(InputDescriptor {
+            port: 0,
+            device: 0,
+            index: 0,
+            id: 0,
+            description: std::ptr::null(),
+        }))
+        .collect();
+    core.call_environment(
+        ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+        descriptors.as_mut_ptr() as *mut c_void,
+    );
+
+    emulator.trace = false;
+    let palette = RomDatabase::bundled()
+        .lookup(&program)
+        .and_then(|metadata| metadata.palette)
+        .unwrap_or_default();
+    core.game = Some(Box::new(Game {
+        initial: emulator.snapshot(),
+        rom_quirks: emulator.quirks,
+        rom_instructions_per_frame: instructions_per_frame,
+        instructions_per_frame,
+        colors: [xrgb(palette.background), xrgb(palette.foreground)],
+        video: [xrgb(palette.background); CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT],
+        emulator,
+        beeper: Beeper { phase: 0.0 },
+        samples: [0; SAMPLES_PER_FRAME * 2],
+        crashed: false,
+    }));
+    core.apply_options();
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    core().game = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(game) = &mut core().game {
+        game.emulator.restore(&game.initial);
+        game.crashed = false;
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let mut core = core();
+    if core.options_updated() {
+        core.apply_options();
+    }
+    let (video_refresh, audio_sample_batch, input_poll, input_state) = (
+        core.video_refresh,
+        core.audio_sample_batch,
+        core.input_poll,
+        core.input_state,
+    );
+    let (Some(video_refresh), Some(game)) = (video_refresh, core.game.as_deref_mut()) else {
+        return;
+    };
+    if let Some(input_poll) = input_poll {
+        unsafe { input_poll() };
+    }
+
+    if let Some(input_state) = input_state {
+        for (id, key) in BUTTON_KEYS.iter().enumerate() {
+            let pressed = unsafe { input_state(0, DEVICE_JOYPAD, 0, id as c_uint) } != 0;
+            if game.emulator.key_pressed(*key) != pressed {
+                game.emulator.input_key(*key, pressed);
+            }
+        }
+    }
+
+    // Panics of the emulator, on instructions it cannot run, stop it instead of unwinding
+    // into the frontend
+    if !game.crashed {
+        let instructions_per_frame = game.instructions_per_frame;
+        let emulator = &mut game.emulator;
+        game.crashed = catch_unwind(AssertUnwindSafe(|| {
+            emulator.run_frame(instructions_per_frame)
+        }))
+        .is_err();
+    }
+
+    for (color, pixel) in game.video.iter_mut().zip(game.emulator.screen.iter()) {
+        *color = game.colors[(*pixel == PixelStatus::White) as usize];
+    }
+    unsafe {
+        video_refresh(
+            game.video.as_ptr() as *const c_void,
+            CHIP8_SCREEN_WIDTH as c_uint,
+            CHIP8_SCREEN_HEIGHT as c_uint,
+            CHIP8_SCREEN_WIDTH * std::mem::size_of::<u32>(),
+        )
+    };
+
+    let sounding = game.emulator.sound_clock > 0 && !game.crashed;
+    game.beeper.fill(sounding, &mut game.samples);
+    if let Some(audio_sample_batch) = audio_sample_batch {
+        unsafe { audio_sample_batch(game.samples.as_ptr(), SAMPLES_PER_FRAME) };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    SNAPSHOT_SIZE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = core();
+    let Some(game) = &core.game else {
+        return false;
+    };
+    if size < SNAPSHOT_SIZE {
+        return false;
+    }
+    let state = game.emulator.snapshot().to_bytes();
+    unsafe { std::slice::from_raw_parts_mut(data as *mut u8, SNAPSHOT_SIZE) }
+        .copy_from_slice(&state);
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = core();
+    let Some(game) = &mut core.game else {
+        return false;
+    };
+    let state = unsafe { std::slice::from_raw_parts(data as *const u8, size) };
+    match catch_unwind(|| Snapshot::from_bytes(state)) {
+        Ok(Ok(snapshot)) => {
+            game.emulator.restore(&snapshot);
+            game.crashed = false;
+            true
+        }
+        _ => false,
+    }
+}
+
+// Cheats come from the cheat files of the other frontends instead
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+// The whole memory, for achievements and the cheat search of frontends
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    match &mut core().game {
+        Some(game) if id == MEMORY_SYSTEM_RAM => {
+            game.emulator.memory_mut().as_mut_ptr() as *mut c_void
+        }
+        _ => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    match &core().game {
+        Some(_) if id == MEMORY_SYSTEM_RAM => CHIP8_MEMORY_SIZE,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quirk_presets() {
+        assert_eq!(quirk_preset("auto"), None);
+        assert_eq!(quirk_preset("superchip"), Some(Quirks::default()));
+        assert!(quirk_preset("vip").unwrap().logic_resets_vf);
+        assert!(quirk_preset("xochip").unwrap().wrap_sprites);
+    }
+
+    #[test]
+    fn test_beeper() {
+        let mut beeper = Beeper { phase: 0.0 };
+        let mut samples = [1; SAMPLES_PER_FRAME * 2];
+        beeper.fill(false, &mut samples);
+        assert!(samples.iter().all(|sample| *sample == 0));
+
+        beeper.fill(true, &mut samples);
+        // 440 Hz at 44100 Hz, so the first 51 frames are high and the next ones low
+        assert!(samples[..102].iter().all(|sample| *sample == BEEP_VOLUME));
+        assert_eq!(samples[102], -BEEP_VOLUME);
+        assert_eq!(samples[102], samples[103]);
+    }
+}
@@ -1,6 +1,379 @@
-mod emulator;
-mod ui;
+#[cfg(feature = "sdl")]
+use chip8::ui;
+use chip8::{
+    cheats, coverage, disassembler, emulator, frontend, gdb_stub, patch, rom_database, scripting,
+    symbols, tui, tui_debugger,
+};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_ROM_PATH: &str = "roms/BLINKY";
+const DEFAULT_COVERAGE_FRAMES: u32 = 600;
+const DEFAULT_GDB_PORT: u16 = 1234;
+const USAGE: &str = "Usage: chip8 [--watch] [--tick-rate N] [--quirks LIST] \
+[--palette RRGGBB,RRGGBB] [--database FILE]
+             [--flags-dir DIR] [--save-flags-on-exit] [--profile FILE]
+             [--coverage FILE] [--terminal [--braille]] [--symbols FILE] [--trace]
+             [--script FILE] [ROM]
+       chip8 create-patch SOURCE TARGET PATCH
+       chip8 disassemble [--symbols FILE] ROM
+       chip8 coverage [--frames N] [--tick-rate N] ROM REPORT
+       chip8 gdb [--port N] [--tick-rate N] [--seed N] ROM
+       chip8 debug [--tick-rate N] [--seed N] [--symbols FILE] ROM
+       chip8 script [--frames N] [--tick-rate N] SCRIPT ROM
+
+ROM is a raw binary, an Octo cartridge GIF, or a zip archive optionally followed by
+the ROM to pick inside it, such as games.zip/BRIX.
+Cartridges hold Octo source code, assembled by the built-in assembler, or by the Octo
+command line assembler given in the CHIP8_OCTO environment variable for string modes and
+assertions, which the built-in one does not support
+Quirks are given as a comma separated list among: shift, load-store, vf-reset, wrap, none
+SUPER-CHIP RPL user flags are saved per ROM in DIR, by default $XDG_DATA_HOME/chip8/rpl_flags
+With --profile, a profile of the ROM is written to FILE at exit and when pressing F10
+Coverage reports are HTML when the file ends with .html, LCOV tracefiles otherwise.
+With --terminal, the ROM runs in the terminal, drawn with half blocks or braille dots
+The coverage subcommand runs the ROM without window nor input for N frames, 600 by default
+The gdb subcommand serves the GDB remote protocol on localhost, port 1234 by default
+The debug subcommand opens a full-screen debugger in the terminal
+Both debuggers can run backwards, --seed makes the random numbers the same on every run
+With --trace, every instruction run is printed on the standard output
+Symbol files name addresses in traces, the disassembly and the debugger. They are Octo
+JSON debug output, or text files with one \"ADDR NAME\" line per symbol, ADDR in hexadecimal
+Scripts are written in Rhai. With --script, the script runs along the ROM and its printed
+text is shown on screen. The script subcommand runs it without window nor input, printing
+its text, until it calls stop() or after N frames";
+
+fn parse_quirks(list: &str) -> Result<emulator::Quirks, String> {
+    let mut quirks = emulator::Quirks::default();
+    for name in list.split(',') {
+        match name {
+            "shift" => quirks.shift_uses_vy = true,
+            "load-store" => quirks.load_store_increments_i = true,
+            "vf-reset" => quirks.logic_resets_vf = true,
+            "wrap" => quirks.wrap_sprites = true,
+            "none" => (),
+            _ => return Err(format!("Unknown quirk {:?}", name)),
+        }
+    }
+    Ok(quirks)
+}
+
+fn parse_palette(colors: &str) -> Result<rom_database::Palette, String> {
+    match colors.split_once(',') {
+        Some((background, foreground)) => Ok(rom_database::Palette {
+            background: rom_database::parse_color(background)?,
+            foreground: rom_database::parse_color(foreground)?,
+        }),
+        None => Err(format!("Invalid palette {:?}", colors)),
+    }
+}
+
+fn load_symbols(path: &str) -> Result<symbols::Symbols, String> {
+    symbols::Symbols::load(Path::new(path))
+}
+
+fn parse_options() -> Result<frontend::Options, String> {
+    let mut options = frontend::Options {
+        rom_path: PathBuf::from(DEFAULT_ROM_PATH),
+        watch_rom: false,
+        tick_rate: None,
+        quirks: None,
+        palette: None,
+        database_path: None,
+        rpl_flags_directory: None,
+        save_flags_on_exit: false,
+        profile_path: None,
+        coverage_path: None,
+        terminal: false,
+        braille: false,
+        trace: false,
+        symbols: symbols::Symbols::default(),
+        script_path: None,
+    };
+    let mut arguments = std::env::args().skip(1);
+    while let Some(argument) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("Missing value for {}", argument))
+        };
+        match argument.as_str() {
+            "--watch" => options.watch_rom = true,
+            "--tick-rate" => {
+                let tick_rate = value()?;
+                options.tick_rate = Some(
+                    tick_rate
+                        .parse()
+                        .map_err(|_| format!("Invalid tick rate {:?}", tick_rate))?,
+                );
+            }
+            "--quirks" => options.quirks = Some(parse_quirks(&value()?)?),
+            "--palette" => options.palette = Some(parse_palette(&value()?)?),
+            "--database" => options.database_path = Some(PathBuf::from(value()?)),
+            "--flags-dir" => options.rpl_flags_directory = Some(PathBuf::from(value()?)),
+            "--save-flags-on-exit" => options.save_flags_on_exit = true,
+            "--profile" => options.profile_path = Some(PathBuf::from(value()?)),
+            "--coverage" => options.coverage_path = Some(PathBuf::from(value()?)),
+            "--terminal" => options.terminal = true,
+            "--braille" => options.braille = true,
+            "--trace" => options.trace = true,
+            "--symbols" => options.symbols = load_symbols(&value()?)?,
+            "--script" => options.script_path = Some(PathBuf::from(value()?)),
+            _ if argument.starts_with("--") => return Err(format!("Unknown option {}", argument)),
+            _ => options.rom_path = PathBuf::from(argument),
+        }
+    }
+    Ok(options)
+}
+
+// Writes an IPS or BPS patch, depending on the extension of the patch file
+fn create_patch(arguments: &[String]) -> Result<(), String> {
+    let [source_path, target_path, patch_path] = arguments else {
+        return Err("create-patch expects SOURCE, TARGET and PATCH files".to_string());
+    };
+    let read = |path: &String| std::fs::read(path).map_err(|error| format!("{}: {}", path, error));
+    let source = read(source_path)?;
+    let target = read(target_path)?;
+    let patch = if patch_path.to_lowercase().ends_with(".bps") {
+        patch::create_bps(&source, &target)
+    } else if patch_path.to_lowercase().ends_with(".ips") {
+        patch::create_ips(&source, &target).map_err(|error| error.to_string())?
+    } else {
+        return Err("The patch file must end with .ips or .bps".to_string());
+    };
+    std::fs::write(patch_path, patch).map_err(|error| format!("{}: {}", patch_path, error))
+}
+
+fn parse_number(text: &str) -> Result<u32, String> {
+    text.parse()
+        .map_err(|_| format!("Invalid number {:?}", text))
+}
+
+// Runs a ROM headless, then writes which instructions and sprite data it reached
+fn record_coverage(arguments: &[String]) -> Result<(), String> {
+    let mut frames = DEFAULT_COVERAGE_FRAMES;
+    let mut tick_rate = None;
+    let mut paths = Vec::new();
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("Missing value for {}", argument))
+        };
+        match argument.as_str() {
+            "--frames" => frames = parse_number(value()?)?,
+            "--tick-rate" => tick_rate = Some(parse_number(value()?)?),
+            _ if argument.starts_with("--") => return Err(format!("Unknown option {}", argument)),
+            _ => paths.push(PathBuf::from(argument)),
+        }
+    }
+    let [rom_path, report_path] = paths.as_slice() else {
+        return Err("coverage expects ROM and REPORT files".to_string());
+    };
+
+    let (mut emulator, program, instructions_per_frame) =
+        frontend::load_headless(rom_path, tick_rate)?;
+    emulator.trace = false;
+    emulator.coverage = Some(coverage::Coverage::new());
+    for _ in 0..frames {
+        emulator.run_frame(instructions_per_frame);
+    }
+
+    let program_start = emulator::CHIP8_FIRST_BYTE_ADDRESS;
+    let report = emulator.coverage.as_ref().unwrap().report(
+        emulator.memory(),
+        program_start,
+        program_start + program.len(),
+        &rom_path.display().to_string(),
+        report_path,
+    );
+    std::fs::write(report_path, report)
+        .map_err(|error| format!("{}: {}", report_path.display(), error))
+}
+
+// Waits for GDB, or any client of its remote protocol, to debug the ROM
+fn serve_gdb(arguments: &[String]) -> Result<(), String> {
+    let mut port = DEFAULT_GDB_PORT;
+    let mut tick_rate = None;
+    let mut seed = None;
+    let mut rom_path = None;
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("Missing value for {}", argument))
+        };
+        match argument.as_str() {
+            "--port" => {
+                let port_text = value()?;
+                port = port_text
+                    .parse()
+                    .map_err(|_| format!("Invalid port {:?}", port_text))?;
+            }
+            "--tick-rate" => tick_rate = Some(parse_number(value()?)?),
+            "--seed" => seed = Some(parse_number(value()?)?),
+            _ if argument.starts_with("--") => return Err(format!("Unknown option {}", argument)),
+            _ => rom_path = Some(PathBuf::from(argument)),
+        }
+    }
+    let rom_path = rom_path.ok_or("gdb expects a ROM file")?;
+
+    let (mut emulator, _, instructions_per_frame) = frontend::load_headless(&rom_path, tick_rate)?;
+    if let Some(seed) = seed {
+        emulator.seed_rng(seed as u64);
+    }
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port))
+        .map_err(|error| format!("Cannot listen on port {}: {}", port, error))?;
+    eprintln!("Waiting for GDB on localhost:{}", port);
+    gdb_stub::GdbStub::new(emulator, instructions_per_frame)
+        .serve(listener)
+        .map_err(|error| error.to_string())
+}
+
+// Prints the disassembly of the program of the ROM, with the labels of the symbol file
+fn print_disassembly(arguments: &[String]) -> Result<(), String> {
+    let mut symbols = symbols::Symbols::default();
+    let mut rom_path = None;
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("Missing value for {}", argument))
+        };
+        match argument.as_str() {
+            "--symbols" => symbols = load_symbols(value()?)?,
+            _ if argument.starts_with("--") => return Err(format!("Unknown option {}", argument)),
+            _ => rom_path = Some(PathBuf::from(argument)),
+        }
+    }
+    let rom_path = rom_path.ok_or("disassemble expects a ROM file")?;
+
+    let (emulator, program, _) = frontend::load_headless(&rom_path, None)?;
+    let program_start = emulator::CHIP8_FIRST_BYTE_ADDRESS;
+    print!(
+        "{}",
+        disassembler::listing(
+            emulator.memory(),
+            program_start,
+            program_start + program.len(),
+            &symbols
+        )
+    );
+    Ok(())
+}
+
+// Runs a ROM headless under the control of a script, until the script stops or for N frames
+fn run_script(arguments: &[String]) -> Result<(), String> {
+    let mut frames = None;
+    let mut tick_rate = None;
+    let mut paths = Vec::new();
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("Missing value for {}", argument))
+        };
+        match argument.as_str() {
+            "--frames" => frames = Some(parse_number(value()?)?),
+            "--tick-rate" => tick_rate = Some(parse_number(value()?)?),
+            _ if argument.starts_with("--") => return Err(format!("Unknown option {}", argument)),
+            _ => paths.push(PathBuf::from(argument)),
+        }
+    }
+    let [script_path, rom_path] = paths.as_slice() else {
+        return Err("script expects SCRIPT and ROM files".to_string());
+    };
+
+    let (mut emulator, _, instructions_per_frame) = frontend::load_headless(rom_path, tick_rate)?;
+    emulator.trace = false;
+    let mut script = scripting::Script::load(script_path, &mut emulator)?;
+    let mut frame = 0;
+    while !script.finished() && frames.is_none_or(|frames| frame < frames) {
+        script.run_frame(&mut emulator, instructions_per_frame);
+        for message in script.take_messages() {
+            println!("{}", message);
+        }
+        if let Some(error) = script.take_error() {
+            return Err(error);
+        }
+        frame += 1;
+    }
+    Ok(())
+}
+
+// Opens the full-screen terminal debugger on the ROM
+fn debug_in_terminal(arguments: &[String]) -> Result<(), String> {
+    let mut tick_rate = None;
+    let mut seed = None;
+    let mut symbols = symbols::Symbols::default();
+    let mut rom_path = None;
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("Missing value for {}", argument))
+        };
+        match argument.as_str() {
+            "--tick-rate" => tick_rate = Some(parse_number(value()?)?),
+            "--seed" => seed = Some(parse_number(value()?)?),
+            "--symbols" => symbols = load_symbols(value()?)?,
+            _ if argument.starts_with("--") => return Err(format!("Unknown option {}", argument)),
+            _ => rom_path = Some(PathBuf::from(argument)),
+        }
+    }
+    let rom_path = rom_path.ok_or("debug expects a ROM file")?;
+
+    let (mut emulator, _, instructions_per_frame) = frontend::load_headless(&rom_path, tick_rate)?;
+    if let Some(seed) = seed {
+        emulator.seed_rng(seed as u64);
+    }
+    emulator.symbols = symbols;
+    let mut cheats = cheats::CheatEngine::new();
+    cheats
+        .load_for_rom(&rom_path)
+        .map_err(|error| format!("Cannot load cheats: {}", error))?;
+    cheats.apply_patches(&mut emulator);
+    tui_debugger::run(emulator, instructions_per_frame, cheats)
+        .map_err(|error| format!("Terminal error: {}", error))
+}
+
+fn run_terminal(options: frontend::Options) -> Result<(), String> {
+    tui::run_program(options).map_err(|error| format!("Terminal error: {}", error))
+}
+
+#[cfg(feature = "sdl")]
+fn run_frontend(options: frontend::Options) -> Result<(), String> {
+    if options.terminal {
+        run_terminal(options)
+    } else {
+        ui::run_program(options);
+        Ok(())
+    }
+}
+
+// Without the graphical frontend, ROMs always run in the terminal
+#[cfg(not(feature = "sdl"))]
+fn run_frontend(options: frontend::Options) -> Result<(), String> {
+    run_terminal(options)
+}
 
 fn main() {
-    ui::run_program();
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let result = match arguments.first().map(String::as_str) {
+        Some("create-patch") => create_patch(&arguments[1..]),
+        Some("coverage") => record_coverage(&arguments[1..]),
+        Some("disassemble") => print_disassembly(&arguments[1..]),
+        Some("gdb") => serve_gdb(&arguments[1..]),
+        Some("debug") => debug_in_terminal(&arguments[1..]),
+        Some("script") => run_script(&arguments[1..]),
+        _ => parse_options().and_then(run_frontend),
+    };
+    if let Err(error) = result {
+        eprintln!("{}\n\n{}", error, USAGE);
+        std::process::exit(1);
+    }
 }
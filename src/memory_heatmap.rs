@@ -0,0 +1,105 @@
+use crate::emulator::CHIP8_MEMORY_SIZE;
+
+// The 4 KiB of memory are shown as a square grid, one cell per byte
+pub const HEATMAP_SIDE: usize = 64;
+// Number of frames for an access to fade out completely
+const FADE_FRAMES: u8 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryAccess {
+    // Bytes read as an instruction
+    Fetch,
+    // Bytes read by DXYN or FX65
+    Read,
+    // Bytes written by FX55
+    Write,
+}
+
+// Recent memory accesses, each kind with its own intensity fading over time
+pub struct MemoryHeatmap {
+    fetches: Vec<u8>,
+    reads: Vec<u8>,
+    writes: Vec<u8>,
+}
+
+impl MemoryHeatmap {
+    pub fn new() -> Self {
+        Self {
+            fetches: vec![0; CHIP8_MEMORY_SIZE],
+            reads: vec![0; CHIP8_MEMORY_SIZE],
+            writes: vec![0; CHIP8_MEMORY_SIZE],
+        }
+    }
+
+    pub fn record(&mut self, access: MemoryAccess, address: usize, length: usize) {
+        let intensities = match access {
+            MemoryAccess::Fetch => &mut self.fetches,
+            MemoryAccess::Read => &mut self.reads,
+            MemoryAccess::Write => &mut self.writes,
+        };
+        let end = (address + length).min(CHIP8_MEMORY_SIZE);
+        for intensity in &mut intensities[address.min(end)..end] {
+            *intensity = u8::MAX;
+        }
+    }
+
+    // Must be called once per rendered frame
+    pub fn fade(&mut self) {
+        let fade_step = u8::MAX.div_ceil(FADE_FRAMES);
+        for intensities in [&mut self.fetches, &mut self.reads, &mut self.writes] {
+            for intensity in intensities.iter_mut() {
+                *intensity = intensity.saturating_sub(fade_step);
+            }
+        }
+    }
+
+    // Writes are red, reads blue and fetches green, mixed when a byte had several accesses
+    pub fn color(&self, address: usize) -> [u8; 3] {
+        [
+            self.writes[address],
+            self.fetches[address],
+            self.reads[address],
+        ]
+    }
+}
+
+impl Default for MemoryHeatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, CHIP8_FIRST_BYTE_ADDRESS};
+
+    #[test]
+    fn test_fade() {
+        let mut heatmap = MemoryHeatmap::new();
+        heatmap.record(MemoryAccess::Write, 0x300, 2);
+        assert_eq!(heatmap.color(0x301), [255, 0, 0]);
+        heatmap.fade();
+        assert_eq!(heatmap.color(0x301), [246, 0, 0]);
+        for _ in 1..FADE_FRAMES {
+            heatmap.fade();
+        }
+        assert_eq!(heatmap.color(0x301), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_emulator_accesses() {
+        let mut emulator = Emulator::new();
+        emulator.heatmap = Some(MemoryHeatmap::new());
+        // I = 0x300, store V0 and V1 there, draw 3 rows from there
+        emulator.load_program(&[0xA3, 0x00, 0xF1, 0x55, 0xD0, 0x03]);
+        for _ in 0..3 {
+            emulator.process_next_instruction();
+        }
+        let heatmap = emulator.heatmap.as_ref().unwrap();
+        assert_eq!(heatmap.color(CHIP8_FIRST_BYTE_ADDRESS + 5), [0, 255, 0]);
+        assert_eq!(heatmap.color(0x301), [255, 0, 255]);
+        assert_eq!(heatmap.color(0x302), [0, 0, 255]);
+        assert_eq!(heatmap.color(0x303), [0, 0, 0]);
+    }
+}
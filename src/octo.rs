@@ -0,0 +1,996 @@
+// Assembler for Octo, the language of the source code held by Octo cartridges. It covers the
+// CHIP-8, SUPER-CHIP and XO-CHIP statements, labels, structured control flow, :const, :alias,
+// :org, :next, :unpack, :byte, :call, :calc and :macro. String modes and assertions are not
+// supported.
+use crate::emulator::{CHIP8_FIRST_BYTE_ADDRESS, CHIP8_MEMORY_SIZE};
+
+use std::collections::{HashMap, VecDeque};
+
+const REGISTER_VF: u8 = 0xF;
+// Bounds the expansion of macros calling each other endlessly
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    arguments: Vec<String>,
+    body: Vec<Token>,
+}
+
+// How a label is written once its address is known
+#[derive(Clone, Copy)]
+enum FixupKind {
+    // Lowest 12 bits of the instruction at the address
+    Address,
+    // 16 bits following the F000 instruction of "i := long"
+    Long,
+    // Nibble and highest 4 bits of the address in the 6XNN instruction of :unpack
+    UnpackHigh(u8),
+    // Lowest 8 bits of the address in the 6XNN instruction of :unpack
+    UnpackLow,
+}
+
+struct Fixup {
+    address: usize,
+    label: String,
+    kind: FixupKind,
+    line: usize,
+}
+
+// Open blocks, patched when they are closed
+enum Flow {
+    // Address of the jump to the else or end
+    Begin(usize),
+    // Address of the jump over the else branch
+    Else(usize),
+    // Start of the loop, and the jumps out of it of its while statements
+    Loop(usize, Vec<usize>),
+}
+
+// Condition of if and while statements
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    Key,
+    NotKey,
+}
+
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    // Memory from CHIP8_FIRST_BYTE_ADDRESS, up to the highest byte written
+    program: Vec<u8>,
+    here: usize,
+    line: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    macro_expansions: usize,
+    fixups: Vec<Fixup>,
+    flow: Vec<Flow>,
+    // The first instruction jumps to main, unless main is the first label
+    jumps_to_main: bool,
+}
+
+// Assembles the source into a program loaded at 0x200
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut assembler = Assembler {
+        tokens: tokenize(source),
+        program: Vec::new(),
+        here: CHIP8_FIRST_BYTE_ADDRESS,
+        line: 0,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        macro_expansions: 0,
+        fixups: Vec::new(),
+        flow: Vec::new(),
+        jumps_to_main: true,
+    };
+    assembler
+        .run()
+        .map_err(|error| format!("line {}: {}", assembler.line, error))?;
+    Ok(assembler.program)
+}
+
+// Words separated by whitespace, without the comments from # to the end of the line
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        for word in line.split_whitespace() {
+            if word.starts_with('#') {
+                break;
+            }
+            tokens.push_back(Token {
+                text: word.to_string(),
+                line: index + 1,
+            });
+        }
+    }
+    tokens
+}
+
+// Decimal, hexadecimal with 0x or binary with 0b, possibly negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hexadecimal) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hexadecimal, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|character: char| character.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn binary_operation(operator: &str, left: i64, right: i64) -> Option<Result<i64, String>> {
+    let result = match operator {
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Some(Err("Division by zero".to_string())),
+        "/" => left / right,
+        "%" => left % right,
+        "&" => left & right,
+        "|" => left | right,
+        "^" => left ^ right,
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "min" => left.min(right),
+        "max" => left.max(right),
+        "<" => (left < right) as i64,
+        ">" => (left > right) as i64,
+        "<=" => (left <= right) as i64,
+        ">=" => (left >= right) as i64,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        _ => return None,
+    };
+    Some(Ok(result))
+}
+
+impl Assembler {
+    fn run(&mut self) -> Result<(), String> {
+        // Room for the jump to main
+        self.instruction(0x0000)?;
+        while let Some(token) = self.tokens.pop_front() {
+            self.line = token.line;
+            self.statement(&token.text)?;
+        }
+        if !self.flow.is_empty() {
+            return Err("Missing end or again at the end of the program".to_string());
+        }
+
+        let main = *self
+            .labels
+            .get("main")
+            .ok_or("This program is missing a 'main' label")?;
+        if self.jumps_to_main {
+            self.write_instruction(CHIP8_FIRST_BYTE_ADDRESS, 0x1000 | main as u16);
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let address = *self
+                .labels
+                .get(&fixup.label)
+                .ok_or(format!("Undefined name {:?}", fixup.label))?;
+            let offset = fixup.address - CHIP8_FIRST_BYTE_ADDRESS;
+            match fixup.kind {
+                FixupKind::Address => {
+                    if address >= CHIP8_MEMORY_SIZE {
+                        return Err(format!("Address of {:?} out of range", fixup.label));
+                    }
+                    self.program[offset] |= (address >> 8) as u8;
+                    self.program[offset + 1] = address as u8;
+                }
+                FixupKind::Long => {
+                    self.program[offset] = (address >> 8) as u8;
+                    self.program[offset + 1] = address as u8;
+                }
+                FixupKind::UnpackHigh(nibble) => {
+                    self.program[offset + 1] = (nibble << 4) | (address >> 8) as u8 & 0x0F;
+                }
+                FixupKind::UnpackLow => self.program[offset + 1] = address as u8,
+            }
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .pop_front()
+            .ok_or("Unexpected end of the program")?;
+        self.line = token.line;
+        Ok(token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(format!("Expected {:?}, found {:?}", expected, token));
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, value: u8) -> Result<(), String> {
+        if !(CHIP8_FIRST_BYTE_ADDRESS..CHIP8_MEMORY_SIZE).contains(&self.here) {
+            return Err(format!(
+                "Address 0x{:X} is outside of the program",
+                self.here
+            ));
+        }
+        let offset = self.here - CHIP8_FIRST_BYTE_ADDRESS;
+        if self.program.len() <= offset {
+            self.program.resize(offset + 1, 0);
+        }
+        self.program[offset] = value;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn instruction(&mut self, opcode: u16) -> Result<(), String> {
+        self.byte((opcode >> 8) as u8)?;
+        self.byte(opcode as u8)
+    }
+
+    fn write_instruction(&mut self, address: usize, opcode: u16) {
+        let offset = address - CHIP8_FIRST_BYTE_ADDRESS;
+        self.program[offset] = (opcode >> 8) as u8;
+        self.program[offset + 1] = opcode as u8;
+    }
+
+    fn define_label(&mut self, name: String, address: usize) -> Result<(), String> {
+        if self.is_register(&name) || parse_number(&name).is_some() {
+            return Err(format!("Invalid name {:?}", name));
+        }
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return Err(format!("Name {:?} is already defined", name));
+        }
+        self.labels.insert(name, address);
+        Ok(())
+    }
+
+    fn is_register(&self, token: &str) -> bool {
+        self.aliases.contains_key(token) || Self::register_number(token).is_some()
+    }
+
+    fn register_number(token: &str) -> Option<u8> {
+        let digit = token.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        match self.aliases.get(&token) {
+            Some(register) => Ok(*register),
+            None => Self::register_number(&token)
+                .ok_or(format!("Expected a register, found {:?}", token)),
+        }
+    }
+
+    // Number, constant or label already defined
+    fn known_value(&self, token: &str) -> Option<i64> {
+        parse_number(token)
+            .or_else(|| self.constants.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|address| *address as i64))
+    }
+
+    fn value(&mut self) -> Result<i64, String> {
+        let token = self.next()?;
+        if token == "{" {
+            return self.calculation();
+        }
+        self.known_value(&token)
+            .ok_or(format!("Expected a number, found {:?}", token))
+    }
+
+    fn bounded(&mut self, minimum: i64, maximum: i64) -> Result<i64, String> {
+        let value = self.value()?;
+        if !(minimum..=maximum).contains(&value) {
+            return Err(format!("Value {} out of range", value));
+        }
+        Ok(value)
+    }
+
+    // Bytes can be given signed, from -128
+    fn byte_value(&mut self) -> Result<u8, String> {
+        Ok(self.bounded(-128, 255)? as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u16, String> {
+        Ok(self.bounded(0, 15)? as u16)
+    }
+
+    // Instruction with a 12 bit address, from a number, a constant or a label defined later
+    fn address_instruction(&mut self, opcode: u16) -> Result<(), String> {
+        let token = self.next()?;
+        match self.known_value(&token) {
+            Some(address) if (0..CHIP8_MEMORY_SIZE as i64).contains(&address) => {
+                self.instruction(opcode | address as u16)
+            }
+            Some(address) => Err(format!("Address {} out of range", address)),
+            None => {
+                self.reference(token, FixupKind::Address);
+                self.instruction(opcode)
+            }
+        }
+    }
+
+    fn reference(&mut self, label: String, kind: FixupKind) {
+        self.fixups.push(Fixup {
+            address: self.here,
+            label,
+            kind,
+            line: self.line,
+        });
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        if self.peek().is_some_and(|token| self.is_register(token)) {
+            Ok(Operand::Register(self.register()?))
+        } else {
+            Ok(Operand::Byte(self.byte_value()?))
+        }
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), String> {
+        if let Some(macro_name) = self.macros.get(token).map(|_| token.to_string()) {
+            return self.expand_macro(&macro_name);
+        }
+        if token.starts_with(':') && token != ":" && token != ":=" {
+            return self.directive(token);
+        }
+        if self.is_register(token) {
+            let x = self.aliases.get(token).copied();
+            let x = x.or(Self::register_number(token)).unwrap();
+            return self.assignment(x);
+        }
+
+        match token {
+            ":" => {
+                let name = self.next()?;
+                // Programs starting with main need no jump to it
+                if name == "main"
+                    && self.jumps_to_main
+                    && self.here == CHIP8_FIRST_BYTE_ADDRESS + 2
+                    && self.program.len() == 2
+                {
+                    self.jumps_to_main = false;
+                    self.program.clear();
+                    self.here = CHIP8_FIRST_BYTE_ADDRESS;
+                }
+                self.define_label(name, self.here)
+            }
+            ";" | "return" => self.instruction(0x00EE),
+            "clear" => self.instruction(0x00E0),
+            "exit" => self.instruction(0x00FD),
+            "scroll-right" => self.instruction(0x00FB),
+            "scroll-left" => self.instruction(0x00FC),
+            "lores" => self.instruction(0x00FE),
+            "hires" => self.instruction(0x00FF),
+            "audio" => self.instruction(0xF002),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.instruction(0x00C0 | n)
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.instruction(0x00D0 | n)
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.instruction(0xF001 | n << 8)
+            }
+            "jump" => self.address_instruction(0x1000),
+            "jump0" => self.address_instruction(0xB000),
+            "native" => self.address_instruction(0x0000),
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.nibble()?;
+                self.instruction(0xD000 | x << 8 | y << 4 | n)
+            }
+            "bcd" => self.register_instruction(0xF033),
+            "saveflags" => self.register_instruction(0xF075),
+            "loadflags" => self.register_instruction(0xF085),
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()? as u16;
+                    let low = if token == "save" { 2 } else { 3 };
+                    return self.instruction(0x5000 | x << 8 | y << 4 | low);
+                }
+                let low = if token == "save" { 0x55 } else { 0x65 };
+                self.instruction(0xF000 | x << 8 | low)
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let low = match token {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.register_instruction(0xF000 | low)
+            }
+            "i" | "I" => self.index_statement(),
+            "if" => self.if_statement(),
+            "else" => match self.flow.pop() {
+                Some(Flow::Begin(jump)) => {
+                    let over_else = self.here;
+                    self.instruction(0x1000)?;
+                    self.write_instruction(jump, 0x1000 | self.here as u16);
+                    self.flow.push(Flow::Else(over_else));
+                    Ok(())
+                }
+                _ => Err("else without if ... begin".to_string()),
+            },
+            "end" => match self.flow.pop() {
+                Some(Flow::Begin(jump) | Flow::Else(jump)) => {
+                    self.write_instruction(jump, 0x1000 | self.here as u16);
+                    Ok(())
+                }
+                _ => Err("end without if ... begin".to_string()),
+            },
+            "loop" => {
+                self.flow.push(Flow::Loop(self.here, Vec::new()));
+                Ok(())
+            }
+            "while" => {
+                let comparison = self.condition()?;
+                self.skip(comparison, true)?;
+                let exit = self.here;
+                self.instruction(0x1000)?;
+                match self.flow.iter_mut().rev().find_map(|flow| match flow {
+                    Flow::Loop(_, exits) => Some(exits),
+                    _ => None,
+                }) {
+                    Some(exits) => exits.push(exit),
+                    None => return Err("while outside of a loop".to_string()),
+                }
+                Ok(())
+            }
+            "again" => match self.flow.pop() {
+                Some(Flow::Loop(start, exits)) => {
+                    self.instruction(0x1000 | start as u16)?;
+                    for exit in exits {
+                        self.write_instruction(exit, 0x1000 | self.here as u16);
+                    }
+                    Ok(())
+                }
+                _ => Err("again without loop".to_string()),
+            },
+            "{" => {
+                let value = self.calculation()?;
+                self.emit_value(value)
+            }
+            _ => match self.known_value(token) {
+                // Numbers alone are data bytes
+                Some(value)
+                    if parse_number(token).is_some() || self.constants.contains_key(token) =>
+                {
+                    self.emit_value(value)
+                }
+                // Other names are subroutines to call
+                Some(address) => self.call(address),
+                None if token.starts_with(|character: char| {
+                    character.is_alphabetic() || character == '_'
+                }) =>
+                {
+                    self.reference(token.to_string(), FixupKind::Address);
+                    self.instruction(0x2000)
+                }
+                None => Err(format!("Unexpected {:?}", token)),
+            },
+        }
+    }
+
+    fn emit_value(&mut self, value: i64) -> Result<(), String> {
+        if !(-128..=255).contains(&value) {
+            return Err(format!("Value {} does not fit in a byte", value));
+        }
+        self.byte(value as u8)
+    }
+
+    fn call(&mut self, address: i64) -> Result<(), String> {
+        if !(0..CHIP8_MEMORY_SIZE as i64).contains(&address) {
+            return Err(format!("Address {} out of range", address));
+        }
+        self.instruction(0x2000 | address as u16)
+    }
+
+    fn register_instruction(&mut self, opcode: u16) -> Result<(), String> {
+        let x = self.register()? as u16;
+        self.instruction(opcode | x << 8)
+    }
+
+    fn assignment(&mut self, x: u8) -> Result<(), String> {
+        let x16 = (x as u16) << 8;
+        let operator = self.next()?;
+        let operand = match (operator.as_str(), self.peek()) {
+            (":=", Some("random")) => {
+                self.next()?;
+                let value = self.byte_value()? as u16;
+                return self.instruction(0xC000 | x16 | value);
+            }
+            (":=", Some("key")) => {
+                self.next()?;
+                return self.instruction(0xF00A | x16);
+            }
+            (":=", Some("delay")) => {
+                self.next()?;
+                return self.instruction(0xF007 | x16);
+            }
+            _ => self.operand()?,
+        };
+        let opcode = match (operator.as_str(), operand) {
+            (":=", Operand::Byte(value)) => 0x6000 | x16 | value as u16,
+            ("+=", Operand::Byte(value)) => 0x7000 | x16 | value as u16,
+            ("-=", Operand::Byte(value)) => 0x7000 | x16 | value.wrapping_neg() as u16,
+            (operator, Operand::Register(y)) => {
+                let low = match operator {
+                    ":=" => 0x0,
+                    "|=" => 0x1,
+                    "&=" => 0x2,
+                    "^=" => 0x3,
+                    "+=" => 0x4,
+                    "-=" => 0x5,
+                    ">>=" => 0x6,
+                    "=-" => 0x7,
+                    "<<=" => 0xE,
+                    _ => return Err(format!("Unknown operator {:?}", operator)),
+                };
+                0x8000 | x16 | (y as u16) << 4 | low
+            }
+            (operator, Operand::Byte(_)) => {
+                return Err(format!("Operator {:?} expects a register", operator))
+            }
+        };
+        self.instruction(opcode)
+    }
+
+    fn index_statement(&mut self) -> Result<(), String> {
+        let operator = self.next()?;
+        match (operator.as_str(), self.peek()) {
+            ("+=", _) => self.register_instruction(0xF01E),
+            (":=", Some("hex")) => {
+                self.next()?;
+                self.register_instruction(0xF029)
+            }
+            (":=", Some("bighex")) => {
+                self.next()?;
+                self.register_instruction(0xF030)
+            }
+            (":=", Some("long")) => {
+                self.next()?;
+                self.instruction(0xF000)?;
+                let token = self.next()?;
+                match self.known_value(&token) {
+                    Some(address) if (0..=0xFFFF).contains(&address) => {
+                        self.instruction(address as u16)
+                    }
+                    Some(address) => Err(format!("Address {} out of range", address)),
+                    None => {
+                        self.reference(token, FixupKind::Long);
+                        self.instruction(0x0000)
+                    }
+                }
+            }
+            (":=", _) => self.address_instruction(0xA000),
+            (operator, _) => Err(format!("Unknown operator {:?} for i", operator)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<(u8, Comparison, Option<Operand>), String> {
+        let x = self.register()?;
+        let comparison = match self.next()?.as_str() {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            "key" => return Ok((x, Comparison::Key, None)),
+            "-key" => return Ok((x, Comparison::NotKey, None)),
+            other => return Err(format!("Unknown comparison {:?}", other)),
+        };
+        Ok((x, comparison, Some(self.operand()?)))
+    }
+
+    // Instructions skipping the next one when the condition is true, or when it is false.
+    // Ordering comparisons go through VF, set to 1 by a subtraction without borrow.
+    fn skip(
+        &mut self,
+        (x, comparison, operand): (u8, Comparison, Option<Operand>),
+        when: bool,
+    ) -> Result<(), String> {
+        let x16 = (x as u16) << 8;
+        let equal = |negate: bool| -> u16 {
+            match (&operand, negate) {
+                (Some(Operand::Byte(value)), false) => 0x3000 | x16 | *value as u16,
+                (Some(Operand::Byte(value)), true) => 0x4000 | x16 | *value as u16,
+                (Some(Operand::Register(y)), false) => 0x5000 | x16 | (*y as u16) << 4,
+                (Some(Operand::Register(y)), true) => 0x9000 | x16 | (*y as u16) << 4,
+                (None, _) => unreachable!(),
+            }
+        };
+        // VF set to 1 when x >= operand, or when operand >= x
+        let flag = |operand_first: bool| -> [u16; 2] {
+            let vf = (REGISTER_VF as u16) << 8;
+            match (&operand, operand_first) {
+                (Some(Operand::Register(y)), false) => {
+                    [0x8000 | vf | x16 >> 4, 0x8005 | vf | (*y as u16) << 4]
+                }
+                (Some(Operand::Register(y)), true) => {
+                    [0x8000 | vf | (*y as u16) << 4, 0x8005 | vf | x16 >> 4]
+                }
+                (Some(Operand::Byte(value)), false) => {
+                    [0x6000 | vf | *value as u16, 0x8007 | vf | x16 >> 4]
+                }
+                (Some(Operand::Byte(value)), true) => {
+                    [0x6000 | vf | *value as u16, 0x8005 | vf | x16 >> 4]
+                }
+                (None, _) => unreachable!(),
+            }
+        };
+        // Skips when VF is 0 and the condition holds, or when VF is 1
+        let (instructions, condition_on_zero) = match comparison {
+            Comparison::Equal => return self.instruction(equal(!when)),
+            Comparison::NotEqual => return self.instruction(equal(when)),
+            Comparison::Key => {
+                return self.instruction(0xE000 | x16 | if when { 0x9E } else { 0xA1 })
+            }
+            Comparison::NotKey => {
+                return self.instruction(0xE000 | x16 | if when { 0xA1 } else { 0x9E })
+            }
+            Comparison::Less => (flag(false), true),
+            Comparison::GreaterOrEqual => (flag(false), false),
+            Comparison::Greater => (flag(true), true),
+            Comparison::LessOrEqual => (flag(true), false),
+        };
+        for instruction in instructions {
+            self.instruction(instruction)?;
+        }
+        let vf = (REGISTER_VF as u16) << 8;
+        // 3F00 skips when VF is 0, 4F00 when it is 1
+        self.instruction(if when == condition_on_zero {
+            0x3000 | vf
+        } else {
+            0x4000 | vf
+        })
+    }
+
+    fn if_statement(&mut self) -> Result<(), String> {
+        let comparison = self.condition()?;
+        match self.next()?.as_str() {
+            // The next statement runs only when the condition holds
+            "then" => self.skip(comparison, false),
+            "begin" => {
+                self.skip(comparison, true)?;
+                self.flow.push(Flow::Begin(self.here));
+                self.instruction(0x1000)
+            }
+            other => Err(format!("Expected then or begin, found {:?}", other)),
+        }
+    }
+
+    fn directive(&mut self, directive: &str) -> Result<(), String> {
+        match directive {
+            ":const" => {
+                let name = self.next()?;
+                let value = self.value()?;
+                self.define_constant(name, value)
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.expect("{")?;
+                let value = self.calculation()?;
+                self.define_constant(name, value)
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+                Ok(())
+            }
+            ":org" => {
+                self.here = self.bounded(
+                    CHIP8_FIRST_BYTE_ADDRESS as i64,
+                    CHIP8_MEMORY_SIZE as i64 - 1,
+                )? as usize;
+                Ok(())
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.define_label(name, self.here + 1)
+            }
+            ":byte" => {
+                let value = self.value()?;
+                self.emit_value(value)
+            }
+            ":call" => {
+                let token = self.next()?;
+                match self.known_value(&token) {
+                    Some(address) => self.call(address),
+                    None => {
+                        self.reference(token, FixupKind::Address);
+                        self.instruction(0x2000)
+                    }
+                }
+            }
+            ":unpack" => {
+                let nibble = self.nibble()? as u8;
+                let token = self.next()?;
+                match self.known_value(&token) {
+                    Some(address) => {
+                        let high = (nibble << 4) | (address >> 8) as u8 & 0x0F;
+                        self.instruction(0x6000 | high as u16)?;
+                        self.instruction(0x6100 | address as u8 as u16)
+                    }
+                    None => {
+                        self.reference(token.clone(), FixupKind::UnpackHigh(nibble));
+                        self.instruction(0x6000)?;
+                        self.reference(token, FixupKind::UnpackLow);
+                        self.instruction(0x6100)
+                    }
+                }
+            }
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(|_| ())
+            }
+            ":macro" => self.define_macro(),
+            _ => Err(format!("Unsupported directive {}", directive)),
+        }
+    }
+
+    fn define_constant(&mut self, name: String, value: i64) -> Result<(), String> {
+        if self.labels.contains_key(&name) || self.is_register(&name) {
+            return Err(format!("Name {:?} is already defined", name));
+        }
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    // Expression up to the closing brace, with operators evaluated from right to left as in
+    // Octo, and parentheses
+    fn calculation(&mut self) -> Result<i64, String> {
+        let value = self.expression()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn expression(&mut self) -> Result<i64, String> {
+        let left = self.term()?;
+        match self.peek() {
+            Some("}" | ")") | None => Ok(left),
+            Some(_) => {
+                let operator = self.next()?;
+                let right = self.expression()?;
+                binary_operation(&operator, left, right)
+                    .ok_or(format!("Unknown operator {:?}", operator))?
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<i64, String> {
+        let token = self.next()?;
+        match token.as_str() {
+            "(" => {
+                let value = self.expression()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            "-" => Ok(self.term()?.wrapping_neg()),
+            "~" => Ok(!self.term()?),
+            "!" => Ok((self.term()? == 0) as i64),
+            "HERE" => Ok(self.here as i64),
+            _ => self
+                .known_value(&token)
+                .ok_or(format!("Unknown name {:?} in expression", token)),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.next()?;
+        let mut arguments = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            arguments.push(token);
+        }
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.tokens.pop_front().ok_or("Unterminated macro")?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { arguments, body });
+        Ok(())
+    }
+
+    // The body replaces the call, with the arguments substituted
+    fn expand_macro(&mut self, name: &str) -> Result<(), String> {
+        self.macro_expansions += 1;
+        if self.macro_expansions > MAX_MACRO_EXPANSIONS {
+            return Err(format!("Too many expansions of macro {:?}", name));
+        }
+        let argument_count = self.macros[name].arguments.len();
+        let mut values = Vec::new();
+        for _ in 0..argument_count {
+            values.push(self.next()?);
+        }
+        let line = self.line;
+        let definition = &self.macros[name];
+        let expanded: Vec<Token> = definition
+            .body
+            .iter()
+            .map(|token| {
+                let text = match definition
+                    .arguments
+                    .iter()
+                    .position(|argument| *argument == token.text)
+                {
+                    Some(index) => values[index].clone(),
+                    None => token.text.clone(),
+                };
+                Token { text, line }
+            })
+            .collect();
+        for token in expanded.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statements() {
+        let program = assemble(
+            "
+            # Draws a sprite
+            : main
+              clear
+              v0 := 5  v1 := v0  v1 += 2  v2 -= 1  v3 := random 0x0F
+              i := sprite
+              sprite v0 v1 3
+              v0 := key
+              delay := v0
+              bcd v2
+              save v2
+              subroutine
+              jump main
+            : subroutine
+              v4 <<= v4
+              return
+            : sprite
+              0b11100000 0xA0 -32
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            vec![
+                0x00, 0xE0, 0x60, 0x05, 0x81, 0x00, 0x71, 0x02, 0x72, 0xFF, 0xC3, 0x0F, 0xA2, 0x20,
+                0xD0, 0x13, 0xF0, 0x0A, 0xF0, 0x15, 0xF2, 0x33, 0xF2, 0x55, 0x22, 0x1C, 0x12, 0x00,
+                0x84, 0x4E, 0x00, 0xEE, 0xE0, 0xA0, 0xE0,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jump_to_main() {
+        assert_eq!(
+            assemble(": data 1 2 : main jump main").unwrap(),
+            vec![0x12, 0x04, 0x01, 0x02, 0x12, 0x04]
+        );
+        assert_eq!(
+            assemble(": f ; : main f").unwrap(),
+            vec![0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]
+        );
+        assert_eq!(
+            assemble(": main\n  g").unwrap_err(),
+            "line 2: Undefined name \"g\""
+        );
+        assert_eq!(
+            assemble(": f ;").unwrap_err(),
+            "line 1: This program is missing a 'main' label"
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let program = assemble(
+            ": main
+              if v0 == 3 then v1 := 1
+              if v0 != v1 begin v2 := 2 else v2 := 3 end
+              loop
+                v0 += 1
+                while v0 < 10
+              again",
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            vec![
+                0x40, 0x03, 0x61, 0x01, // if then
+                0x90, 0x10, 0x12, 0x0C, 0x62, 0x02, 0x12, 0x0E, 0x62, 0x03, // if begin
+                0x70, 0x01, 0x6F, 0x0A, 0x8F, 0x07, 0x3F, 0x00, 0x12, 0x1A, 0x12,
+                0x0E, // loop
+            ]
+        );
+        assert!(assemble(": main again").is_err());
+        assert!(assemble(": main if v0 == 1 begin").is_err());
+    }
+
+    #[test]
+    fn test_directives() {
+        let program = assemble(
+            ":const SPEED 3
+             :alias speed v5
+             :calc DOUBLE { SPEED * 2 + 1 }
+             :macro twice register { register += SPEED register += SPEED }
+             : main
+               speed := DOUBLE
+               twice speed
+               :unpack 0xA table
+               :next target v6 := 0
+             :org 0x300
+             : table
+               :byte { 1 + 2 }",
+        )
+        .unwrap();
+        // Operators are evaluated from right to left, DOUBLE is 3 * (2 + 1)
+        assert_eq!(
+            program[..14],
+            [0x65, 0x09, 0x75, 0x03, 0x75, 0x03, 0x60, 0xA3, 0x61, 0x00, 0x66, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(program[0x100], 3);
+        assert_eq!(program.len(), 0x101);
+        assert!(assemble(":stringmode x 0 { }").is_err());
+        assert_eq!(
+            assemble(": main\nv0 := 256").unwrap_err(),
+            "line 2: Value 256 out of range"
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use crate::emulator::CHIP8_MAX_PROGRAM_SIZE;
+
+use std::io::{Error, ErrorKind};
+
+pub const PATCH_EXTENSIONS: [&str; 2] = ["ips", "bps"];
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_END: &[u8] = b"EOF";
+const IPS_MAX_OFFSET: usize = 0xFFFFFF;
+const IPS_MAX_RECORD_SIZE: usize = 0xFFFF;
+const BPS_MAGIC: &[u8] = b"BPS1";
+const BPS_FOOTER_SIZE: usize = 12;
+
+fn invalid_patch(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// Applies an IPS or a BPS patch, depending on its header
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> std::io::Result<Vec<u8>> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(invalid_patch("Unknown patch format"))
+    }
+}
+
+// Reads bytes from a patch, failing instead of panicking on truncated patches
+struct PatchReader<'a> {
+    patch: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn read(&mut self, length: usize) -> std::io::Result<&'a [u8]> {
+        if length > self.patch.len() - self.position {
+            return Err(invalid_patch("Truncated patch"));
+        }
+        let bytes = &self.patch[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn read_big_endian(&mut self, length: usize) -> std::io::Result<usize> {
+        Ok(self
+            .read(length)?
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as usize))
+    }
+
+    // BPS numbers are variable-length, 7 bits per byte, with the last byte flagged
+    fn read_number(&mut self) -> std::io::Result<usize> {
+        let too_large = || invalid_patch("Number too large in patch");
+        let mut number: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.read(1)?[0] as usize;
+            number = (byte & 0x7F)
+                .checked_mul(shift)
+                .and_then(|value| number.checked_add(value))
+                .ok_or_else(too_large)?;
+            if byte & 0x80 != 0 {
+                return Ok(number);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(too_large)?;
+            number = number.checked_add(shift).ok_or_else(too_large)?;
+        }
+    }
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut reader = PatchReader { patch, position: 0 };
+    if reader.read(IPS_MAGIC.len())? != IPS_MAGIC {
+        return Err(invalid_patch("Not an IPS patch"));
+    }
+    let mut patched = rom.to_vec();
+    loop {
+        let offset_bytes = reader.read(3)?;
+        if offset_bytes == IPS_END {
+            break;
+        }
+        let offset = offset_bytes
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as usize);
+        let size = reader.read_big_endian(2)?;
+        // Records with a null size are run-length encoded
+        let data = if size == 0 {
+            let run_length = reader.read_big_endian(2)?;
+            vec![reader.read(1)?[0]; run_length]
+        } else {
+            reader.read(size)?.to_vec()
+        };
+        if patched.len() < offset + data.len() {
+            patched.resize(offset + data.len(), 0);
+        }
+        patched[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    // Optional truncation extension
+    if reader.position + 3 == patch.len() {
+        let size = reader.read_big_endian(3)?;
+        patched.truncate(size);
+    }
+    Ok(patched)
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> std::io::Result<Vec<u8>> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_SIZE || !patch.starts_with(BPS_MAGIC) {
+        return Err(invalid_patch("Not a BPS patch"));
+    }
+    let footer = &patch[patch.len() - BPS_FOOTER_SIZE..];
+    let source_checksum = u32::from_le_bytes(footer[0..4].try_into().unwrap());
+    let target_checksum = u32::from_le_bytes(footer[4..8].try_into().unwrap());
+    let patch_checksum = u32::from_le_bytes(footer[8..12].try_into().unwrap());
+    if crc32fast::hash(&patch[..patch.len() - 4]) != patch_checksum {
+        return Err(invalid_patch(
+            "BPS patch checksum mismatch, the patch is corrupted",
+        ));
+    }
+    if crc32fast::hash(rom) != source_checksum {
+        return Err(invalid_patch(
+            "BPS source checksum mismatch, wrong ROM for this patch",
+        ));
+    }
+
+    let actions_end = patch.len() - BPS_FOOTER_SIZE;
+    let mut reader = PatchReader {
+        patch: &patch[..actions_end],
+        position: BPS_MAGIC.len(),
+    };
+    let source_size = reader.read_number()?;
+    let target_size = reader.read_number()?;
+    let metadata_size = reader.read_number()?;
+    reader.read(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(invalid_patch("BPS source size mismatch"));
+    }
+    // Patched ROMs must fit in memory, which also bounds what the patch allocates
+    if target_size > CHIP8_MAX_PROGRAM_SIZE {
+        return Err(invalid_patch("BPS target too large for a CHIP-8 program"));
+    }
+
+    let mut target: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_relative_offset: usize = 0;
+    let mut target_relative_offset: usize = 0;
+    while reader.position < actions_end {
+        let data = reader.read_number()?;
+        let length = (data >> 2) + 1;
+        let output_offset = target.len();
+        if length > target_size - output_offset {
+            return Err(invalid_patch("BPS write outside of the target"));
+        }
+        match data & 0x03 {
+            // SourceRead
+            0 => target.extend_from_slice(
+                rom.get(output_offset..output_offset + length)
+                    .ok_or_else(|| invalid_patch("BPS read outside of the source"))?,
+            ),
+            // TargetRead
+            1 => target.extend_from_slice(reader.read(length)?),
+            // SourceCopy
+            2 => {
+                source_relative_offset =
+                    apply_relative_offset(source_relative_offset, reader.read_number()?)?;
+                target.extend_from_slice(
+                    source_relative_offset
+                        .checked_add(length)
+                        .and_then(|end| rom.get(source_relative_offset..end))
+                        .ok_or_else(|| invalid_patch("BPS copy outside of the source"))?,
+                );
+                source_relative_offset += length;
+            }
+            // TargetCopy, byte per byte as the copied area may overlap the output
+            _ => {
+                target_relative_offset =
+                    apply_relative_offset(target_relative_offset, reader.read_number()?)?;
+                for _ in 0..length {
+                    let byte = *target
+                        .get(target_relative_offset)
+                        .ok_or_else(|| invalid_patch("BPS copy outside of the target"))?;
+                    target.push(byte);
+                    target_relative_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(invalid_patch("BPS target size mismatch"));
+    }
+    if crc32fast::hash(&target) != target_checksum {
+        return Err(invalid_patch("BPS target checksum mismatch"));
+    }
+    Ok(target)
+}
+
+// Relative offsets are encoded with their sign in the lowest bit
+fn apply_relative_offset(offset: usize, encoded: usize) -> std::io::Result<usize> {
+    let delta = encoded >> 1;
+    let result = if encoded & 1 == 1 {
+        offset.checked_sub(delta)
+    } else {
+        offset.checked_add(delta)
+    };
+    result.ok_or_else(|| invalid_patch("Invalid BPS relative offset"))
+}
+
+pub fn create_ips(source: &[u8], target: &[u8]) -> std::io::Result<Vec<u8>> {
+    if target.len() > IPS_MAX_OFFSET {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "ROM too large for an IPS patch",
+        ));
+    }
+    let mut patch = IPS_MAGIC.to_vec();
+    let mut offset = 0;
+    while offset < target.len() {
+        if source.get(offset) == Some(&target[offset]) {
+            offset += 1;
+            continue;
+        }
+        // An offset reading as "EOF" would end the patch, start one byte earlier instead
+        let mut start = offset;
+        if start == 0x454F46 {
+            start -= 1;
+        }
+        let mut end = offset;
+        while end < target.len()
+            && end - start < IPS_MAX_RECORD_SIZE
+            && source.get(end) != Some(&target[end])
+        {
+            end += 1;
+        }
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&target[start..end]);
+        offset = end;
+    }
+    patch.extend_from_slice(IPS_END);
+    if target.len() < source.len() {
+        patch.extend_from_slice(&(target.len() as u32).to_be_bytes()[1..]);
+    }
+    Ok(patch)
+}
+
+fn write_number(patch: &mut Vec<u8>, mut number: usize) {
+    loop {
+        let byte = (number & 0x7F) as u8;
+        number >>= 7;
+        if number == 0 {
+            patch.push(0x80 | byte);
+            return;
+        }
+        patch.push(byte);
+        number -= 1;
+    }
+}
+
+// Only uses SourceRead and TargetRead actions, which is enough for ROM fixes and translations
+pub fn create_bps(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = BPS_MAGIC.to_vec();
+    write_number(&mut patch, source.len());
+    write_number(&mut patch, target.len());
+    write_number(&mut patch, 0);
+
+    let mut offset = 0;
+    while offset < target.len() {
+        let unchanged = source.get(offset) == Some(&target[offset]);
+        let mut end = offset + 1;
+        while end < target.len() && (source.get(end) == Some(&target[end])) == unchanged {
+            end += 1;
+        }
+        let length = end - offset;
+        if unchanged {
+            write_number(&mut patch, (length - 1) << 2);
+        } else {
+            write_number(&mut patch, ((length - 1) << 2) | 1);
+            patch.extend_from_slice(&target[offset..end]);
+        }
+        offset = end;
+    }
+
+    patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+    patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+    let patch_checksum = crc32fast::hash(&patch);
+    patch.extend_from_slice(&patch_checksum.to_le_bytes());
+    patch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_ips() {
+        let patch = [
+            b'P', b'A', b'T', b'C', b'H', // Header
+            0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB, // Two bytes at 1
+            0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x03, 0xCC, // Three 0xCC at 5
+            b'E', b'O', b'F',
+        ];
+        assert_eq!(
+            apply_patch(&[0x00, 0x01, 0x02, 0x03], &patch).unwrap(),
+            vec![0x00, 0xAA, 0xBB, 0x03, 0x00, 0xCC, 0xCC, 0xCC]
+        );
+        assert!(apply_patch(&[0x00], &patch[..10]).is_err());
+    }
+
+    #[test]
+    fn test_ips_round_trip() {
+        let source = std::fs::read("roms/BRIX").unwrap();
+        let mut target = source.clone();
+        target[0x10] ^= 0xFF;
+        target[0x11] ^= 0xFF;
+        target[0x50] = 0x42;
+        target.extend_from_slice(&[0x12, 0x00]);
+        let patch = create_ips(&source, &target).unwrap();
+        assert_eq!(apply_ips(&source, &patch).unwrap(), target);
+
+        let shorter = &source[..100];
+        let patch = create_ips(&source, shorter).unwrap();
+        assert_eq!(apply_ips(&source, &patch).unwrap(), shorter);
+    }
+
+    #[test]
+    fn test_bps_numbers() {
+        for number in [0, 1, 127, 128, 129, 16383, 16384, 1 << 20] {
+            let mut encoded = Vec::new();
+            write_number(&mut encoded, number);
+            let mut reader = PatchReader {
+                patch: &encoded,
+                position: 0,
+            };
+            assert_eq!(reader.read_number().unwrap(), number);
+            assert_eq!(reader.position, encoded.len());
+        }
+    }
+
+    #[test]
+    fn test_bps_round_trip() {
+        let source = std::fs::read("roms/INVADERS").unwrap();
+        let mut target = source.clone();
+        target[0x20] ^= 0xFF;
+        target[0x200..0x210].fill(0x00);
+        target.truncate(1000);
+        let patch = create_bps(&source, &target);
+        assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+
+        // Wrong source ROM
+        assert!(apply_bps(&source[1..], &patch).is_err());
+
+        // Corrupted patch
+        let mut corrupted = patch.clone();
+        corrupted[8] ^= 0x01;
+        assert!(apply_bps(&source, &corrupted).is_err());
+    }
+
+    #[test]
+    fn test_bps_copy_actions() {
+        // Target "ABAB" built from source "AB": SourceRead 2, then TargetCopy 2 from offset 0
+        let source = b"AB";
+        let target = b"ABAB";
+        let mut patch = BPS_MAGIC.to_vec();
+        write_number(&mut patch, 2);
+        write_number(&mut patch, 4);
+        write_number(&mut patch, 0);
+        write_number(&mut patch, 1 << 2);
+        write_number(&mut patch, (1 << 2) | 3);
+        write_number(&mut patch, 0);
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        let patch_checksum = crc32fast::hash(&patch);
+        patch.extend_from_slice(&patch_checksum.to_le_bytes());
+        assert_eq!(apply_bps(source, &patch).unwrap(), target.to_vec());
+    }
+
+    // BPS patch from its actions, with valid checksums
+    fn bps_patch(actions: &[u8], source: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend_from_slice(actions);
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&0u32.to_le_bytes());
+        let patch_checksum = crc32fast::hash(&patch);
+        patch.extend_from_slice(&patch_checksum.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_bps_overflows() {
+        let source = b"AB";
+        let error = |actions: &[u8]| {
+            apply_bps(source, &bps_patch(actions, source))
+                .unwrap_err()
+                .to_string()
+        };
+        let mut huge = Vec::new();
+        write_number(&mut huge, usize::MAX);
+
+        // Numbers longer than usize
+        let mut actions = vec![0x7F; 12];
+        actions.push(0xFF);
+        assert_eq!(error(&actions), "Number too large in patch");
+
+        // Metadata larger than the patch
+        let mut actions = vec![0x82, 0x82];
+        actions.extend_from_slice(&huge);
+        assert_eq!(error(&actions), "Truncated patch");
+
+        // Target larger than the program area
+        let mut actions = vec![0x82];
+        actions.extend_from_slice(&huge);
+        actions.push(0x80);
+        assert_eq!(error(&actions), "BPS target too large for a CHIP-8 program");
+
+        // TargetCopy longer than the target
+        let mut actions = vec![0x82, 0x84, 0x80];
+        write_number(&mut actions, 1 << 2);
+        write_number(&mut actions, (1000 << 2) | 3);
+        write_number(&mut actions, 0);
+        assert_eq!(error(&actions), "BPS write outside of the target");
+
+        // SourceCopy from an offset far outside the source
+        let mut actions = vec![0x82, 0x84, 0x80];
+        write_number(&mut actions, 2);
+        write_number(&mut actions, usize::MAX & !1);
+        assert_eq!(error(&actions), "BPS copy outside of the source");
+    }
+}
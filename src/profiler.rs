@@ -0,0 +1,215 @@
+use crate::disassembler::{disassemble, disassemble_instruction};
+use crate::emulator::{OpCode, CHIP8_MEMORY_SIZE};
+use crate::symbols::Symbols;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// Number of rows in the table of the most executed addresses
+const HOTTEST_ADDRESSES: usize = 20;
+
+#[derive(Clone, Copy, Default)]
+struct SubroutineStatistics {
+    calls: u64,
+    // Instructions executed between the call and the matching return, callees included
+    instructions: u64,
+}
+
+// Counts executed instructions per address, per kind of instruction and per subroutine.
+// Time is measured in executed instructions, which is what the tick rate budgets.
+pub struct Profiler {
+    total_instructions: u64,
+    address_counts: Vec<u64>,
+    kind_counts: HashMap<&'static str, u64>,
+    subroutines: HashMap<usize, SubroutineStatistics>,
+    // Subroutines being run, with the instruction count when they were called
+    active_calls: Vec<(usize, u64)>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            total_instructions: 0,
+            address_counts: vec![0; CHIP8_MEMORY_SIZE],
+            kind_counts: HashMap::new(),
+            subroutines: HashMap::new(),
+            active_calls: Vec::new(),
+        }
+    }
+
+    // Must be called before the instruction at the given address is processed
+    pub(crate) fn record_instruction(&mut self, address: usize, opcode: &OpCode) {
+        self.total_instructions += 1;
+        self.address_counts[address] += 1;
+        *self.kind_counts.entry(opcode.kind()).or_insert(0) += 1;
+        match opcode {
+            OpCode::OC_2NNN(nnn) => {
+                self.subroutines.entry(*nnn).or_default().calls += 1;
+                self.active_calls.push((*nnn, self.total_instructions));
+            }
+            OpCode::OC_00EE => {
+                if let Some((subroutine, called_at)) = self.active_calls.pop() {
+                    // Recursive calls are only timed once, by the outermost call
+                    if self
+                        .active_calls
+                        .iter()
+                        .all(|(active, _)| *active != subroutine)
+                    {
+                        self.subroutines.entry(subroutine).or_default().instructions +=
+                            self.total_instructions - called_at;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn execution_count(&self, address: usize) -> u64 {
+        self.address_counts[address]
+    }
+
+    fn percentage(&self, count: u64) -> f64 {
+        100.0 * count as f64 / self.total_instructions.max(1) as f64
+    }
+
+    // Tables of the most executed addresses, instruction kinds and subroutines
+    pub fn report(&self, memory: &[u8]) -> String {
+        let mut report = format!("Instructions executed: {}\n", self.total_instructions);
+
+        let mut addresses: Vec<usize> = (0..CHIP8_MEMORY_SIZE)
+            .filter(|address| self.address_counts[*address] > 0)
+            .collect();
+        addresses.sort_by_key(|address| std::cmp::Reverse(self.address_counts[*address]));
+        report += "\nHottest addresses\nAddress       Count      %  Instruction\n";
+        for address in addresses.iter().take(HOTTEST_ADDRESSES) {
+            let count = self.address_counts[*address];
+            let raw_opcode = ((memory[*address] as u16) << 8) | memory[*address + 1] as u16;
+            writeln!(
+                report,
+                "0x{:03X}  {:>12} {:>6.2}  {}",
+                address,
+                count,
+                self.percentage(count),
+                disassemble_instruction(raw_opcode, &Symbols::default())
+            )
+            .unwrap();
+        }
+
+        let mut kinds: Vec<(&'static str, u64)> = self.kind_counts.clone().into_iter().collect();
+        kinds.sort_by_key(|(kind, count)| (std::cmp::Reverse(*count), *kind));
+        report += "\nInstruction kinds\nKind         Count      %\n";
+        for (kind, count) in kinds {
+            writeln!(
+                report,
+                "{}  {:>12} {:>6.2}",
+                kind,
+                count,
+                self.percentage(count)
+            )
+            .unwrap();
+        }
+
+        let mut subroutines: Vec<(usize, SubroutineStatistics)> =
+            self.subroutines.clone().into_iter().collect();
+        subroutines.sort_by_key(|(address, statistics)| {
+            (std::cmp::Reverse(statistics.instructions), *address)
+        });
+        report += "\nSubroutines, including callees\nAddress    Calls  Instructions      %\n";
+        for (address, statistics) in subroutines {
+            writeln!(
+                report,
+                "0x{:03X}  {:>9}  {:>12} {:>6.2}",
+                address,
+                statistics.calls,
+                statistics.instructions,
+                self.percentage(statistics.instructions)
+            )
+            .unwrap();
+        }
+        report
+    }
+
+    // Disassembly of memory[start..end] with the execution count of each instruction
+    pub fn annotated_disassembly(&self, memory: &[u8], start: usize, end: usize) -> String {
+        let mut output = String::new();
+        for line in disassemble(memory, start, end, &Symbols::default()) {
+            let count = match self.execution_count(line.address) {
+                0 => String::new(),
+                count => count.to_string(),
+            };
+            writeln!(
+                output,
+                "0x{:03X}  {:04X}  {:>12}  {}",
+                line.address, line.raw_opcode, count, line.text
+            )
+            .unwrap();
+        }
+        output
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, CHIP8_FIRST_BYTE_ADDRESS};
+
+    // Calls a subroutine at 0x206 twice, which itself calls another one at 0x20A
+    const PROGRAM: [u8; 14] = [
+        0x22, 0x06, 0x22, 0x06, 0x12, 0x04, 0x22, 0x0A, 0x00, 0xEE, 0x60, 0x01, 0x00, 0xEE,
+    ];
+
+    fn profile(instructions: usize) -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator.profiler = Some(Profiler::new());
+        emulator.load_program(&PROGRAM);
+        for _ in 0..instructions {
+            emulator.process_next_instruction();
+        }
+        emulator
+    }
+
+    #[test]
+    fn test_counts() {
+        let emulator = profile(12);
+        let profiler = emulator.profiler.as_ref().unwrap();
+        assert_eq!(profiler.total_instructions, 12);
+        assert_eq!(profiler.execution_count(CHIP8_FIRST_BYTE_ADDRESS), 1);
+        assert_eq!(profiler.execution_count(0x206), 2);
+        assert_eq!(profiler.execution_count(0x204), 2);
+        assert_eq!(profiler.kind_counts["2NNN"], 4);
+        assert_eq!(profiler.kind_counts["00EE"], 4);
+    }
+
+    #[test]
+    fn test_subroutines_include_callees() {
+        let emulator = profile(12);
+        let profiler = emulator.profiler.as_ref().unwrap();
+        let outer = profiler.subroutines[&0x206];
+        let inner = profiler.subroutines[&0x20A];
+        assert_eq!((outer.calls, outer.instructions), (2, 8));
+        assert_eq!((inner.calls, inner.instructions), (2, 4));
+    }
+
+    #[test]
+    fn test_report() {
+        let emulator = profile(12);
+        let profiler = emulator.profiler.as_ref().unwrap();
+        let report = profiler.report(emulator.memory());
+        assert!(report.starts_with("Instructions executed: 12\n"));
+        assert!(report.contains("0x206             2  16.67  CALL 0x20A\n"));
+        assert!(report.contains("0x206          2             8  66.67\n"));
+
+        let disassembly =
+            profiler.annotated_disassembly(emulator.memory(), CHIP8_FIRST_BYTE_ADDRESS, 0x20E);
+        assert_eq!(
+            disassembly.lines().next(),
+            Some("0x200  2206             1  CALL 0x206")
+        );
+    }
+}
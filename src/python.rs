@@ -0,0 +1,341 @@
+// Python bindings of the emulator core, in the chip8 extension module:
+//
+//     import chip8, numpy
+//     emulator = chip8.Emulator.load("roms/BLINKY")
+//     emulator.run_frame()
+//     screen = numpy.asarray(emulator.screen)  # 32 rows of 64 pixels, 0 or 1
+//     numpy.asarray(emulator.memory)[0x300] = 42
+use crate::disassembler::{disassemble, disassemble_instruction};
+use crate::emulator::{
+    check_program_size, Emulator, Snapshot, CHIP8_MEMORY_SIZE, CHIP8_NUMBER_KEYS,
+    CHIP8_NUMBER_REGISTERS, CHIP8_SCREEN_HEIGHT, CHIP8_SCREEN_WIDTH,
+};
+use crate::frontend::{load_headless, INSTRUCTIONS_PER_FRAME};
+use crate::symbols::Symbols;
+
+use pyo3::exceptions::{PyBufferError, PyValueError};
+use pyo3::ffi;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::ffi::{c_char, c_int, c_void};
+use std::path::PathBuf;
+
+static MEMORY_SHAPE: [isize; 1] = [CHIP8_MEMORY_SIZE as isize];
+static MEMORY_STRIDES: [isize; 1] = [1];
+static SCREEN_SHAPE: [isize; 2] = [CHIP8_SCREEN_HEIGHT as isize, CHIP8_SCREEN_WIDTH as isize];
+static SCREEN_STRIDES: [isize; 2] = [CHIP8_SCREEN_WIDTH as isize, 1];
+
+fn check(value: usize, limit: usize, what: &str) -> PyResult<usize> {
+    if value < limit {
+        Ok(value)
+    } else {
+        Err(PyValueError::new_err(format!("Invalid {} {}", what, value)))
+    }
+}
+
+#[pyclass(name = "Emulator", module = "chip8")]
+struct PyEmulator {
+    emulator: Emulator,
+    // Used by run_frame when it is not given a number of instructions
+    #[pyo3(get, set)]
+    instructions_per_frame: u32,
+}
+
+#[pymethods]
+impl PyEmulator {
+    #[new]
+    fn new() -> Self {
+        let mut emulator = Emulator::new();
+        emulator.trace = false;
+        Self {
+            emulator,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+        }
+    }
+
+    // ROM file with its quirks and speed from the file or the ROM database
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        let (mut emulator, _, instructions_per_frame) =
+            load_headless(&path, None).map_err(PyValueError::new_err)?;
+        emulator.trace = false;
+        Ok(Self {
+            emulator,
+            instructions_per_frame,
+        })
+    }
+
+    // Resets the emulator, then loads the program at 0x200
+    fn load_program(&mut self, program: &[u8]) -> PyResult<()> {
+        check_program_size(program).map_err(PyValueError::new_err)?;
+        self.emulator.reset();
+        self.emulator.load_program(program);
+        Ok(())
+    }
+
+    // Runs one instruction
+    fn step(&mut self) {
+        self.emulator.process_next_instruction();
+    }
+
+    #[pyo3(signature = (instructions=None))]
+    fn run_frame(&mut self, instructions: Option<u32>) -> u32 {
+        self.emulator
+            .run_frame(instructions.unwrap_or(self.instructions_per_frame))
+    }
+
+    fn set_key(&mut self, key: usize, pressed: bool) -> PyResult<()> {
+        let key = check(key, CHIP8_NUMBER_KEYS, "key")?;
+        self.emulator.input_key(key as u8, pressed);
+        Ok(())
+    }
+
+    fn key_pressed(&self, key: usize) -> PyResult<bool> {
+        let key = check(key, CHIP8_NUMBER_KEYS, "key")?;
+        Ok(self.emulator.key_pressed(key as u8))
+    }
+
+    // Writable view of the 4096 bytes of memory
+    #[getter]
+    fn memory(slf: Bound<'_, Self>) -> MemoryView {
+        MemoryView {
+            owner: slf.unbind(),
+        }
+    }
+
+    // Read-only view of the screen, 32 rows of 64 pixels which are 0 or 1
+    #[getter]
+    fn screen(slf: Bound<'_, Self>) -> ScreenView {
+        ScreenView {
+            owner: slf.unbind(),
+        }
+    }
+
+    #[getter]
+    fn registers<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.emulator.registers())
+    }
+
+    fn set_register(&mut self, x: usize, value: u8) -> PyResult<()> {
+        let x = check(x, CHIP8_NUMBER_REGISTERS, "register")?;
+        self.emulator.set_register(x, value);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_pc(&self) -> usize {
+        self.emulator.program_counter()
+    }
+
+    #[setter]
+    fn set_pc(&mut self, address: usize) -> PyResult<()> {
+        let address = check(address, CHIP8_MEMORY_SIZE - 1, "address")?;
+        self.emulator.set_program_counter(address);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_index(&self) -> usize {
+        self.emulator.index_register()
+    }
+
+    #[setter]
+    fn set_index(&mut self, address: usize) -> PyResult<()> {
+        let address = check(address, CHIP8_MEMORY_SIZE, "address")?;
+        self.emulator.set_index_register(address);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_delay_timer(&self) -> u8 {
+        self.emulator.system_clock
+    }
+
+    #[setter]
+    fn set_delay_timer(&mut self, value: u8) {
+        self.emulator.system_clock = value;
+    }
+
+    #[getter]
+    fn get_sound_timer(&self) -> u8 {
+        self.emulator.sound_clock
+    }
+
+    #[setter]
+    fn set_sound_timer(&mut self, value: u8) {
+        self.emulator.sound_clock = value;
+    }
+
+    #[getter]
+    fn waiting_for_key(&self) -> bool {
+        self.emulator.waiting_for_key
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.emulator.seed_rng(seed);
+    }
+
+    // The same bytes as the state files of scripts
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.emulator.snapshot().to_bytes())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        let snapshot = Snapshot::from_bytes(state).map_err(PyValueError::new_err)?;
+        self.emulator.restore(&snapshot);
+        Ok(())
+    }
+
+    // (address, opcode, mnemonic) of every instruction between start and end
+    fn disassemble(&self, start: usize, end: usize) -> Vec<(usize, u16, String)> {
+        disassemble(self.emulator.memory(), start, end, &self.emulator.symbols)
+            .into_iter()
+            .map(|line| (line.address, line.raw_opcode, line.text))
+            .collect()
+    }
+
+    // Names of addresses used by the disassembly, from an Octo JSON or "ADDR NAME" file
+    fn load_symbols(&mut self, path: PathBuf) -> PyResult<()> {
+        self.emulator.symbols = Symbols::load(&path).map_err(PyValueError::new_err)?;
+        Ok(())
+    }
+}
+
+// Fills a buffer view of bytes in C order. The owner is kept alive as long as the view is used.
+unsafe fn fill_view(
+    view: *mut ffi::Py_buffer,
+    flags: c_int,
+    data: *mut u8,
+    shape: &'static [isize],
+    strides: &'static [isize],
+    readonly: bool,
+    owner: Bound<'_, PyAny>,
+) -> PyResult<()> {
+    if view.is_null() {
+        return Err(PyBufferError::new_err("View is null"));
+    }
+    if readonly && (flags & ffi::PyBUF_WRITABLE) == ffi::PyBUF_WRITABLE {
+        return Err(PyBufferError::new_err("Object is not writable"));
+    }
+    let nd = (flags & ffi::PyBUF_ND) == ffi::PyBUF_ND;
+    unsafe {
+        (*view).obj = owner.into_ptr();
+        (*view).buf = data as *mut c_void;
+        (*view).len = shape.iter().product();
+        (*view).readonly = readonly as c_int;
+        (*view).itemsize = 1;
+        (*view).format = if (flags & ffi::PyBUF_FORMAT) == ffi::PyBUF_FORMAT {
+            c"B".as_ptr() as *mut c_char
+        } else {
+            std::ptr::null_mut()
+        };
+        // Without shape, consumers see a single dimension of bytes
+        (*view).ndim = if nd { shape.len() as c_int } else { 1 };
+        (*view).shape = if nd {
+            shape.as_ptr() as *mut isize
+        } else {
+            std::ptr::null_mut()
+        };
+        (*view).strides = if (flags & ffi::PyBUF_STRIDES) == ffi::PyBUF_STRIDES {
+            strides.as_ptr() as *mut isize
+        } else {
+            std::ptr::null_mut()
+        };
+        (*view).suboffsets = std::ptr::null_mut();
+        (*view).internal = std::ptr::null_mut();
+    }
+    Ok(())
+}
+
+// Views share the bytes of the emulator, which never move while it is alive. Python only runs
+// one thread at a time, so nothing else uses the emulator while the view is written.
+#[pyclass(frozen, module = "chip8")]
+struct MemoryView {
+    owner: Py<PyEmulator>,
+}
+
+#[pymethods]
+impl MemoryView {
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        let data = slf
+            .get()
+            .owner
+            .bind(slf.py())
+            .try_borrow_mut()?
+            .emulator
+            .memory_mut()
+            .as_mut_ptr();
+        unsafe {
+            fill_view(
+                view,
+                flags,
+                data,
+                &MEMORY_SHAPE,
+                &MEMORY_STRIDES,
+                false,
+                slf.into_any(),
+            )
+        }
+    }
+
+    fn __len__(&self) -> usize {
+        CHIP8_MEMORY_SIZE
+    }
+}
+
+#[pyclass(frozen, module = "chip8")]
+struct ScreenView {
+    owner: Py<PyEmulator>,
+}
+
+#[pymethods]
+impl ScreenView {
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        let data = slf
+            .get()
+            .owner
+            .bind(slf.py())
+            .try_borrow_mut()?
+            .emulator
+            .screen
+            .as_mut_ptr() as *mut u8;
+        unsafe {
+            fill_view(
+                view,
+                flags,
+                data,
+                &SCREEN_SHAPE,
+                &SCREEN_STRIDES,
+                true,
+                slf.into_any(),
+            )
+        }
+    }
+
+    fn __len__(&self) -> usize {
+        CHIP8_SCREEN_HEIGHT
+    }
+}
+
+// Mnemonic of a single opcode, such as "LD V1, 0x2A"
+#[pyfunction(name = "disassemble_instruction")]
+fn disassemble_opcode(opcode: u16) -> String {
+    disassemble_instruction(opcode, &Symbols::default())
+}
+
+#[pymodule]
+fn chip8(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyEmulator>()?;
+    module.add_class::<MemoryView>()?;
+    module.add_class::<ScreenView>()?;
+    module.add_function(wrap_pyfunction!(disassemble_opcode, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+}
+
+// Guesses the platform from instructions that only exist in its extensions.
+// Data is not distinguished from code, so this is only a heuristic.
+pub fn detect_platform(program: &[u8]) -> Platform {
+    let mut platform = Platform::Chip8;
+    for chunk in program.chunks_exact(2) {
+        let opcode = ((chunk[0] as u16) << 8) | chunk[1] as u16;
+        let is_xo_chip = opcode == 0xF000
+            || opcode == 0xF002
+            || opcode & 0xF0FF == 0xF001
+            || opcode & 0xF00F == 0x5002
+            || opcode & 0xF00F == 0x5003;
+        let is_super_chip = matches!(opcode, 0x00C1..=0x00CF | 0x00FB..=0x00FF)
+            || opcode & 0xF0FF == 0xF030
+            || opcode & 0xF0FF == 0xF075
+            || opcode & 0xF0FF == 0xF085;
+        if is_xo_chip {
+            return Platform::XoChip;
+        }
+        if is_super_chip {
+            platform = Platform::SuperChip;
+        }
+    }
+    platform
+}
+
+// Polls the modification time of a ROM file, to restart the emulator when it is rebuilt
+pub struct RomWatcher {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+}
+
+impl RomWatcher {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            last_modified: modification_time(path),
+        }
+    }
+
+    pub fn has_changed(&mut self) -> bool {
+        let modified = modification_time(&self.path);
+        if modified.is_some() && modified != self.last_modified {
+            self.last_modified = modified;
+            return true;
+        }
+        false
+    }
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_platform() {
+        assert_eq!(detect_platform(&[0x00, 0xE0, 0x12, 0x00]), Platform::Chip8);
+        assert_eq!(
+            detect_platform(&[0x00, 0xFF, 0x12, 0x00]),
+            Platform::SuperChip
+        );
+        assert_eq!(detect_platform(&[0x00, 0xFF, 0xF0, 0x00]), Platform::XoChip);
+        assert_eq!(
+            detect_platform(&std::fs::read("roms/BLINKY").unwrap()),
+            Platform::Chip8
+        );
+    }
+
+    #[test]
+    fn test_rom_watcher() {
+        let path = std::env::temp_dir().join(format!("chip8_watcher_{}.ch8", std::process::id()));
+        std::fs::write(&path, [0x00, 0xE0]).unwrap();
+        let mut watcher = RomWatcher::new(&path);
+        assert!(!watcher.has_changed());
+
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        assert!(watcher.has_changed());
+        assert!(!watcher.has_changed());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!watcher.has_changed());
+    }
+}
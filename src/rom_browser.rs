@@ -0,0 +1,212 @@
+extern crate sdl2;
+use crate::emulator::CHIP8_MAX_PROGRAM_SIZE;
+use crate::hud;
+use crate::rom::{self, Platform};
+use crate::rom_database::RomDatabase;
+use crate::rom_loader::{ARCHIVE_EXTENSION, CARTRIDGE_EXTENSION, ROM_EXTENSIONS};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+use std::path::{Path, PathBuf};
+
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub name: String,
+    pub size: u64,
+    // Unknown for archives and cartridges, which hold the program in another format
+    pub platform: Option<Platform>,
+}
+
+// Keyboard-navigable list of the ROMs found in a directory
+pub struct RomBrowser {
+    pub directory: PathBuf,
+    pub entries: Vec<RomEntry>,
+    pub selected: usize,
+    scroll: usize,
+}
+
+impl RomBrowser {
+    // Lists the files with a ROM extension, or without extension like most ROM dumps. The
+    // platform comes from the ROM database when the ROM is known, or is guessed otherwise.
+    // Files that cannot be read are left out.
+    pub fn open(directory: &Path, database: &RomDatabase) -> std::io::Result<Self> {
+        let mut entries = Vec::new();
+        for dir_entry in std::fs::read_dir(directory)?.flatten() {
+            let path = dir_entry.path();
+            let extension = path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+            // Raw programs, as opposed to archives and cartridges
+            let (is_rom, is_raw) = match extension.as_deref() {
+                None => (true, true),
+                Some(ARCHIVE_EXTENSION | CARTRIDGE_EXTENSION) => (true, false),
+                Some(extension) => {
+                    let is_rom = ROM_EXTENSIONS.contains(&extension);
+                    (is_rom, is_rom)
+                }
+            };
+            let Ok(metadata) = dir_entry.metadata() else {
+                continue;
+            };
+            if !is_rom || !metadata.is_file() {
+                continue;
+            }
+            // Only programs that fit in memory are read, to guess their platform
+            let platform = if is_raw && metadata.len() <= CHIP8_MAX_PROGRAM_SIZE as u64 {
+                let Ok(program) = std::fs::read(&path) else {
+                    continue;
+                };
+                Some(match database.lookup(&program) {
+                    Some(metadata) => metadata.platform,
+                    None => rom::detect_platform(&program),
+                })
+            } else {
+                None
+            };
+            entries.push(RomEntry {
+                name: path.file_name().unwrap().to_string_lossy().into_owned(),
+                size: metadata.len(),
+                platform,
+                path,
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            entries,
+            selected: 0,
+            scroll: 0,
+        })
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn selected_entry(&self) -> Option<&RomEntry> {
+        self.entries.get(self.selected)
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>) {
+        let (output_width, output_height) = canvas.output_size().unwrap();
+        let scale = (output_height / 160).max(1);
+        let margin = (4 * scale) as i32;
+        let line_height = (7 * scale) as i32;
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 40, 220));
+        canvas
+            .fill_rect(Rect::new(0, 0, output_width, output_height))
+            .unwrap();
+        canvas.set_blend_mode(BlendMode::None);
+
+        canvas.set_draw_color(Color::RGB(255, 200, 0));
+        let title = format!("ROMS IN {}", self.directory.display());
+        hud::draw_text(canvas, &title, margin, margin, scale);
+        if self.entries.is_empty() {
+            hud::draw_text(
+                canvas,
+                "NO ROM FOUND",
+                margin,
+                margin + 2 * line_height,
+                scale,
+            );
+            return;
+        }
+
+        // Keep the selection visible
+        let visible_lines = ((output_height as i32 - 3 * margin) / line_height - 2).max(1) as usize;
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + visible_lines {
+            self.scroll = self.selected + 1 - visible_lines;
+        }
+
+        for (line, (index, entry)) in self
+            .entries
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(visible_lines)
+            .enumerate()
+        {
+            let marker = if index == self.selected { ">" } else { " " };
+            let text = format!(
+                "{} {:<20} {:>6} B  {}",
+                marker,
+                entry.name,
+                entry.size,
+                entry
+                    .platform
+                    .map(|platform| platform.name())
+                    .unwrap_or("?")
+            );
+            let color = if index == self.selected {
+                Color::WHITE
+            } else {
+                Color::RGB(160, 160, 160)
+            };
+            canvas.set_draw_color(color);
+            let y = margin + (line as i32 + 2) * line_height;
+            hud::draw_text(canvas, &text, margin, y, scale);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rom_browser() {
+        let mut browser = RomBrowser::open(Path::new("roms"), &RomDatabase::bundled()).unwrap();
+        let names: Vec<&str> = browser.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["BLINKY", "BRIX", "INVADERS", "MAZE"]);
+        browser.select_previous();
+        assert_eq!(browser.selected_entry().unwrap().name, "BLINKY");
+        for _ in 0..10 {
+            browser.select_next();
+        }
+        assert_eq!(browser.selected_entry().unwrap().name, "MAZE");
+    }
+
+    #[test]
+    fn test_rom_browser_filters_files() {
+        let directory = std::env::temp_dir().join(format!("chip8_browser_{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("saves")).unwrap();
+        for (name, size) in [
+            ("GAME", 2),
+            ("game.ch8", 2),
+            ("game.ips", 8),
+            ("games.zip", 4),
+            ("notes.txt", 4),
+            ("HUGE", 10000),
+        ] {
+            std::fs::write(directory.join(name), vec![0x12; size]).unwrap();
+        }
+        let browser = RomBrowser::open(&directory, &RomDatabase::bundled()).unwrap();
+        let entries: Vec<(&str, u64, Option<Platform>)> = browser
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.size, entry.platform))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("GAME", 2, Some(Platform::Chip8)),
+                ("HUGE", 10000, None),
+                ("game.ch8", 2, Some(Platform::Chip8)),
+                ("games.zip", 4, None),
+            ]
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
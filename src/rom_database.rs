@@ -0,0 +1,128 @@
+use crate::emulator::Quirks;
+use crate::rom::Platform;
+
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::path::Path;
+
+// Bundled metadata of known ROMs, keyed by the SHA-1 of the ROM bytes
+const BUNDLED_DATABASE: &str = include_str!("../data/rom_database.json");
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "[String; 2]")]
+pub struct Palette {
+    pub background: [u8; 3],
+    pub foreground: [u8; 3],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            background: [0x00, 0x00, 0x00],
+            foreground: [0xFF, 0xFF, 0xFF],
+        }
+    }
+}
+
+impl TryFrom<[String; 2]> for Palette {
+    type Error = String;
+
+    fn try_from(colors: [String; 2]) -> Result<Self, Self::Error> {
+        Ok(Self {
+            background: parse_color(&colors[0])?,
+            foreground: parse_color(&colors[1])?,
+        })
+    }
+}
+
+// Parses a color written as RRGGBB, with an optional leading '#'
+pub fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(format!("Invalid color {:?}", color));
+    }
+    let mut rgb = [0; 3];
+    for (i, component) in rgb.iter_mut().enumerate() {
+        *component = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|_| format!("Invalid color {:?}", color))?;
+    }
+    Ok(rgb)
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RomMetadata {
+    pub title: String,
+    pub platform: Platform,
+    #[serde(default)]
+    pub quirks: Quirks,
+    // Instructions executed per frame
+    pub tick_rate: Option<u32>,
+    pub palette: Option<Palette>,
+    pub key_hints: Option<String>,
+}
+
+pub struct RomDatabase {
+    entries: HashMap<String, RomMetadata>,
+}
+
+impl RomDatabase {
+    pub fn bundled() -> Self {
+        Self {
+            entries: serde_json::from_str(BUNDLED_DATABASE).unwrap(),
+        }
+    }
+
+    // Adds the entries of a user database file, replacing bundled ones with the same hash
+    pub fn merge_file(&mut self, path: &Path) -> std::io::Result<()> {
+        let contents = std::fs::read_to_string(path)?;
+        let entries: HashMap<String, RomMetadata> = serde_json::from_str(&contents)?;
+        for (hash, metadata) in entries {
+            self.entries.insert(hash.to_lowercase(), metadata);
+        }
+        Ok(())
+    }
+
+    pub fn lookup(&self, program: &[u8]) -> Option<&RomMetadata> {
+        self.entries.get(&sha1_hex(program))
+    }
+}
+
+pub fn sha1_hex(program: &[u8]) -> String {
+    Sha1::digest(program)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha1_hex() {
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#FF8000"), Ok([0xFF, 0x80, 0x00]));
+        assert_eq!(parse_color("00ff00"), Ok([0x00, 0xFF, 0x00]));
+        assert!(parse_color("#FFF").is_err());
+        assert!(parse_color("GGGGGG").is_err());
+    }
+
+    #[test]
+    fn test_bundled_database() {
+        let database = RomDatabase::bundled();
+        let brix = database
+            .lookup(&std::fs::read("roms/BRIX").unwrap())
+            .unwrap();
+        assert_eq!(brix.title, "Brix");
+        assert_eq!(brix.platform, Platform::Chip8);
+        assert!(brix.quirks.logic_resets_vf);
+        assert!(!brix.quirks.shift_uses_vy);
+        assert_eq!(brix.tick_rate, Some(15));
+        assert!(database.lookup(&[0x12, 0x00]).is_none());
+    }
+}
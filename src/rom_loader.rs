@@ -0,0 +1,500 @@
+use crate::emulator::{check_program_size, Quirks};
+use crate::octo;
+use crate::patch;
+use crate::rom_database::{parse_color, Palette};
+
+use serde::Deserialize;
+use std::io::{Cursor, Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GIF_MAGICS: [&[u8]; 2] = [b"GIF87a", b"GIF89a"];
+pub const CARTRIDGE_EXTENSION: &str = "gif";
+pub const ROM_EXTENSIONS: [&str; 5] = ["ch8", "c8", "sc8", "xo8", CARTRIDGE_EXTENSION];
+pub const ARCHIVE_EXTENSION: &str = "zip";
+// Numbers the temporary files of the assembler, as several threads may load cartridges
+static ASSEMBLY_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// Settings embedded in the ROM file itself, such as the options of an Octo cartridge
+#[derive(Debug, Default, PartialEq)]
+pub struct EmbeddedOptions {
+    pub quirks: Option<Quirks>,
+    pub tick_rate: Option<u32>,
+    pub palette: Option<Palette>,
+}
+
+pub struct LoadedRom {
+    pub program: Vec<u8>,
+    pub options: EmbeddedOptions,
+    pub patch_path: Option<PathBuf>,
+}
+
+// Loads a raw binary, a ROM from a zip archive or an Octo cartridge.
+// A ROM inside an archive can be chosen with a path such as "games.zip/BRIX",
+// otherwise the archive must contain a single ROM.
+// An IPS or BPS patch with the same name as the ROM file is applied automatically, for a ROM
+// chosen inside an archive it is named after the entry and sits next to the archive.
+// Programs too large for the memory are rejected.
+pub fn load_rom(path: &Path) -> std::io::Result<LoadedRom> {
+    let mut rom = load_unpatched_rom(path)?;
+    for extension in patch::PATCH_EXTENSIONS {
+        let patch_path = sidecar_file(path, extension);
+        if patch_path.is_file() {
+            rom.program = patch::apply_patch(&rom.program, &std::fs::read(&patch_path)?)?;
+            rom.patch_path = Some(patch_path);
+            break;
+        }
+    }
+    check_program_size(&rom.program).map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+    Ok(rom)
+}
+
+fn load_unpatched_rom(path: &Path) -> std::io::Result<LoadedRom> {
+    if path.is_file() {
+        let contents = std::fs::read(path)?;
+        if contents.starts_with(ZIP_MAGIC) {
+            return load_from_zip(contents, None);
+        }
+        return decode_contents(contents);
+    }
+    match split_archive_path(path) {
+        Some((archive_path, entry_name)) => {
+            load_from_zip(std::fs::read(archive_path)?, Some(&entry_name))
+        }
+        None => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} not found", path.display()),
+        )),
+    }
+}
+
+// File to watch on disk for the ROM at the given path
+pub fn source_file(path: &Path) -> PathBuf {
+    match split_archive_path(path) {
+        Some((archive_path, _)) if !path.is_file() => archive_path,
+        _ => path.to_path_buf(),
+    }
+}
+
+// File of the ROM with another extension, such as its patch or cheats. For "games.zip/BRIX"
+// it is named after the entry, such as BRIX.ips in the directory of games.zip.
+pub fn sidecar_file(path: &Path, extension: &str) -> PathBuf {
+    let base_path = match split_archive_path(path) {
+        Some((archive_path, entry_name)) if !path.is_file() => {
+            archive_path.with_file_name(entry_name.rsplit('/').next().unwrap_or_default())
+        }
+        _ => path.to_path_buf(),
+    };
+    base_path.with_extension(extension)
+}
+
+fn split_archive_path(path: &Path) -> Option<(PathBuf, String)> {
+    for archive_path in path.ancestors().skip(1) {
+        let is_zip = archive_path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(ARCHIVE_EXTENSION));
+        if is_zip && archive_path.is_file() {
+            let entry_name = path.strip_prefix(archive_path).ok()?;
+            return Some((
+                archive_path.to_path_buf(),
+                entry_name.to_string_lossy().replace('\\', "/"),
+            ));
+        }
+    }
+    None
+}
+
+fn decode_contents(contents: Vec<u8>) -> std::io::Result<LoadedRom> {
+    if GIF_MAGICS.iter().any(|magic| contents.starts_with(magic)) {
+        return load_octo_cartridge(&contents);
+    }
+    Ok(LoadedRom {
+        program: contents,
+        options: EmbeddedOptions::default(),
+        patch_path: None,
+    })
+}
+
+fn load_from_zip(archive: Vec<u8>, entry_name: Option<&str>) -> std::io::Result<LoadedRom> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive))?;
+    let entry_name = match entry_name {
+        Some(entry_name) => entry_name.to_string(),
+        None => pick_zip_entry(&mut archive)?,
+    };
+    let mut contents = Vec::new();
+    archive.by_name(&entry_name)?.read_to_end(&mut contents)?;
+    decode_contents(contents)
+}
+
+// The only file of the archive, or else the only one with a known ROM extension
+fn pick_zip_entry<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+) -> std::io::Result<String> {
+    let files: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(String::from)
+        .collect();
+    if files.len() == 1 {
+        return Ok(files[0].clone());
+    }
+    let roms: Vec<&String> = files
+        .iter()
+        .filter(|name| {
+            Path::new(name).extension().is_some_and(|extension| {
+                ROM_EXTENSIONS
+                    .iter()
+                    .any(|rom_extension| extension.eq_ignore_ascii_case(rom_extension))
+            })
+        })
+        .collect();
+    match roms.as_slice() {
+        [rom] => Ok(rom.to_string()),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Cannot choose a ROM in the archive, append one of {:?} to its path",
+                files
+            ),
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct CartridgePayload {
+    program: String,
+    #[serde(default)]
+    options: OctoOptions,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct OctoOptions {
+    tickrate: Option<u32>,
+    fill_color: Option<String>,
+    background_color: Option<String>,
+    shift_quirks: Option<bool>,
+    load_store_quirks: Option<bool>,
+    logic_quirks: Option<bool>,
+    clip_quirks: Option<bool>,
+}
+
+impl OctoOptions {
+    // Most Octo quirk flags enable the SUPER-CHIP behaviour, logicQuirks enables the original one
+    fn to_embedded_options(&self) -> EmbeddedOptions {
+        let palette = match (&self.background_color, &self.fill_color) {
+            (Some(background), Some(foreground)) => {
+                match (parse_color(background), parse_color(foreground)) {
+                    (Ok(background), Ok(foreground)) => Some(Palette {
+                        background,
+                        foreground,
+                    }),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        EmbeddedOptions {
+            quirks: Some(Quirks {
+                shift_uses_vy: !self.shift_quirks.unwrap_or(false),
+                load_store_increments_i: !self.load_store_quirks.unwrap_or(false),
+                logic_resets_vf: self.logic_quirks.unwrap_or(false),
+                wrap_sprites: !self.clip_quirks.unwrap_or(false),
+            }),
+            tick_rate: self.tickrate,
+            palette,
+        }
+    }
+}
+
+// Octo cartridges store their payload in the 2 lowest bits of the color index of every
+// pixel, frame after frame. The payload is a 32 bits big endian length, followed by a JSON
+// object with the program source and the Octo options.
+fn decode_cartridge_payload(gif: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decode_options = gif::DecodeOptions::new();
+    decode_options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = decode_options
+        .read_info(gif)
+        .map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+
+    let mut bytes = Vec::new();
+    let mut current_byte = 0u8;
+    let mut bit_count = 0;
+    while let Some(frame) = decoder
+        .read_next_frame()
+        .map_err(|error| Error::new(ErrorKind::InvalidData, error))?
+    {
+        for index in frame.buffer.iter() {
+            current_byte = (current_byte << 2) | (index & 0x03);
+            bit_count += 2;
+            if bit_count == 8 {
+                bytes.push(current_byte);
+                current_byte = 0;
+                bit_count = 0;
+            }
+        }
+    }
+
+    if bytes.len() < 4 {
+        return Err(Error::new(ErrorKind::InvalidData, "Cartridge too small"));
+    }
+    let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    if bytes.len() < 4 + length {
+        return Err(Error::new(ErrorKind::InvalidData, "Truncated cartridge"));
+    }
+    Ok(bytes[4..4 + length].to_vec())
+}
+
+fn load_octo_cartridge(gif: &[u8]) -> std::io::Result<LoadedRom> {
+    let payload: CartridgePayload = serde_json::from_slice(&decode_cartridge_payload(gif)?)?;
+    Ok(LoadedRom {
+        program: assemble_octo_source(&payload.program)?,
+        options: payload.options.to_embedded_options(),
+        patch_path: None,
+    })
+}
+
+// Cartridges hold source code, assembled by the built-in assembler, or by the Octo command
+// line assembler given in CHIP8_OCTO for what the built-in one does not support
+fn assemble_octo_source(source: &str) -> std::io::Result<Vec<u8>> {
+    match std::env::var("CHIP8_OCTO") {
+        Ok(octo_command) => assemble_with(&octo_command, source),
+        Err(_) => octo::assemble(source).map_err(|error| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Cannot assemble the cartridge: {}", error),
+            )
+        }),
+    }
+}
+
+fn assemble_with(octo_command: &str, source: &str) -> std::io::Result<Vec<u8>> {
+    let base_path = std::env::temp_dir().join(format!(
+        "chip8_cartridge_{}_{}",
+        std::process::id(),
+        ASSEMBLY_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let source_path = base_path.with_extension("8o");
+    let program_path = base_path.with_extension("ch8");
+    std::fs::write(&source_path, source)?;
+
+    let status = Command::new(octo_command)
+        .arg(&source_path)
+        .arg(&program_path)
+        .status()
+        .map_err(|error| {
+            let message = if error.kind() == ErrorKind::NotFound {
+                format!(
+                    "The Octo assembler {:?} given in CHIP8_OCTO was not found",
+                    octo_command
+                )
+            } else {
+                format!(
+                    "Cannot run the Octo assembler {:?}: {}",
+                    octo_command, error
+                )
+            };
+            Error::new(error.kind(), message)
+        });
+    let program = match status {
+        Ok(status) if status.success() => std::fs::read(&program_path),
+        Ok(status) => Err(Error::other(format!(
+            "The Octo assembler failed with {}",
+            status
+        ))),
+        Err(error) => Err(error),
+    };
+    let _ = std::fs::remove_file(&source_path);
+    let _ = std::fs::remove_file(&program_path);
+    program
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chip8_loader_{}_{}", std::process::id(), name))
+    }
+
+    fn build_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    // Same layout as the one Octo writes, on a single 64x64 frame
+    fn build_cartridge(payload: &str) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload.as_bytes());
+        let mut pixels = Vec::new();
+        for byte in bytes {
+            for shift in [6, 4, 2, 0] {
+                pixels.push(0x04 | ((byte >> shift) & 0x03));
+            }
+        }
+        pixels.resize(64 * 64, 0);
+
+        let palette: Vec<u8> = (0..8).flat_map(|i| [i * 32, i * 32, i * 32]).collect();
+        let mut gif = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut gif, 64, 64, &palette).unwrap();
+            let frame = gif::Frame::from_indexed_pixels(64, 64, pixels, None);
+            encoder.write_frame(&frame).unwrap();
+        }
+        gif
+    }
+
+    #[test]
+    fn test_load_raw() {
+        let path = temp_path("raw.ch8");
+        std::fs::write(&path, [0x00, 0xE0]).unwrap();
+        let rom = load_rom(&path).unwrap();
+        assert_eq!(rom.program, vec![0x00, 0xE0]);
+        assert_eq!(rom.options, EmbeddedOptions::default());
+
+        std::fs::write(&path, [0x12; 3585]).unwrap();
+        assert!(matches!(load_rom(&path), Err(error) if error.kind() == ErrorKind::InvalidData));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_patched() {
+        let path = temp_path("patched.ch8");
+        let patch_path = temp_path("patched.ips");
+        std::fs::write(&path, [0x00, 0xE0, 0x12, 0x00]).unwrap();
+        std::fs::write(
+            &patch_path,
+            patch::create_ips(&[0x00, 0xE0, 0x12, 0x00], &[0x00, 0xE0, 0x12, 0x02]).unwrap(),
+        )
+        .unwrap();
+        let rom = load_rom(&path).unwrap();
+        assert_eq!(rom.program, vec![0x00, 0xE0, 0x12, 0x02]);
+        assert_eq!(rom.patch_path, Some(patch_path.clone()));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&patch_path).unwrap();
+    }
+
+    #[test]
+    fn test_load_patched_from_zip() {
+        let directory = temp_path("patched_zip");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("games.zip");
+        std::fs::write(
+            &path,
+            build_zip(&[("A.ch8", &[0x00, 0xE0]), ("B.ch8", &[0x12, 0x00])]),
+        )
+        .unwrap();
+        let patch_path = directory.join("B.ips");
+        std::fs::write(
+            &patch_path,
+            patch::create_ips(&[0x12, 0x00], &[0x12, 0x02]).unwrap(),
+        )
+        .unwrap();
+        // Only the entry named like the patch is patched
+        let rom = load_rom(&path.join("A.ch8")).unwrap();
+        assert_eq!((rom.program, rom.patch_path), (vec![0x00, 0xE0], None));
+        let rom = load_rom(&path.join("B.ch8")).unwrap();
+        assert_eq!(rom.program, vec![0x12, 0x02]);
+        assert_eq!(rom.patch_path, Some(patch_path));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_load_from_zip() {
+        let path = temp_path("single.zip");
+        std::fs::write(&path, build_zip(&[("GAME.ch8", &[0x12, 0x00])])).unwrap();
+        assert_eq!(load_rom(&path).unwrap().program, vec![0x12, 0x00]);
+        std::fs::remove_file(&path).unwrap();
+
+        let path = temp_path("several.zip");
+        std::fs::write(
+            &path,
+            build_zip(&[
+                ("README.txt", b"Two games"),
+                ("A.ch8", &[0x00, 0xE0]),
+                ("B.ch8", &[0x12, 0x00]),
+            ]),
+        )
+        .unwrap();
+        assert!(load_rom(&path).is_err());
+        assert_eq!(
+            load_rom(&path.join("B.ch8")).unwrap().program,
+            vec![0x12, 0x00]
+        );
+        assert_eq!(source_file(&path.join("B.ch8")), path);
+        assert!(load_rom(&path.join("C.ch8")).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_assembler() {
+        let error = assemble_with("/nonexistent/octo", ": main").unwrap_err();
+        assert!(error.to_string().contains("CHIP8_OCTO"));
+
+        // An assembler copying the source, run from several threads at once
+        let octo_path = temp_path("octo.sh");
+        std::fs::write(&octo_path, "#!/bin/sh\ncp \"$1\" \"$2\"\n").unwrap();
+        std::process::Command::new("chmod")
+            .arg("+x")
+            .arg(&octo_path)
+            .status()
+            .unwrap();
+        let octo_command = octo_path.to_str().unwrap();
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                scope.spawn(move || {
+                    let source = format!(": main {}", thread);
+                    for _ in 0..10 {
+                        assert_eq!(
+                            assemble_with(octo_command, &source).unwrap(),
+                            source.as_bytes()
+                        );
+                    }
+                });
+            }
+        });
+        std::fs::remove_file(&octo_path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_cartridge() {
+        let payload = r##"{"program":": main\n  jump main","options":{"tickrate":100,
+            "fillColor":"#FF0000","backgroundColor":"#000080",
+            "shiftQuirks":true,"loadStoreQuirks":false,"clipQuirks":true}}"##;
+        let gif = build_cartridge(payload);
+        assert_eq!(
+            decode_cartridge_payload(&gif).unwrap(),
+            payload.as_bytes().to_vec()
+        );
+
+        let rom = load_octo_cartridge(&gif).unwrap();
+        assert_eq!(rom.program, vec![0x12, 0x00]);
+        assert_eq!(rom.options.tick_rate, Some(100));
+
+        let payload: CartridgePayload = serde_json::from_str(payload).unwrap();
+        assert_eq!(payload.program, ": main\n  jump main");
+        let options = payload.options.to_embedded_options();
+        assert_eq!(options.tick_rate, Some(100));
+        assert_eq!(
+            options.palette,
+            Some(Palette {
+                background: [0x00, 0x00, 0x80],
+                foreground: [0xFF, 0x00, 0x00],
+            })
+        );
+        let quirks = options.quirks.unwrap();
+        assert!(!quirks.shift_uses_vy);
+        assert!(quirks.load_store_increments_i);
+        assert!(!quirks.logic_resets_vf);
+        assert!(!quirks.wrap_sprites);
+    }
+}
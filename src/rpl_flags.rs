@@ -0,0 +1,192 @@
+use crate::emulator::{Emulator, CHIP8_NUMBER_RPL_FLAGS};
+use crate::rom_database::sha1_hex;
+#[cfg(test)]
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+pub type Flags = [u8; CHIP8_NUMBER_RPL_FLAGS];
+
+// Where the RPL user flags of each ROM are kept between runs, keyed by the ROM hash
+pub trait RplFlagsStore {
+    fn load(&self, rom_hash: &str) -> io::Result<Option<Flags>>;
+    fn save(&mut self, rom_hash: &str, flags: &Flags) -> io::Result<()>;
+}
+
+// One small binary file per ROM, in a directory created on the first save
+pub struct DirectoryStore {
+    directory: PathBuf,
+}
+
+impl DirectoryStore {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    // $XDG_DATA_HOME/chip8/rpl_flags, falling back to ~/.local/share/chip8/rpl_flags
+    pub fn default_directory() -> PathBuf {
+        let data_directory = match (std::env::var_os("XDG_DATA_HOME"), std::env::var_os("HOME")) {
+            (Some(data_home), _) if !data_home.is_empty() => PathBuf::from(data_home),
+            (_, Some(home)) => PathBuf::from(home).join(".local").join("share"),
+            _ => PathBuf::from("."),
+        };
+        data_directory.join("chip8").join("rpl_flags")
+    }
+
+    fn path(&self, rom_hash: &str) -> PathBuf {
+        self.directory.join(format!("{}.flags", rom_hash))
+    }
+}
+
+impl RplFlagsStore for DirectoryStore {
+    fn load(&self, rom_hash: &str) -> io::Result<Option<Flags>> {
+        match std::fs::read(self.path(rom_hash)) {
+            Ok(bytes) => {
+                // Files written with fewer flags, like the 8 of SUPER-CHIP 1.1, are padded
+                let mut flags = [0; CHIP8_NUMBER_RPL_FLAGS];
+                let length = bytes.len().min(CHIP8_NUMBER_RPL_FLAGS);
+                flags[..length].copy_from_slice(&bytes[..length]);
+                Ok(Some(flags))
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn save(&mut self, rom_hash: &str, flags: &Flags) -> io::Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        std::fs::write(self.path(rom_hash), flags)
+    }
+}
+
+// Keeps the flags for the lifetime of the store only
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore {
+    flags: HashMap<String, Flags>,
+}
+
+#[cfg(test)]
+impl RplFlagsStore for MemoryStore {
+    fn load(&self, rom_hash: &str) -> io::Result<Option<Flags>> {
+        Ok(self.flags.get(rom_hash).copied())
+    }
+
+    fn save(&mut self, rom_hash: &str, flags: &Flags) -> io::Result<()> {
+        self.flags.insert(rom_hash.to_string(), *flags);
+        Ok(())
+    }
+}
+
+// Keeps the RPL user flags of the running ROM in sync with a store
+pub struct RplFlags {
+    store: Box<dyn RplFlagsStore>,
+    // Save as soon as the program writes the flags, instead of only when leaving the ROM
+    save_on_write: bool,
+    rom_hash: Option<String>,
+}
+
+impl RplFlags {
+    pub fn new(store: Box<dyn RplFlagsStore>, save_on_write: bool) -> Self {
+        Self {
+            store,
+            save_on_write,
+            rom_hash: None,
+        }
+    }
+
+    // Must be called right after a program is loaded in the emulator
+    pub fn restore(&mut self, emulator: &mut Emulator, program: &[u8]) -> io::Result<()> {
+        let rom_hash = sha1_hex(program);
+        let flags = self.store.load(&rom_hash)?.unwrap_or_default();
+        emulator.set_rpl_flags(&flags);
+        self.rom_hash = Some(rom_hash);
+        Ok(())
+    }
+
+    // Saves the flags of the running ROM if the program wrote them since the last save.
+    // Must be called before the emulator is reset, and before exiting.
+    pub fn save(&mut self, emulator: &mut Emulator) -> io::Result<()> {
+        if let Some(rom_hash) = &self.rom_hash {
+            if emulator.rpl_flags_changed {
+                self.store.save(rom_hash, emulator.rpl_flags())?;
+                emulator.rpl_flags_changed = false;
+            }
+        }
+        Ok(())
+    }
+
+    // Must be called after every emulated frame
+    pub fn save_if_written(&mut self, emulator: &mut Emulator) -> io::Result<()> {
+        if self.save_on_write {
+            self.save(emulator)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sets V0 = 0x2A and V1 = 0x07, then stores V0 and V1 in the flags
+    const STORE_PROGRAM: [u8; 6] = [0x60, 0x2A, 0x61, 0x07, 0xF1, 0x75];
+
+    fn run_store_program(rpl_flags: &mut RplFlags) -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&STORE_PROGRAM);
+        rpl_flags.restore(&mut emulator, &STORE_PROGRAM).unwrap();
+        for _ in 0..3 {
+            emulator.process_next_instruction();
+        }
+        emulator
+    }
+
+    #[test]
+    fn test_save_on_write() {
+        let mut rpl_flags = RplFlags::new(Box::new(MemoryStore::default()), true);
+        let mut emulator = run_store_program(&mut rpl_flags);
+        rpl_flags.save_if_written(&mut emulator).unwrap();
+        assert!(!emulator.rpl_flags_changed);
+
+        let mut next_run = Emulator::new();
+        rpl_flags.restore(&mut next_run, &STORE_PROGRAM).unwrap();
+        assert_eq!(next_run.rpl_flags()[..3], [0x2A, 0x07, 0x00]);
+    }
+
+    #[test]
+    fn test_save_on_exit() {
+        let mut rpl_flags = RplFlags::new(Box::new(MemoryStore::default()), false);
+        let mut emulator = run_store_program(&mut rpl_flags);
+        rpl_flags.save_if_written(&mut emulator).unwrap();
+        assert!(emulator.rpl_flags_changed);
+        rpl_flags.save(&mut emulator).unwrap();
+        assert!(!emulator.rpl_flags_changed);
+
+        // Flags are kept per ROM
+        let mut other_rom = Emulator::new();
+        rpl_flags.restore(&mut other_rom, &[0x00, 0xE0]).unwrap();
+        assert_eq!(other_rom.rpl_flags(), &[0; CHIP8_NUMBER_RPL_FLAGS]);
+        rpl_flags.restore(&mut other_rom, &STORE_PROGRAM).unwrap();
+        assert_eq!(other_rom.rpl_flags()[..2], [0x2A, 0x07]);
+    }
+
+    #[test]
+    fn test_directory_store() {
+        let directory =
+            std::env::temp_dir().join(format!("chip8_rpl_flags_{}", std::process::id()));
+        let mut store = DirectoryStore::new(directory.clone());
+        assert_eq!(store.load("abcd").unwrap(), None);
+        let mut flags = [0; CHIP8_NUMBER_RPL_FLAGS];
+        flags[7] = 0x99;
+        store.save("abcd", &flags).unwrap();
+        assert_eq!(store.load("abcd").unwrap(), Some(flags));
+
+        // SUPER-CHIP 1.1 only has 8 flags
+        std::fs::write(directory.join("short.flags"), [1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        let short_flags = store.load("short").unwrap().unwrap();
+        assert_eq!(short_flags[..9], [1, 2, 3, 4, 5, 6, 7, 8, 0]);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,450 @@
+use crate::emulator::{
+    Emulator, InstructionStep, PixelStatus, Snapshot, CHIP8_MEMORY_SIZE, CHIP8_NUMBER_KEYS,
+    CHIP8_NUMBER_REGISTERS, CHIP8_SCREEN_HEIGHT, CHIP8_SCREEN_WIDTH,
+};
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, AST};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// Operations allowed in one call of the script, so that an endless loop fails instead of
+// freezing the frontend
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+// Shared between the script host and the functions registered in the engine
+struct State {
+    // The emulator is swapped in while the script runs, and back out afterwards
+    emulator: Emulator,
+    frame_callbacks: Vec<FnPtr>,
+    instruction_callbacks: Vec<(usize, FnPtr)>,
+    // First address and length of the watched bytes
+    write_callbacks: Vec<(usize, usize, FnPtr)>,
+    // Printed text, shown by the frontend
+    messages: Vec<String>,
+    frames: i64,
+    finished: bool,
+}
+
+// Rhai script driving the emulator. Scripts read and write the memory, registers, screen and
+// keys, save and load states, and register callbacks run at the end of every frame, before
+// the instruction at an address, or after writes to memory:
+//
+//     on_instruction(0x206, |address| print(`drawing at ${pc()}`));
+//     on_memory_write(0x300, |address, value| if value > 9 { stop() });
+//     on_frame(|| if frame() == 60 { press(5) });
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    state: Rc<RefCell<State>>,
+    failed: bool,
+    error: Option<String>,
+}
+
+fn check(value: i64, limit: usize, what: &str) -> ScriptResult<usize> {
+    if value >= 0 && (value as usize) < limit {
+        Ok(value as usize)
+    } else {
+        Err(format!("Invalid {} {}", what, value).into())
+    }
+}
+
+fn check_pixel(x: i64, y: i64) -> ScriptResult<usize> {
+    let x = check(x, CHIP8_SCREEN_WIDTH, "column")?;
+    let y = check(y, CHIP8_SCREEN_HEIGHT, "row")?;
+    Ok(y * CHIP8_SCREEN_WIDTH + x)
+}
+
+impl Script {
+    pub fn load(path: &Path, emulator: &mut Emulator) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|error| format!("{}: {}", path.display(), error))?;
+        Self::new(&source, emulator).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    // Compiles the script, then runs its top level, which registers the callbacks
+    pub fn new(source: &str, emulator: &mut Emulator) -> Result<Self, String> {
+        let state = Rc::new(RefCell::new(State {
+            emulator: Emulator::new(),
+            frame_callbacks: Vec::new(),
+            instruction_callbacks: Vec::new(),
+            write_callbacks: Vec::new(),
+            messages: Vec::new(),
+            frames: 0,
+            finished: false,
+        }));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        register_functions(&mut engine, &state);
+        let ast = engine.compile(source).map_err(|error| error.to_string())?;
+
+        let script = Self {
+            engine,
+            ast,
+            state,
+            failed: false,
+            error: None,
+        };
+        script.swap_emulator(emulator);
+        let result = script.engine.run_ast(&script.ast);
+        script.swap_emulator(emulator);
+        result.map_err(|error| error.to_string())?;
+        Ok(script)
+    }
+
+    fn swap_emulator(&self, emulator: &mut Emulator) {
+        std::mem::swap(emulator, &mut self.state.borrow_mut().emulator);
+    }
+
+    // After an error, the script is not called anymore
+    fn call(&mut self, emulator: &mut Emulator, callback: &FnPtr, arguments: impl FuncArgs) {
+        if self.failed {
+            return;
+        }
+        self.swap_emulator(emulator);
+        let result = callback.call::<Dynamic>(&self.engine, &self.ast, arguments);
+        self.swap_emulator(emulator);
+        if let Err(error) = result {
+            self.failed = true;
+            self.error = Some(format!("Script error: {}", error));
+        }
+    }
+
+    // Emulator::run_frame, with the callbacks of the script in between instructions
+    pub fn run_frame(&mut self, emulator: &mut Emulator, instructions_per_frame: u32) -> u32 {
+        let (instruction_callbacks, write_callbacks) = {
+            let state = self.state.borrow();
+            (
+                state.instruction_callbacks.clone(),
+                state.write_callbacks.clone(),
+            )
+        };
+        if !write_callbacks.is_empty() {
+            emulator.memory_writes.get_or_insert_with(Vec::new);
+        }
+
+        let instructions_executed =
+            emulator.run_frame_with(instructions_per_frame, |emulator, step| match step {
+                InstructionStep::Before => {
+                    let address = emulator.program_counter();
+                    for (_, callback) in instruction_callbacks
+                        .iter()
+                        .filter(|(callback_address, _)| *callback_address == address)
+                    {
+                        self.call(emulator, callback, (address as i64,));
+                    }
+                }
+                InstructionStep::After => {
+                    let memory_writes = emulator.memory_writes.as_mut().map(std::mem::take);
+                    for (start, length) in memory_writes.unwrap_or_default() {
+                        for address in start..start + length {
+                            for (_, _, callback) in write_callbacks.iter().filter(
+                                |(watched_start, watched_length, _)| {
+                                    (*watched_start..watched_start + watched_length)
+                                        .contains(&address)
+                                },
+                            ) {
+                                let value = emulator.memory()[address] as i64;
+                                self.call(emulator, callback, (address as i64, value));
+                            }
+                        }
+                    }
+                }
+            });
+
+        self.state.borrow_mut().frames += 1;
+        let frame_callbacks = self.state.borrow().frame_callbacks.clone();
+        for callback in &frame_callbacks {
+            self.call(emulator, callback, ());
+        }
+        instructions_executed
+    }
+
+    // Text printed by the script since the last call
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.state.borrow_mut().messages)
+    }
+
+    // The script called stop()
+    pub fn finished(&self) -> bool {
+        self.state.borrow().finished
+    }
+
+    // Error which stopped the callbacks, reported once
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+}
+
+fn register_functions(engine: &mut Engine, state: &Rc<RefCell<State>>) {
+    let shared = Rc::clone(state);
+    engine.on_print(move |text| shared.borrow_mut().messages.push(text.to_string()));
+
+    // Memory and registers
+    let shared = Rc::clone(state);
+    engine.register_fn("peek", move |address: i64| -> ScriptResult<i64> {
+        let address = check(address, CHIP8_MEMORY_SIZE, "address")?;
+        Ok(shared.borrow().emulator.memory()[address] as i64)
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn(
+        "poke",
+        move |address: i64, value: i64| -> ScriptResult<()> {
+            let address = check(address, CHIP8_MEMORY_SIZE, "address")?;
+            shared
+                .borrow_mut()
+                .emulator
+                .write_memory(address, value as u8);
+            Ok(())
+        },
+    );
+    let shared = Rc::clone(state);
+    engine.register_fn("register", move |x: i64| -> ScriptResult<i64> {
+        let x = check(x, CHIP8_NUMBER_REGISTERS, "register")?;
+        Ok(shared.borrow().emulator.registers()[x] as i64)
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn(
+        "set_register",
+        move |x: i64, value: i64| -> ScriptResult<()> {
+            let x = check(x, CHIP8_NUMBER_REGISTERS, "register")?;
+            shared.borrow_mut().emulator.set_register(x, value as u8);
+            Ok(())
+        },
+    );
+    let shared = Rc::clone(state);
+    engine.register_fn("pc", move || {
+        shared.borrow().emulator.program_counter() as i64
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn("set_pc", move |address: i64| -> ScriptResult<()> {
+        let address = check(address, CHIP8_MEMORY_SIZE - 1, "address")?;
+        shared.borrow_mut().emulator.set_program_counter(address);
+        Ok(())
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn("index", move || {
+        shared.borrow().emulator.index_register() as i64
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn("set_index", move |address: i64| -> ScriptResult<()> {
+        let address = check(address, CHIP8_MEMORY_SIZE, "address")?;
+        shared.borrow_mut().emulator.set_index_register(address);
+        Ok(())
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn("delay_timer", move || {
+        shared.borrow().emulator.system_clock as i64
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn("set_delay_timer", move |value: i64| {
+        shared.borrow_mut().emulator.system_clock = value as u8;
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn("sound_timer", move || {
+        shared.borrow().emulator.sound_clock as i64
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn("set_sound_timer", move |value: i64| {
+        shared.borrow_mut().emulator.sound_clock = value as u8;
+    });
+
+    // Screen and keys
+    let shared = Rc::clone(state);
+    engine.register_fn("pixel", move |x: i64, y: i64| -> ScriptResult<bool> {
+        Ok(shared.borrow().emulator.screen[check_pixel(x, y)?] == PixelStatus::White)
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn(
+        "set_pixel",
+        move |x: i64, y: i64, on: bool| -> ScriptResult<()> {
+            let pixel = check_pixel(x, y)?;
+            let emulator = &mut shared.borrow_mut().emulator;
+            emulator.screen[pixel] = if on {
+                PixelStatus::White
+            } else {
+                PixelStatus::Black
+            };
+            emulator.screen_changed = true;
+            Ok(())
+        },
+    );
+    let shared = Rc::clone(state);
+    engine.register_fn("press", move |key: i64| -> ScriptResult<()> {
+        let key = check(key, CHIP8_NUMBER_KEYS, "key")?;
+        shared.borrow_mut().emulator.input_key(key as u8, true);
+        Ok(())
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn("release", move |key: i64| -> ScriptResult<()> {
+        let key = check(key, CHIP8_NUMBER_KEYS, "key")?;
+        shared.borrow_mut().emulator.input_key(key as u8, false);
+        Ok(())
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn("is_pressed", move |key: i64| -> ScriptResult<bool> {
+        let key = check(key, CHIP8_NUMBER_KEYS, "key")?;
+        Ok(shared.borrow().emulator.key_pressed(key as u8))
+    });
+
+    // States, in memory or in files
+    engine.register_type_with_name::<Snapshot>("State");
+    let shared = Rc::clone(state);
+    engine.register_fn("save_state", move || shared.borrow().emulator.snapshot());
+    let shared = Rc::clone(state);
+    engine.register_fn("load_state", move |snapshot: Snapshot| {
+        shared.borrow_mut().emulator.restore(&snapshot);
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn("save_state_file", move |path: &str| -> ScriptResult<()> {
+        let bytes = shared.borrow().emulator.snapshot().to_bytes();
+        std::fs::write(path, bytes).map_err(|error| format!("{}: {}", path, error).into())
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn("load_state_file", move |path: &str| -> ScriptResult<()> {
+        let bytes = std::fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+        let snapshot =
+            Snapshot::from_bytes(&bytes).map_err(|error| format!("{}: {}", path, error))?;
+        shared.borrow_mut().emulator.restore(&snapshot);
+        Ok(())
+    });
+
+    // Callbacks and control of the run
+    let shared = Rc::clone(state);
+    engine.register_fn("on_frame", move |callback: FnPtr| {
+        shared.borrow_mut().frame_callbacks.push(callback);
+    });
+    let shared = Rc::clone(state);
+    engine.register_fn(
+        "on_instruction",
+        move |address: i64, callback: FnPtr| -> ScriptResult<()> {
+            let address = check(address, CHIP8_MEMORY_SIZE, "address")?;
+            shared
+                .borrow_mut()
+                .instruction_callbacks
+                .push((address, callback));
+            Ok(())
+        },
+    );
+    let shared = Rc::clone(state);
+    engine.register_fn(
+        "on_memory_write",
+        move |address: i64, callback: FnPtr| -> ScriptResult<()> {
+            let address = check(address, CHIP8_MEMORY_SIZE, "address")?;
+            shared
+                .borrow_mut()
+                .write_callbacks
+                .push((address, 1, callback));
+            Ok(())
+        },
+    );
+    let shared = Rc::clone(state);
+    engine.register_fn(
+        "on_memory_write",
+        move |address: i64, length: i64, callback: FnPtr| -> ScriptResult<()> {
+            let address = check(address, CHIP8_MEMORY_SIZE, "address")?;
+            let length = check(length, CHIP8_MEMORY_SIZE - address + 1, "length")?;
+            shared
+                .borrow_mut()
+                .write_callbacks
+                .push((address, length, callback));
+            Ok(())
+        },
+    );
+    let shared = Rc::clone(state);
+    engine.register_fn("frame", move || shared.borrow().frames);
+    let shared = Rc::clone(state);
+    engine.register_fn("stop", move || shared.borrow_mut().finished = true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, 0x07; LD I, 0x300; LD [I], V0; JP 0x206
+    const PROGRAM: [u8; 8] = [0x60, 0x07, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06];
+
+    fn emulator() -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator.trace = false;
+        emulator.load_program(&PROGRAM);
+        emulator
+    }
+
+    #[test]
+    fn test_memory_registers_and_keys() {
+        let mut emulator = emulator();
+        let script = Script::new(
+            "poke(0x300, 42); set_register(3, peek(0x200)); press(5); set_pixel(1, 2, true);",
+            &mut emulator,
+        )
+        .unwrap();
+        assert_eq!(emulator.memory()[0x300], 42);
+        assert_eq!(emulator.registers()[3], 0x60);
+        assert!(emulator.key_pressed(5));
+        assert_eq!(
+            emulator.screen[2 * CHIP8_SCREEN_WIDTH + 1],
+            PixelStatus::White
+        );
+        assert!(!script.finished());
+
+        assert!(Script::new("poke(4096, 0)", &mut emulator).is_err());
+        assert!(Script::new("register(", &mut emulator).is_err());
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let mut emulator = emulator();
+        let mut script = Script::new(
+            r#"
+            on_instruction(0x204, |address| print(`store at ${address} with I ${index()}`));
+            on_memory_write(0x300, |address, value| print(`wrote ${value} at ${address}`));
+            on_frame(|| if frame() == 2 { stop() });
+            "#,
+            &mut emulator,
+        )
+        .unwrap();
+        assert_eq!(script.run_frame(&mut emulator, 4), 4);
+        assert_eq!(
+            script.take_messages(),
+            vec!["store at 516 with I 768", "wrote 7 at 768"]
+        );
+        assert!(!script.finished());
+        script.run_frame(&mut emulator, 4);
+        assert!(script.take_messages().is_empty());
+        assert!(script.finished());
+    }
+
+    #[test]
+    fn test_states() {
+        let mut emulator = emulator();
+        let mut script = Script::new(
+            r#"
+            let start = save_state();
+            on_frame(|| if frame() == 1 { load_state(start) });
+            "#,
+            &mut emulator,
+        )
+        .unwrap();
+        script.run_frame(&mut emulator, 3);
+        assert_eq!(emulator.program_counter(), 0x200);
+        assert_eq!(emulator.memory()[0x300], 0);
+    }
+
+    #[test]
+    fn test_errors_stop_the_script() {
+        let mut emulator = emulator();
+        let mut script = Script::new("on_frame(|| peek(-1))", &mut emulator).unwrap();
+        script.run_frame(&mut emulator, 1);
+        assert!(script.take_error().unwrap().contains("Invalid address -1"));
+        script.run_frame(&mut emulator, 1);
+        assert_eq!(script.take_error(), None);
+
+        let mut script = Script::new("on_frame(|| loop {})", &mut emulator).unwrap();
+        script.run_frame(&mut emulator, 1);
+        assert!(script.take_error().unwrap().contains("Too many operations"));
+        assert!(Script::new("loop {}", &mut emulator).is_err());
+    }
+}
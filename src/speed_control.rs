@@ -0,0 +1,163 @@
+const FAST_FORWARD_MULTIPLIERS: [Option<u32>; 4] = [Some(2), Some(4), Some(8), None];
+const SLOW_MOTION_DIVISORS: [u32; 3] = [1, 2, 4];
+
+#[derive(Debug, PartialEq)]
+pub enum FrameBudget {
+    // Run exactly this number of emulated frames during the next real frame
+    Frames(u32),
+    // Run as many emulated frames as time allows during the next real frame
+    Uncapped,
+}
+
+// Decides how many emulated frames (instructions and timers together) are run per real frame,
+// so timers always stay consistent with the CPU whatever the speed.
+pub struct SpeedControl {
+    pub paused: bool,
+    pub fast_forward: bool,
+    fast_forward_index: usize,
+    slow_motion_index: usize,
+    frame_advance_requested: bool,
+    slow_motion_counter: u32,
+}
+
+impl SpeedControl {
+    pub fn new() -> Self {
+        Self {
+            paused: false,
+            fast_forward: false,
+            fast_forward_index: 0,
+            slow_motion_index: 0,
+            frame_advance_requested: false,
+            slow_motion_counter: 0,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // Only has an effect while paused
+    pub fn request_frame_advance(&mut self) {
+        if self.paused {
+            self.frame_advance_requested = true;
+        }
+    }
+
+    pub fn next_fast_forward_multiplier(&mut self) {
+        self.fast_forward_index = (self.fast_forward_index + 1) % FAST_FORWARD_MULTIPLIERS.len();
+    }
+
+    pub fn fast_forward_multiplier(&self) -> Option<u32> {
+        FAST_FORWARD_MULTIPLIERS[self.fast_forward_index]
+    }
+
+    pub fn next_slow_motion_divisor(&mut self) {
+        self.slow_motion_index = (self.slow_motion_index + 1) % SLOW_MOTION_DIVISORS.len();
+        self.slow_motion_counter = 0;
+    }
+
+    pub fn slow_motion_divisor(&self) -> u32 {
+        SLOW_MOTION_DIVISORS[self.slow_motion_index]
+    }
+
+    // Must be called exactly once per real frame
+    pub fn next_frame_budget(&mut self) -> FrameBudget {
+        if self.paused {
+            let frames = if self.frame_advance_requested { 1 } else { 0 };
+            self.frame_advance_requested = false;
+            return FrameBudget::Frames(frames);
+        }
+        if self.fast_forward {
+            return match self.fast_forward_multiplier() {
+                Some(multiplier) => FrameBudget::Frames(multiplier),
+                None => FrameBudget::Uncapped,
+            };
+        }
+        self.slow_motion_counter = (self.slow_motion_counter + 1) % self.slow_motion_divisor();
+        if self.slow_motion_counter == 0 {
+            FrameBudget::Frames(1)
+        } else {
+            FrameBudget::Frames(0)
+        }
+    }
+
+    pub fn indicator(&self) -> Option<String> {
+        if self.paused {
+            Some("PAUSED".to_string())
+        } else if self.fast_forward {
+            match self.fast_forward_multiplier() {
+                Some(multiplier) => Some(format!("FAST FORWARD X{}", multiplier)),
+                None => Some("FAST FORWARD UNCAPPED".to_string()),
+            }
+        } else if self.slow_motion_divisor() > 1 {
+            Some(format!("SLOW MOTION 1/{}", self.slow_motion_divisor()))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for SpeedControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normal_speed() {
+        let mut speed_control = SpeedControl::new();
+        assert_eq!(speed_control.next_frame_budget(), FrameBudget::Frames(1));
+        assert_eq!(speed_control.next_frame_budget(), FrameBudget::Frames(1));
+        assert_eq!(speed_control.indicator(), None);
+    }
+
+    #[test]
+    fn test_pause_and_frame_advance() {
+        let mut speed_control = SpeedControl::new();
+        speed_control.request_frame_advance();
+        assert_eq!(speed_control.next_frame_budget(), FrameBudget::Frames(1));
+        speed_control.toggle_pause();
+        assert_eq!(speed_control.next_frame_budget(), FrameBudget::Frames(0));
+        speed_control.request_frame_advance();
+        assert_eq!(speed_control.next_frame_budget(), FrameBudget::Frames(1));
+        assert_eq!(speed_control.next_frame_budget(), FrameBudget::Frames(0));
+        speed_control.toggle_pause();
+        assert_eq!(speed_control.next_frame_budget(), FrameBudget::Frames(1));
+    }
+
+    #[test]
+    fn test_fast_forward() {
+        let mut speed_control = SpeedControl::new();
+        speed_control.fast_forward = true;
+        assert_eq!(speed_control.next_frame_budget(), FrameBudget::Frames(2));
+        speed_control.next_fast_forward_multiplier();
+        assert_eq!(speed_control.next_frame_budget(), FrameBudget::Frames(4));
+        speed_control.next_fast_forward_multiplier();
+        speed_control.next_fast_forward_multiplier();
+        assert_eq!(speed_control.next_frame_budget(), FrameBudget::Uncapped);
+        speed_control.fast_forward = false;
+        assert_eq!(speed_control.next_frame_budget(), FrameBudget::Frames(1));
+    }
+
+    #[test]
+    fn test_slow_motion() {
+        let mut speed_control = SpeedControl::new();
+        speed_control.next_slow_motion_divisor();
+        speed_control.next_slow_motion_divisor();
+        assert_eq!(speed_control.slow_motion_divisor(), 4);
+        let frames: Vec<FrameBudget> = (0..8).map(|_| speed_control.next_frame_budget()).collect();
+        assert_eq!(
+            frames
+                .iter()
+                .filter(|budget| **budget == FrameBudget::Frames(1))
+                .count(),
+            2
+        );
+        speed_control.next_slow_motion_divisor();
+        assert_eq!(speed_control.slow_motion_divisor(), 1);
+    }
+}
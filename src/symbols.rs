@@ -0,0 +1,134 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Names of program addresses, loaded from label files exported by assemblers
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Symbols {
+    names: BTreeMap<usize, String>,
+}
+
+impl Symbols {
+    // Octo JSON debug output when the file ends with .json or starts with '{',
+    // "addr name" lines otherwise
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("{}: {}", path.display(), error))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+            || text.trim_start().starts_with('{');
+        let symbols = if is_json {
+            Self::parse_octo_json(&text)
+        } else {
+            Self::parse_text(&text)
+        };
+        symbols.map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    // One "addr name" pair per line, such as "0x206 draw_player" or "206 draw_player".
+    // Addresses are hexadecimal, '#' and ';' start comments.
+    pub fn parse_text(text: &str) -> Result<Self, String> {
+        let mut symbols = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split(['#', ';']).next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("Invalid symbol on line {}: {:?}", index + 1, line);
+            let mut fields = line.split_whitespace();
+            let (Some(address), Some(name), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid());
+            };
+            let address = address.trim_start_matches("0x").trim_start_matches("0X");
+            let address = usize::from_str_radix(address, 16).map_err(|_| invalid())?;
+            symbols.insert(address, name);
+        }
+        Ok(symbols)
+    }
+
+    // Labels of the debug output of Octo, an object mapping names to addresses under "labels".
+    // Addresses are numbers, or strings in decimal or 0x prefixed hexadecimal.
+    pub fn parse_octo_json(text: &str) -> Result<Self, String> {
+        let document: Value = serde_json::from_str(text).map_err(|error| error.to_string())?;
+        let labels = document
+            .get("labels")
+            .and_then(Value::as_object)
+            .ok_or("Missing \"labels\" object")?;
+        let mut symbols = Self::default();
+        for (name, address) in labels {
+            let address = match address {
+                Value::Number(number) => number.as_u64().map(|address| address as usize),
+                Value::String(text) => match text.strip_prefix("0x") {
+                    Some(hexadecimal) => usize::from_str_radix(hexadecimal, 16).ok(),
+                    None => text.parse().ok(),
+                },
+                _ => None,
+            }
+            .ok_or(format!("Invalid address of label {:?}", name))?;
+            symbols.insert(address, name);
+        }
+        Ok(symbols)
+    }
+
+    // The first name given to an address is kept
+    fn insert(&mut self, address: usize, name: &str) {
+        self.names
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+    }
+
+    pub fn name(&self, address: usize) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    // Name of the address, or the address in hexadecimal when it has none
+    pub fn format_address(&self, address: usize) -> String {
+        match self.name(address) {
+            Some(name) => name.to_string(),
+            None => format!("0x{:03X}", address),
+        }
+    }
+
+    // Address relative to the closest name before it, such as "draw_player+0x4"
+    pub fn locate(&self, address: usize) -> String {
+        match self.names.range(..=address).next_back() {
+            Some((start, name)) if *start == address => name.clone(),
+            Some((start, name)) => format!("{}+0x{:X}", name, address - start),
+            None => format!("0x{:03X}", address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_text() {
+        let symbols =
+            Symbols::parse_text("# Labels\n0x200 main\n206 draw_player ; sprite drawing\n\n")
+                .unwrap();
+        assert_eq!(symbols.name(0x200), Some("main"));
+        assert_eq!(symbols.format_address(0x206), "draw_player");
+        assert_eq!(symbols.format_address(0x300), "0x300");
+        assert_eq!(symbols.locate(0x20A), "draw_player+0x4");
+        assert_eq!(symbols.locate(0x100), "0x100");
+        assert!(Symbols::parse_text("0x200").is_err());
+        assert!(Symbols::parse_text("main 0x200").is_err());
+    }
+
+    #[test]
+    fn test_parse_octo_json() {
+        let symbols = Symbols::parse_octo_json(
+            r#"{"labels": {"main": 512, "draw_player": "0x206", "level": "768"}}"#,
+        )
+        .unwrap();
+        assert_eq!(symbols.name(0x200), Some("main"));
+        assert_eq!(symbols.name(0x206), Some("draw_player"));
+        assert_eq!(symbols.name(0x300), Some("level"));
+        assert!(Symbols::parse_octo_json(r#"{"labels": {"main": true}}"#).is_err());
+        assert!(Symbols::parse_octo_json("{}").is_err());
+    }
+}
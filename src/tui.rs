@@ -0,0 +1,514 @@
+use crate::cheats::CheatEngine;
+use crate::disassembler::disassemble;
+use crate::emulator::{
+    Emulator, PixelStatus, CHIP8_MEMORY_SIZE, CHIP8_SCREEN_HEIGHT, CHIP8_SCREEN_WIDTH,
+};
+use crate::frontend::{
+    load_script, new_emulator, open_rom_database, open_rpl_flags, reload_rom, run_frame_budget,
+    save_at_exit, write_profile, Options, FRAME_DURATION, ROM_WATCH_PERIOD_IN_FRAMES,
+};
+use crate::rom::RomWatcher;
+use crate::rom_database::Palette;
+use crate::rom_loader;
+use crate::speed_control::SpeedControl;
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, queue, terminal};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+// Terminals without key release events only repeat a held key, so a key press is released
+// after this many frames unless the terminal repeats it
+const KEY_HOLD_FRAMES: u32 = 10;
+// Instructions shown before and after the program counter in the side panel
+const DISASSEMBLY_CONTEXT: usize = 4;
+const PANEL_MARGIN: &str = "  ";
+
+// Each character shows two pixels stacked vertically
+pub fn render_half_blocks(screen: &[PixelStatus]) -> Vec<String> {
+    let lit = |i: usize, j: usize| screen[j * CHIP8_SCREEN_WIDTH + i] == PixelStatus::White;
+    (0..CHIP8_SCREEN_HEIGHT)
+        .step_by(2)
+        .map(|j| {
+            (0..CHIP8_SCREEN_WIDTH)
+                .map(|i| match (lit(i, j), lit(i, j + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Each character shows a block of 2x4 pixels as braille dots
+pub fn render_braille(screen: &[PixelStatus]) -> Vec<String> {
+    // Bit of each dot of a braille pattern, by row then column
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+    (0..CHIP8_SCREEN_HEIGHT)
+        .step_by(4)
+        .map(|j| {
+            (0..CHIP8_SCREEN_WIDTH)
+                .step_by(2)
+                .map(|i| {
+                    let mut pattern = 0;
+                    for (row, bits) in DOTS.iter().enumerate() {
+                        for (column, bit) in bits.iter().enumerate() {
+                            let pixel = (j + row) * CHIP8_SCREEN_WIDTH + i + column;
+                            if screen[pixel] == PixelStatus::White {
+                                pattern |= bit;
+                            }
+                        }
+                    }
+                    char::from_u32(0x2800 + pattern).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Registers, then the disassembly around the program counter which is marked with '>'
+pub fn side_panel(emulator: &Emulator) -> Vec<String> {
+    let mut lines = vec![
+        format!(
+            "PC {:03X}  I {:03X}  SP {:X}",
+            emulator.program_counter(),
+            emulator.index_register(),
+            emulator.stack_pointer()
+        ),
+        format!(
+            "DT {:02X}  ST {:02X}",
+            emulator.system_clock, emulator.sound_clock
+        ),
+    ];
+    for (row, values) in emulator.registers().chunks(4).enumerate() {
+        let registers: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(column, value)| format!("V{:X} {:02X}", row * 4 + column, value))
+            .collect();
+        lines.push(registers.join("  "));
+    }
+    lines.push(String::new());
+
+    let program_counter = emulator.program_counter();
+    let start = program_counter.saturating_sub(2 * DISASSEMBLY_CONTEXT);
+    let end = (program_counter + 2 * DISASSEMBLY_CONTEXT + 2).min(CHIP8_MEMORY_SIZE);
+    for line in disassemble(emulator.memory(), start, end, &emulator.symbols) {
+        let marker = if line.address == program_counter {
+            '>'
+        } else {
+            ' '
+        };
+        lines.push(format!(
+            "{} {:03X}  {:04X}  {}",
+            marker, line.address, line.raw_opcode, line.text
+        ));
+    }
+    lines
+}
+
+pub(crate) fn map_key_to_chip8_code(code: KeyCode) -> Option<u8> {
+    match code {
+        KeyCode::Char(character) => match character.to_ascii_uppercase() {
+            '0'..='9' => character.to_digit(10).map(|digit| digit as u8),
+            'A' => Some(0x0A),
+            'Z' => Some(0x0B),
+            'E' => Some(0x0C),
+            'R' => Some(0x0D),
+            'T' => Some(0x0E),
+            'Y' => Some(0x0F),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// CHIP8 keys held down, released by the terminal when it reports key releases, or after
+// KEY_HOLD_FRAMES frames without being repeated otherwise
+pub(crate) struct HeldKeys {
+    release_events: bool,
+    frames_left: [u32; 16],
+}
+
+impl HeldKeys {
+    pub(crate) fn new(release_events: bool) -> Self {
+        Self {
+            release_events,
+            frames_left: [0; 16],
+        }
+    }
+
+    pub(crate) fn press(&mut self, emulator: &mut Emulator, key: u8) {
+        self.frames_left[key as usize] = KEY_HOLD_FRAMES;
+        emulator.input_key(key, true);
+    }
+
+    pub(crate) fn release(&mut self, emulator: &mut Emulator, key: u8) {
+        self.frames_left[key as usize] = 0;
+        emulator.input_key(key, false);
+    }
+
+    // Must be called once per real frame
+    pub(crate) fn expire(&mut self, emulator: &mut Emulator) {
+        if self.release_events {
+            return;
+        }
+        for key in 0..self.frames_left.len() {
+            if self.frames_left[key] > 0 {
+                self.frames_left[key] -= 1;
+                if self.frames_left[key] == 0 {
+                    emulator.input_key(key as u8, false);
+                }
+            }
+        }
+    }
+}
+
+// Raw mode on the alternate screen for as long as it lives, the terminal is restored on drop
+pub(crate) struct TerminalGuard {
+    pub(crate) release_events: bool,
+}
+
+impl TerminalGuard {
+    pub(crate) fn enter() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = std::io::stdout();
+        queue!(
+            stdout,
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+        let release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if release_events {
+            queue!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        stdout.flush()?;
+        Ok(Self { release_events })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut stdout = std::io::stdout();
+        if self.release_events {
+            let _ = queue!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = queue!(
+            stdout,
+            ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = stdout.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+pub(crate) fn color(rgb: [u8; 3]) -> Color {
+    Color::Rgb {
+        r: rgb[0],
+        g: rgb[1],
+        b: rgb[2],
+    }
+}
+
+// Writes the rows that differ from the previously drawn ones, the screen in the palette colors
+// followed by the side panel, then the status line below them
+fn draw(
+    stdout: &mut std::io::Stdout,
+    rows: &[(String, String)],
+    drawn_rows: &mut Vec<(String, String)>,
+    status: &str,
+    palette: Palette,
+) -> std::io::Result<()> {
+    if drawn_rows.len() != rows.len() {
+        queue!(stdout, terminal::Clear(terminal::ClearType::All))?;
+        drawn_rows.clear();
+    }
+    for (index, row) in rows.iter().enumerate() {
+        if drawn_rows.get(index) == Some(row) {
+            continue;
+        }
+        queue!(
+            stdout,
+            cursor::MoveTo(0, index as u16),
+            SetForegroundColor(color(palette.foreground)),
+            SetBackgroundColor(color(palette.background)),
+            Print(&row.0),
+            ResetColor,
+            Print(PANEL_MARGIN),
+            Print(&row.1),
+            terminal::Clear(terminal::ClearType::UntilNewLine)
+        )?;
+    }
+    *drawn_rows = rows.to_vec();
+    queue!(
+        stdout,
+        cursor::MoveTo(0, rows.len() as u16 + 1),
+        Print(status),
+        terminal::Clear(terminal::ClearType::UntilNewLine)
+    )?;
+    stdout.flush()
+}
+
+// Terminal frontend, running the same frames as the graphical one. Keys 0-9 and A Z E R T Y
+// are the CHIP8 keys, Esc quits, P pauses, N advances a frame while paused, M and F6 change
+// the speed, F9 resets and F10 writes the profile.
+pub fn run_program(options: Options) -> std::io::Result<()> {
+    let mut emulator = new_emulator(&options);
+    emulator.trace = false;
+    let rom_database = open_rom_database(&options)
+        .map_err(|error| std::io::Error::other(format!("Cannot load ROM database: {}", error)))?;
+    let mut rpl_flags = open_rpl_flags(&options);
+    let mut cheats = CheatEngine::new();
+    let mut rom_settings = reload_rom(
+        &mut emulator,
+        &options.rom_path,
+        &rom_database,
+        &mut rpl_flags,
+        &mut cheats,
+        &options,
+    )?;
+    let mut script = load_script(&mut emulator, &options)
+        .map_err(|error| std::io::Error::other(format!("Cannot load script: {}", error)))?;
+    let mut rom_watcher = if options.watch_rom {
+        Some(RomWatcher::new(&rom_loader::source_file(&options.rom_path)))
+    } else {
+        None
+    };
+    let mut frames_since_rom_check = 0;
+    let mut speed_control = SpeedControl::new();
+    let mut message = match &rom_settings.key_hints {
+        Some(key_hints) => format!("{} - {}", rom_settings.title, key_hints),
+        None => format!("Loaded {}", rom_settings.title),
+    };
+
+    let guard = TerminalGuard::enter()?;
+    let mut held_keys = HeldKeys::new(guard.release_events);
+    let mut stdout = std::io::stdout();
+    let mut drawn_rows = Vec::new();
+    let mut sound_playing = false;
+
+    'running: loop {
+        while event::poll(Duration::ZERO)? {
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                Event::Resize(_, _) => {
+                    drawn_rows.clear();
+                    queue!(stdout, terminal::Clear(terminal::ClearType::All))?;
+                    continue;
+                }
+                _ => continue,
+            };
+            let KeyEvent {
+                code,
+                modifiers,
+                kind,
+                ..
+            } = key;
+            if let Some(chip8_code) = map_key_to_chip8_code(code) {
+                match kind {
+                    KeyEventKind::Press | KeyEventKind::Repeat => {
+                        held_keys.press(&mut emulator, chip8_code)
+                    }
+                    KeyEventKind::Release => held_keys.release(&mut emulator, chip8_code),
+                }
+                continue;
+            }
+            if kind != KeyEventKind::Press {
+                continue;
+            }
+            match code {
+                KeyCode::Esc => break 'running,
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => break 'running,
+                KeyCode::Char('p' | 'P') => speed_control.toggle_pause(),
+                KeyCode::Char('n' | 'N') => speed_control.request_frame_advance(),
+                KeyCode::Char('m' | 'M') => {
+                    speed_control.next_slow_motion_divisor();
+                    message = format!("Slow motion: 1/{}", speed_control.slow_motion_divisor());
+                }
+                KeyCode::F(6) => {
+                    speed_control.next_fast_forward_multiplier();
+                    message = match speed_control.fast_forward_multiplier() {
+                        Some(multiplier) => format!("Fast forward: x{}", multiplier),
+                        None => "Fast forward: uncapped".to_string(),
+                    };
+                }
+                KeyCode::F(9) => {
+                    match reload_rom(
+                        &mut emulator,
+                        &options.rom_path,
+                        &rom_database,
+                        &mut rpl_flags,
+                        &mut cheats,
+                        &options,
+                    ) {
+                        Ok(settings) => {
+                            rom_settings = settings;
+                            message = "Reset, ROM reloaded".to_string();
+                        }
+                        Err(error) => message = format!("Cannot reload ROM: {}", error),
+                    }
+                }
+                KeyCode::F(10) => {
+                    if let Some(profile_path) = &options.profile_path {
+                        message = match write_profile(&emulator, &rom_settings, profile_path) {
+                            Ok(()) => format!("Profile written to {}", profile_path.display()),
+                            Err(error) => format!("Cannot write profile: {}", error),
+                        };
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(rom_watcher) = &mut rom_watcher {
+            frames_since_rom_check += 1;
+            if frames_since_rom_check >= ROM_WATCH_PERIOD_IN_FRAMES {
+                frames_since_rom_check = 0;
+                if rom_watcher.has_changed() {
+                    match reload_rom(
+                        &mut emulator,
+                        &options.rom_path,
+                        &rom_database,
+                        &mut rpl_flags,
+                        &mut cheats,
+                        &options,
+                    ) {
+                        Ok(settings) => {
+                            rom_settings = settings;
+                            message = "ROM changed on disk, restarted".to_string();
+                        }
+                        Err(error) => message = format!("Cannot reload ROM: {}", error),
+                    }
+                }
+            }
+        }
+
+        let frame_start = Instant::now();
+        run_frame_budget(
+            &mut emulator,
+            &cheats,
+            &mut script,
+            speed_control.next_frame_budget(),
+            rom_settings.instructions_per_frame,
+            frame_start,
+        );
+        held_keys.expire(&mut emulator);
+        if let Some(script) = &mut script {
+            if let Some(script_message) = script.take_error().or(script.take_messages().pop()) {
+                message = script_message;
+            }
+            if script.finished() {
+                break 'running;
+            }
+        }
+        if let Err(error) = rpl_flags.save_if_written(&mut emulator) {
+            message = format!("Cannot save RPL user flags: {}", error);
+        }
+
+        // The terminal bell rings once at the start of every sound
+        let sound = emulator.sound_clock > 0 && !speed_control.paused;
+        if sound && !sound_playing {
+            queue!(stdout, Print('\x07'))?;
+        }
+        sound_playing = sound;
+
+        let screen_lines = if options.braille {
+            render_braille(&emulator.screen)
+        } else {
+            render_half_blocks(&emulator.screen)
+        };
+        emulator.screen_changed = false;
+        let panel_lines = side_panel(&emulator);
+        let screen_width = screen_lines[0].chars().count();
+        let rows: Vec<(String, String)> = (0..screen_lines.len().max(panel_lines.len()))
+            .map(|index| {
+                (
+                    screen_lines
+                        .get(index)
+                        .cloned()
+                        .unwrap_or_else(|| " ".repeat(screen_width)),
+                    panel_lines.get(index).cloned().unwrap_or_default(),
+                )
+            })
+            .collect();
+        let indicator = speed_control.indicator().or(if emulator.waiting_for_key {
+            Some("WAITING FOR KEY".to_string())
+        } else {
+            None
+        });
+        let status = match indicator {
+            Some(indicator) => format!("[{}] {}", indicator, message),
+            None => message.clone(),
+        };
+        draw(
+            &mut stdout,
+            &rows,
+            &mut drawn_rows,
+            &status,
+            rom_settings.palette,
+        )?;
+
+        // About 60Hz of refresh time
+        ::std::thread::sleep(FRAME_DURATION.saturating_sub(frame_start.elapsed()));
+    }
+
+    drop(guard);
+    save_at_exit(&mut emulator, &mut rpl_flags, &rom_settings, &options);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lights the pixels (0, 0), (1, 1) and (0, 3)
+    fn screen() -> Emulator {
+        let mut emulator = Emulator::new();
+        for (i, j) in [(0, 0), (1, 1), (0, 3)] {
+            emulator.screen[j * CHIP8_SCREEN_WIDTH + i] = PixelStatus::White;
+        }
+        emulator
+    }
+
+    #[test]
+    fn test_render_half_blocks() {
+        let lines = render_half_blocks(&screen().screen);
+        assert_eq!(lines.len(), CHIP8_SCREEN_HEIGHT / 2);
+        assert!(lines[0].starts_with("▀▄ "));
+        assert!(lines[1].starts_with("▄ "));
+        assert_eq!(lines[1].chars().count(), CHIP8_SCREEN_WIDTH);
+    }
+
+    #[test]
+    fn test_render_braille() {
+        let lines = render_braille(&screen().screen);
+        assert_eq!(lines.len(), CHIP8_SCREEN_HEIGHT / 4);
+        assert!(lines[0].starts_with("⡑⠀"));
+        assert_eq!(lines[0].chars().count(), CHIP8_SCREEN_WIDTH / 2);
+    }
+
+    #[test]
+    fn test_side_panel() {
+        let mut emulator = Emulator::new();
+        emulator.load_program(&[0x60, 0x2A, 0xA3, 0x00]);
+        emulator.trace = false;
+        emulator.process_next_instruction();
+        let lines = side_panel(&emulator);
+        assert_eq!(lines[0], "PC 202  I 000  SP 0");
+        assert_eq!(lines[2], "V0 2A  V1 00  V2 00  V3 00");
+        assert!(lines.contains(&"  200  602A  LD V0, 0x2A".to_string()));
+        assert!(lines.contains(&"> 202  A300  LD I, 0x300".to_string()));
+    }
+}
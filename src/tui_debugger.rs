@@ -0,0 +1,555 @@
+use crate::cheats::CheatEngine;
+use crate::debugger::{Debugger, StopReason, WatchKind, Watchpoint};
+use crate::disassembler::disassemble;
+use crate::emulator::{Emulator, CHIP8_MEMORY_SIZE, CHIP8_NUMBER_REGISTERS};
+use crate::frontend::FRAME_DURATION;
+use crate::tui::{map_key_to_chip8_code, render_braille, HeldKeys, TerminalGuard};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, SetAttribute, SetForegroundColor};
+use crossterm::{cursor, queue, terminal};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+// Width of the disassembly pane, the other panes are stacked on its right
+const DISASSEMBLY_WIDTH: usize = 34;
+const PANE_MARGIN: usize = 2;
+const MEMORY_BYTES_PER_ROW: usize = 8;
+const MEMORY_ROWS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tone {
+    Plain,
+    Title,
+    // Register changed since the program last stopped
+    Changed,
+    ProgramCounter,
+    Breakpoint,
+    // Line selected in the disassembly, where breakpoints are toggled
+    Cursor,
+}
+
+// Text of a terminal row, in pieces of different tones
+type Line = Vec<(Tone, String)>;
+
+fn plain(text: String) -> Line {
+    vec![(Tone::Plain, text)]
+}
+
+fn title(text: &str) -> Line {
+    vec![(Tone::Title, text.to_string())]
+}
+
+#[cfg(test)]
+fn text(line: &Line) -> String {
+    line.iter().map(|(_, text)| text.as_str()).collect()
+}
+
+// Registers as they were when the program last stopped, to highlight what an instruction changed
+#[derive(Clone, PartialEq)]
+struct RegisterSnapshot {
+    registers: [u8; CHIP8_NUMBER_REGISTERS],
+    index_register: usize,
+    program_counter: usize,
+    stack_pointer: usize,
+    system_clock: u8,
+    sound_clock: u8,
+}
+
+impl RegisterSnapshot {
+    fn take(emulator: &Emulator) -> Self {
+        Self {
+            registers: *emulator.registers(),
+            index_register: emulator.index_register(),
+            program_counter: emulator.program_counter(),
+            stack_pointer: emulator.stack_pointer(),
+            system_clock: emulator.system_clock,
+            sound_clock: emulator.sound_clock,
+        }
+    }
+}
+
+// Disassembly of height lines starting at start, '*' marks breakpoints and '>' the program counter.
+// Addresses with a symbol are preceded by a "name:" line.
+fn disassembly_pane(
+    emulator: &Emulator,
+    debugger: &Debugger,
+    start: usize,
+    cursor: usize,
+    height: usize,
+) -> Vec<Line> {
+    let mut lines = vec![title("Disassembly")];
+    let end = (start + 2 * height.saturating_sub(1)).min(CHIP8_MEMORY_SIZE);
+    for line in disassemble(emulator.memory(), start, end, &emulator.symbols) {
+        if let Some(name) = emulator.symbols.name(line.address) {
+            lines.push(plain(format!("{}:", name)));
+        }
+        let breakpoint = debugger.breakpoints.contains(&line.address);
+        let current = line.address == emulator.program_counter();
+        let tone = if line.address == cursor {
+            Tone::Cursor
+        } else if current {
+            Tone::ProgramCounter
+        } else if breakpoint {
+            Tone::Breakpoint
+        } else {
+            Tone::Plain
+        };
+        let text = format!(
+            "{}{} {:03X}  {:04X}  {}",
+            if breakpoint { '*' } else { ' ' },
+            if current { '>' } else { ' ' },
+            line.address,
+            line.raw_opcode,
+            line.text
+        );
+        lines.push(vec![(tone, text)]);
+    }
+    lines.truncate(height);
+    lines
+}
+
+fn registers_pane(emulator: &Emulator, previous: &RegisterSnapshot) -> Vec<Line> {
+    let current = RegisterSnapshot::take(emulator);
+    let field = |name: String, value: String, changed: bool| {
+        let tone = if changed { Tone::Changed } else { Tone::Plain };
+        vec![(Tone::Plain, name), (tone, value)]
+    };
+    let mut lines = vec![title("Registers")];
+    let row = [
+        field(
+            "PC ".to_string(),
+            format!("{:03X}", current.program_counter),
+            false,
+        ),
+        field(
+            "  I ".to_string(),
+            format!("{:03X}", current.index_register),
+            current.index_register != previous.index_register,
+        ),
+        field(
+            "  SP ".to_string(),
+            format!("{:X}", current.stack_pointer),
+            current.stack_pointer != previous.stack_pointer,
+        ),
+        field(
+            "  DT ".to_string(),
+            format!("{:02X}", current.system_clock),
+            current.system_clock != previous.system_clock,
+        ),
+        field(
+            "  ST ".to_string(),
+            format!("{:02X}", current.sound_clock),
+            current.sound_clock != previous.sound_clock,
+        ),
+    ];
+    lines.push(row.concat());
+    for first in (0..CHIP8_NUMBER_REGISTERS).step_by(4) {
+        let row: Vec<Line> = (first..first + 4)
+            .map(|x| {
+                field(
+                    format!("{}V{:X} ", if x == first { "" } else { "  " }, x),
+                    format!("{:02X}", current.registers[x]),
+                    current.registers[x] != previous.registers[x],
+                )
+            })
+            .collect();
+        lines.push(row.concat());
+    }
+    lines
+}
+
+fn call_stack_pane(emulator: &Emulator) -> Vec<Line> {
+    let mut lines = vec![title("Call stack")];
+    if emulator.call_stack().is_empty() {
+        lines.push(plain("(empty)".to_string()));
+    }
+    for (depth, call) in emulator.call_stack().iter().enumerate().rev() {
+        let subroutine = ((emulator.memory()[*call] as usize & 0x0F) << 8)
+            | emulator.memory()[*call + 1] as usize;
+        lines.push(plain(format!(
+            "#{:<2} {} called from {}",
+            depth,
+            emulator.symbols.format_address(subroutine),
+            emulator.symbols.locate(*call)
+        )));
+    }
+    lines
+}
+
+// Hex and ASCII dump of the memory around I, the byte at I and watched bytes are highlighted
+fn memory_pane(emulator: &Emulator) -> Vec<Line> {
+    let index_register = emulator.index_register().min(CHIP8_MEMORY_SIZE - 1);
+    let first_row = (index_register / MEMORY_BYTES_PER_ROW)
+        .min(CHIP8_MEMORY_SIZE / MEMORY_BYTES_PER_ROW - MEMORY_ROWS);
+    let mut lines = vec![title("Memory at I")];
+    for row in first_row..first_row + MEMORY_ROWS {
+        let start = row * MEMORY_BYTES_PER_ROW;
+        let bytes = &emulator.memory()[start..start + MEMORY_BYTES_PER_ROW];
+        let mut line = vec![(Tone::Plain, format!("{:03X} ", start))];
+        for (offset, byte) in bytes.iter().enumerate() {
+            let address = start + offset;
+            let watched = emulator.watchpoints.iter().any(|watchpoint| {
+                (watchpoint.address..watchpoint.address + watchpoint.length).contains(&address)
+            });
+            let tone = if address == index_register {
+                Tone::Changed
+            } else if watched {
+                Tone::Breakpoint
+            } else {
+                Tone::Plain
+            };
+            line.push((Tone::Plain, " ".to_string()));
+            line.push((tone, format!("{:02X}", byte)));
+        }
+        let ascii: String = bytes
+            .iter()
+            .map(|byte| {
+                if byte.is_ascii_graphic() {
+                    *byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        line.push((Tone::Plain, format!("  {}", ascii)));
+        lines.push(line);
+    }
+    lines
+}
+
+fn screen_pane(emulator: &Emulator) -> Vec<Line> {
+    let mut lines = vec![title("Screen")];
+    lines.extend(render_braille(&emulator.screen).into_iter().map(plain));
+    lines
+}
+
+// Disassembly on the left, the other panes stacked on the right, cut to height rows
+fn layout(left: Vec<Line>, right: Vec<Line>, height: usize) -> Vec<Line> {
+    let width = |line: &Line| -> usize { line.iter().map(|(_, text)| text.chars().count()).sum() };
+    (0..height)
+        .map(|row| {
+            let mut line: Line = Vec::new();
+            if let Some(left_line) = left.get(row) {
+                for (tone, text) in left_line {
+                    line.push((*tone, text.chars().take(DISASSEMBLY_WIDTH).collect()));
+                }
+            }
+            let padding = (DISASSEMBLY_WIDTH + PANE_MARGIN).saturating_sub(width(&line));
+            line.push((Tone::Plain, " ".repeat(padding)));
+            if let Some(right_line) = right.get(row) {
+                line.extend(right_line.iter().cloned());
+            }
+            line
+        })
+        .collect()
+}
+
+fn draw(
+    stdout: &mut std::io::Stdout,
+    lines: &[Line],
+    drawn_lines: &mut Vec<Line>,
+) -> std::io::Result<()> {
+    if drawn_lines.len() != lines.len() {
+        queue!(stdout, terminal::Clear(terminal::ClearType::All))?;
+        drawn_lines.clear();
+    }
+    for (row, line) in lines.iter().enumerate() {
+        if drawn_lines.get(row) == Some(line) {
+            continue;
+        }
+        queue!(stdout, cursor::MoveTo(0, row as u16))?;
+        for (tone, text) in line {
+            match tone {
+                Tone::Plain => (),
+                Tone::Title => queue!(stdout, SetAttribute(Attribute::Bold))?,
+                Tone::Changed => queue!(stdout, SetForegroundColor(Color::Yellow))?,
+                Tone::ProgramCounter => queue!(stdout, SetForegroundColor(Color::Green))?,
+                Tone::Breakpoint => queue!(stdout, SetForegroundColor(Color::Red))?,
+                Tone::Cursor => queue!(stdout, SetAttribute(Attribute::Reverse))?,
+            }
+            queue!(stdout, Print(text), SetAttribute(Attribute::Reset))?;
+        }
+        queue!(stdout, terminal::Clear(terminal::ClearType::UntilNewLine))?;
+    }
+    *drawn_lines = lines.to_vec();
+    stdout.flush()
+}
+
+fn stop_message(reason: StopReason) -> String {
+    match reason {
+        StopReason::Step => "Stepped".to_string(),
+        StopReason::Breakpoint(address) => format!("Breakpoint at 0x{:03X}", address),
+        StopReason::Watchpoint(hit) => {
+            format!("{:?} watchpoint hit at 0x{:03X}", hit.kind, hit.address)
+        }
+        StopReason::HistoryStart => "Reached the start of the recorded history".to_string(),
+    }
+}
+
+// Full-screen debugger. S steps, C continues, P stops, B toggles a breakpoint on the selected
+// line, the arrows and page keys move the selection, '.' selects the program counter again and
+// Q quits. U steps back and R continues backwards, W toggles a write watchpoint on the byte at I.
+// ':' opens the command line of the cheat console, with the commands of the graphical one.
+// While the program runs, the CHIP8 keys are the same as in the terminal frontend.
+pub fn run(
+    mut emulator: Emulator,
+    instructions_per_frame: u32,
+    cheats: CheatEngine,
+) -> std::io::Result<()> {
+    emulator.trace = false;
+    let mut debugger = Debugger::new(instructions_per_frame);
+    debugger.cheats = cheats;
+    // Command line of the cheat console, keys go to it instead of the debugger while it is open
+    let mut cheat_console: Option<String> = None;
+    let mut previous = RegisterSnapshot::take(&emulator);
+    let mut running = false;
+    let mut cursor = emulator.program_counter();
+    let mut message = "Stopped".to_string();
+
+    let guard = TerminalGuard::enter()?;
+    let mut held_keys = HeldKeys::new(guard.release_events);
+    let mut stdout = std::io::stdout();
+    let mut drawn_lines = Vec::new();
+
+    'running: loop {
+        let frame_start = Instant::now();
+        let (_, rows) = terminal::size()?;
+        let pane_height = (rows as usize).saturating_sub(1).max(1);
+        let mut follow_program_counter = false;
+
+        while event::poll(Duration::ZERO)? {
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                Event::Resize(_, _) => {
+                    drawn_lines.clear();
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Some(command) = &mut cheat_console {
+                if key.kind == KeyEventKind::Release {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Enter => {
+                        message = match debugger.execute_cheat(&mut emulator, command) {
+                            Ok(output) => output,
+                            Err(error) => error,
+                        };
+                        command.clear();
+                    }
+                    KeyCode::Backspace => {
+                        command.pop();
+                    }
+                    KeyCode::Esc => cheat_console = None,
+                    KeyCode::Char(character) => command.push(character),
+                    _ => (),
+                }
+                continue;
+            }
+            if running {
+                if let Some(chip8_code) = map_key_to_chip8_code(key.code) {
+                    match key.kind {
+                        KeyEventKind::Press | KeyEventKind::Repeat => {
+                            held_keys.press(&mut emulator, chip8_code)
+                        }
+                        KeyEventKind::Release => held_keys.release(&mut emulator, chip8_code),
+                    }
+                    continue;
+                }
+            }
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q' | 'Q') | KeyCode::Esc => break 'running,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break 'running
+                }
+                KeyCode::Char('s' | 'S') | KeyCode::F(11) if !running => {
+                    previous = RegisterSnapshot::take(&emulator);
+                    message = stop_message(debugger.step(&mut emulator));
+                    follow_program_counter = true;
+                }
+                KeyCode::Char('c' | 'C') | KeyCode::F(5) if !running => {
+                    previous = RegisterSnapshot::take(&emulator);
+                    running = true;
+                    message = "Running".to_string();
+                }
+                KeyCode::Char('u' | 'U') if !running => {
+                    previous = RegisterSnapshot::take(&emulator);
+                    message = match debugger.step_back(&mut emulator) {
+                        StopReason::Step => "Stepped back".to_string(),
+                        reason => stop_message(reason),
+                    };
+                    follow_program_counter = true;
+                }
+                KeyCode::Char('r' | 'R') if !running => {
+                    previous = RegisterSnapshot::take(&emulator);
+                    message = stop_message(debugger.reverse_continue(&mut emulator));
+                    follow_program_counter = true;
+                }
+                KeyCode::Char('w' | 'W') => {
+                    let watchpoint = Watchpoint {
+                        kind: WatchKind::Write,
+                        address: emulator.index_register(),
+                        length: 1,
+                    };
+                    message = if emulator.watchpoints.contains(&watchpoint) {
+                        emulator
+                            .watchpoints
+                            .retain(|existing| *existing != watchpoint);
+                        format!("Watchpoint removed at 0x{:03X}", watchpoint.address)
+                    } else {
+                        emulator.watchpoints.push(watchpoint);
+                        format!("Write watchpoint set at 0x{:03X}", watchpoint.address)
+                    };
+                }
+                KeyCode::Char('p' | 'P') if running => {
+                    running = false;
+                    message = "Stopped".to_string();
+                    follow_program_counter = true;
+                }
+                KeyCode::Char('b' | 'B') | KeyCode::F(9) => {
+                    message = if debugger.toggle_breakpoint(cursor) {
+                        format!("Breakpoint set at 0x{:03X}", cursor)
+                    } else {
+                        format!("Breakpoint removed at 0x{:03X}", cursor)
+                    };
+                }
+                KeyCode::Up => cursor = cursor.saturating_sub(2),
+                KeyCode::Down => cursor = (cursor + 2).min(CHIP8_MEMORY_SIZE - 2),
+                KeyCode::PageUp => cursor = cursor.saturating_sub(2 * pane_height),
+                KeyCode::PageDown => cursor = (cursor + 2 * pane_height).min(CHIP8_MEMORY_SIZE - 2),
+                KeyCode::Char('.') => follow_program_counter = true,
+                KeyCode::Char(':') => cheat_console = Some(String::new()),
+                _ => (),
+            }
+        }
+
+        if running {
+            if let Some(reason) = debugger.run_frame(&mut emulator) {
+                running = false;
+                message = stop_message(reason);
+            }
+            follow_program_counter = true;
+            held_keys.expire(&mut emulator);
+        }
+        if follow_program_counter {
+            cursor = emulator.program_counter();
+        }
+
+        // The selected line stays in the middle of the disassembly
+        let start = cursor.saturating_sub(pane_height / 2 * 2);
+        let mut right = registers_pane(&emulator, &previous);
+        for pane in [
+            call_stack_pane(&emulator),
+            memory_pane(&emulator),
+            screen_pane(&emulator),
+        ] {
+            right.push(Vec::new());
+            right.extend(pane);
+        }
+        let mut lines = layout(
+            disassembly_pane(&emulator, &debugger, start, cursor, pane_height),
+            right,
+            pane_height,
+        );
+        lines.push(plain(match &cheat_console {
+            Some(command) => format!(":{}_  {}", command, message),
+            None => format!(
+                "[{}] {}  |  s step  c continue  p stop  u back  r reverse  b break  w watch I  \
+                 : cheats  q quit",
+                if running { "RUNNING" } else { "STOPPED" },
+                message
+            ),
+        }));
+        draw(&mut stdout, &lines, &mut drawn_lines)?;
+
+        ::std::thread::sleep(FRAME_DURATION.saturating_sub(frame_start.elapsed()));
+    }
+
+    drop(guard);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::Symbols;
+
+    // Calls a subroutine at 0x206 which loads I and V1
+    const PROGRAM: [u8; 10] = [0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0xA3, 0x00, 0x61, 0x41];
+
+    fn emulator() -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator.trace = false;
+        emulator.load_program(&PROGRAM);
+        emulator
+    }
+
+    #[test]
+    fn test_disassembly_pane() {
+        let mut emulator = emulator();
+        emulator.process_next_instruction();
+        let mut debugger = Debugger::new(20);
+        debugger.toggle_breakpoint(0x208);
+        let lines = disassembly_pane(&emulator, &debugger, 0x200, 0x200, 6);
+        let texts: Vec<String> = lines.iter().map(text).collect();
+        assert_eq!(texts[1], "   200  2206  CALL 0x206");
+        assert_eq!(texts[4], " > 206  A300  LD I, 0x300");
+        assert_eq!(texts[5], "*  208  6141  LD V1, 0x41");
+        assert_eq!(lines[1][0].0, Tone::Cursor);
+        assert_eq!(lines[4][0].0, Tone::ProgramCounter);
+        assert_eq!(lines[5][0].0, Tone::Breakpoint);
+
+        emulator.symbols = Symbols::parse_text("206 draw_player").unwrap();
+        let texts: Vec<String> = disassembly_pane(&emulator, &debugger, 0x200, 0x200, 6)
+            .iter()
+            .map(text)
+            .collect();
+        assert_eq!(texts[1], "   200  2206  CALL draw_player");
+        assert_eq!(texts[4], "draw_player:");
+        assert_eq!(texts[5], " > 206  A300  LD I, 0x300");
+        assert_eq!(texts.len(), 6);
+    }
+
+    #[test]
+    fn test_registers_pane_highlights_changes() {
+        let mut emulator = emulator();
+        emulator.process_next_instruction();
+        emulator.process_next_instruction();
+        let previous = RegisterSnapshot::take(&emulator);
+        emulator.process_next_instruction();
+        let lines = registers_pane(&emulator, &previous);
+        assert_eq!(text(&lines[1]), "PC 20A  I 300  SP 1  DT 00  ST 00");
+        assert_eq!(text(&lines[2]), "V0 00  V1 41  V2 00  V3 00");
+        assert!(lines[1].contains(&(Tone::Plain, "300".to_string())));
+        assert!(lines[2].contains(&(Tone::Changed, "41".to_string())));
+    }
+
+    #[test]
+    fn test_call_stack_and_memory_panes() {
+        let mut emulator = emulator();
+        assert_eq!(text(&call_stack_pane(&emulator)[1]), "(empty)");
+        for _ in 0..2 {
+            emulator.process_next_instruction();
+        }
+        assert_eq!(
+            text(&call_stack_pane(&emulator)[1]),
+            "#0  0x206 called from 0x200"
+        );
+        emulator.symbols = Symbols::parse_text("200 main\n206 draw_player").unwrap();
+        assert_eq!(
+            text(&call_stack_pane(&emulator)[1]),
+            "#0  draw_player called from main"
+        );
+
+        emulator.write_memory(0x300, b'H');
+        emulator.write_memory(0x301, b'i');
+        let lines = memory_pane(&emulator);
+        assert_eq!(text(&lines[1]), "300  48 69 00 00 00 00 00 00  Hi......");
+        assert_eq!(lines[1][2], (Tone::Changed, "48".to_string()));
+    }
+}
@@ -1,17 +1,32 @@
 extern crate sdl2;
+use crate::cheats::CheatEngine;
+use crate::display_filter;
 use crate::emulator;
+use crate::frontend::{
+    load_script, new_emulator, open_rom_database, open_rpl_flags, reload_rom, run_frame_budget,
+    save_at_exit, write_profile, Options, RomSettings, FRAME_DURATION, ROM_WATCH_PERIOD_IN_FRAMES,
+};
+use crate::hud;
+use crate::memory_heatmap::{MemoryHeatmap, HEATMAP_SIDE};
+use crate::rom::RomWatcher;
+use crate::rom_browser::RomBrowser;
+use crate::rom_loader;
+use crate::speed_control::{FrameBudget, SpeedControl};
 
 use emulator::{CHIP8_SCREEN_HEIGHT, CHIP8_SCREEN_WIDTH};
 use sdl2::audio::{AudioCallback, AudioSpecDesired};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use std::time::Duration;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 const PIXEL_SIZE_RATIO: u32 = 15;
 const SDL_SCREEN_WIDTH: u32 = (emulator::CHIP8_SCREEN_WIDTH as u32) * PIXEL_SIZE_RATIO;
 const SDL_SCREEN_HEIGHT: u32 = (emulator::CHIP8_SCREEN_HEIGHT as u32) * PIXEL_SIZE_RATIO;
-
 struct SquareWave {
     phase_inc: f32,
     phase: f32,
@@ -34,6 +49,70 @@ impl AudioCallback for SquareWave {
     }
 }
 
+// Largest area keeping the CHIP8 aspect ratio that fits in the output, centered
+fn letterbox_rect(output_width: u32, output_height: u32, integer_scaling: bool) -> Rect {
+    let horizontal_scale = output_width as f32 / CHIP8_SCREEN_WIDTH as f32;
+    let vertical_scale = output_height as f32 / CHIP8_SCREEN_HEIGHT as f32;
+    let mut scale = horizontal_scale.min(vertical_scale);
+    if integer_scaling && scale >= 1.0 {
+        scale = scale.floor();
+    }
+    let width = (CHIP8_SCREEN_WIDTH as f32 * scale) as u32;
+    let height = (CHIP8_SCREEN_HEIGHT as f32 * scale) as u32;
+    Rect::new(
+        ((output_width - width) / 2) as i32,
+        ((output_height - height) / 2) as i32,
+        width.max(1),
+        height.max(1),
+    )
+}
+
+// One cell per memory byte, with the byte at the program counter in white
+fn draw_heatmap(
+    canvas: &mut Canvas<Window>,
+    texture: &mut Texture,
+    heatmap: &MemoryHeatmap,
+    program_counter: usize,
+    rect: Rect,
+) {
+    texture
+        .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            for address in 0..HEATMAP_SIDE * HEATMAP_SIDE {
+                let offset = (address / HEATMAP_SIDE) * pitch + (address % HEATMAP_SIDE) * 3;
+                let color = if address == program_counter || address == program_counter + 1 {
+                    [u8::MAX; 3]
+                } else {
+                    heatmap.color(address)
+                };
+                buffer[offset..offset + 3].copy_from_slice(&color);
+            }
+        })
+        .unwrap();
+    canvas.copy(texture, None, Some(rect)).unwrap();
+}
+
+fn toggle_fullscreen(window: &mut sdl2::video::Window) {
+    let fullscreen_type = match window.fullscreen_state() {
+        FullscreenType::Off => FullscreenType::Desktop,
+        _ => FullscreenType::Off,
+    };
+    window.set_fullscreen(fullscreen_type).unwrap();
+}
+
+fn show_rom_settings(window: &mut sdl2::video::Window, hud: &mut hud::Hud, settings: &RomSettings) {
+    window
+        .set_title(&format!("CHIP8 emulator - {}", settings.title))
+        .unwrap();
+    let mut message = match &settings.key_hints {
+        Some(key_hints) => format!("{} - {}", settings.title, key_hints),
+        None => format!("Loaded {}", settings.title),
+    };
+    if let Some(patch_path) = &settings.patch_path {
+        message += &format!(" (patched with {})", patch_path.display());
+    }
+    hud.show_message(message);
+}
+
 fn map_sdl_keycode_to_chip8_code(sdl_code: Keycode) -> Option<u8> {
     match sdl_code {
         Keycode::KP_0 => Some(0x00),
@@ -56,7 +135,7 @@ fn map_sdl_keycode_to_chip8_code(sdl_code: Keycode) -> Option<u8> {
     }
 }
 
-pub fn run_program() {
+pub fn run_program(options: Options) {
     // SDL setup
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -83,15 +162,87 @@ pub fn run_program() {
     let window = video_subsystem
         .window("CHIP8 emulator", SDL_SCREEN_WIDTH, SDL_SCREEN_HEIGHT)
         .position_centered()
+        .resizable()
         .build()
         .unwrap();
 
     let mut canvas = window.into_canvas().build().unwrap();
 
+    // The whole CHIP8 screen is uploaded in a single texture, then scaled by SDL
+    let texture_creator = canvas.texture_creator();
+    let mut screen_texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            CHIP8_SCREEN_WIDTH as u32,
+            CHIP8_SCREEN_HEIGHT as u32,
+        )
+        .unwrap();
+    let mut integer_scaling = false;
+
+    // Memory heatmap panel, shown on the right of the screen
+    let mut heatmap_texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            HEATMAP_SIDE as u32,
+            HEATMAP_SIDE as u32,
+        )
+        .unwrap();
+
     // Emulator setup
-    let mut emulator = emulator::Emulator::new();
+    let mut emulator = new_emulator(&options);
+    let rom_database = match open_rom_database(&options) {
+        Ok(rom_database) => rom_database,
+        Err(error) => {
+            eprintln!("Cannot load ROM database: {}", error);
+            return;
+        }
+    };
+    let mut rpl_flags = open_rpl_flags(&options);
+    let mut cheats = CheatEngine::new();
+    let mut rom_path = options.rom_path.clone();
+    let mut rom_settings = match reload_rom(
+        &mut emulator,
+        &rom_path,
+        &rom_database,
+        &mut rpl_flags,
+        &mut cheats,
+        &options,
+    ) {
+        Ok(rom_settings) => rom_settings,
+        Err(error) => {
+            eprintln!("Cannot load ROM: {}", error);
+            return;
+        }
+    };
+    let mut script = match load_script(&mut emulator, &options) {
+        Ok(script) => script,
+        Err(error) => {
+            eprintln!("Cannot load script: {}", error);
+            return;
+        }
+    };
+    let mut rom_watcher = if options.watch_rom {
+        Some(RomWatcher::new(&rom_loader::source_file(&rom_path)))
+    } else {
+        None
+    };
+    let mut frames_since_rom_check = 0;
 
-    emulator.load_program(std::fs::read("roms/BLINKY").unwrap().as_slice());
+    // Display filter setup, to reduce flickering
+    let mut display_filter = display_filter::DisplayFilter::new();
+
+    // Overlay setup, with statistics and messages
+    let mut hud = hud::Hud::new();
+    show_rom_settings(canvas.window_mut(), &mut hud, &rom_settings);
+
+    // Pause, fast-forward and slow motion
+    let mut speed_control = SpeedControl::new();
+
+    // In-window ROM browser, the emulator does not run while it is open
+    let mut rom_browser: Option<RomBrowser> = None;
+
+    // Command line of the cheat console, keys go to it instead of the emulator while it is open
+    let mut cheat_console: Option<String> = None;
 
     // Event setup
     let mut event_pump = sdl_context.event_pump().unwrap();
@@ -103,12 +254,201 @@ pub fn run_program() {
 
         // Event loop
         for event in event_pump.poll_iter() {
+            let mut rom_to_open: Option<PathBuf> = None;
             match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
+                Event::Quit { .. } => break 'running,
+                Event::DropFile { filename, .. } => rom_to_open = Some(PathBuf::from(filename)),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } if rom_browser.is_some() => {
+                    let browser = rom_browser.as_mut().unwrap();
+                    match keycode {
+                        Keycode::Up => browser.select_previous(),
+                        Keycode::Down => browser.select_next(),
+                        Keycode::Return => {
+                            rom_to_open = browser.selected_entry().map(|entry| entry.path.clone());
+                            rom_browser = None;
+                        }
+                        Keycode::Escape | Keycode::F7 => rom_browser = None,
+                        _ => (),
+                    }
+                }
+                Event::TextInput { text, .. } if cheat_console.is_some() => {
+                    cheat_console.as_mut().unwrap().push_str(&text)
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } if cheat_console.is_some() => match keycode {
+                    Keycode::Return => {
+                        let command = cheat_console.replace(String::new()).unwrap();
+                        match cheats.execute(&mut emulator, &command) {
+                            Ok(output) => hud.show_message(output),
+                            Err(error) => hud.show_message(error),
+                        }
+                    }
+                    Keycode::Backspace => {
+                        cheat_console.as_mut().unwrap().pop();
+                    }
+                    Keycode::Escape | Keycode::F8 => {
+                        cheat_console = None;
+                        video_subsystem.text_input().stop();
+                    }
+                    _ => (),
+                },
+                Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => {
+                    cheat_console = Some(String::new());
+                    video_subsystem.text_input().start();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => {
+                    let directory = match rom_path.parent() {
+                        Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+                        _ => PathBuf::from("."),
+                    };
+                    match RomBrowser::open(&directory, &rom_database) {
+                        Ok(browser) => rom_browser = Some(browser),
+                        Err(error) => hud.show_message(format!("Cannot list ROMs: {}", error)),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    toggle_fullscreen(canvas.window_mut())
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    ..
+                } => {
+                    display_filter.next_mode();
+                    hud.show_message(format!("Display filter: {:?}", display_filter.mode));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } => {
+                    display_filter.decrease_persistence();
+                    hud.show_message(format!(
+                        "Persistence: {} frames",
+                        display_filter.persistence_frames
+                    ));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => {
+                    display_filter.increase_persistence();
+                    hud.show_message(format!(
+                        "Persistence: {} frames",
+                        display_filter.persistence_frames
+                    ));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => {
+                    integer_scaling = !integer_scaling;
+                    hud.show_message(format!("Integer scaling: {}", integer_scaling));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => hud.toggle(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => match reload_rom(
+                    &mut emulator,
+                    &rom_path,
+                    &rom_database,
+                    &mut rpl_flags,
+                    &mut cheats,
+                    &options,
+                ) {
+                    Ok(settings) => {
+                        rom_settings = settings;
+                        hud.show_message("Reset, ROM reloaded".to_string());
+                    }
+                    Err(error) => hud.show_message(format!("Cannot reload ROM: {}", error)),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } => {
+                    if let Some(profile_path) = &options.profile_path {
+                        match write_profile(&emulator, &rom_settings, profile_path) {
+                            Ok(()) => hud.show_message(format!(
+                                "Profile written to {}",
+                                profile_path.display()
+                            )),
+                            Err(error) => {
+                                hud.show_message(format!("Cannot write profile: {}", error))
+                            }
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => {
+                    emulator.heatmap = match emulator.heatmap {
+                        Some(_) => None,
+                        None => Some(MemoryHeatmap::new()),
+                    };
+                    hud.show_message(
+                        "Memory heatmap: green fetches, blue reads, red writes".to_string(),
+                    );
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => {
+                    speed_control.next_fast_forward_multiplier();
+                    hud.show_message(match speed_control.fast_forward_multiplier() {
+                        Some(multiplier) => format!("Fast forward: x{}", multiplier),
+                        None => "Fast forward: uncapped".to_string(),
+                    });
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } => speed_control.toggle_pause(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::N),
+                    ..
+                } => speed_control.request_frame_advance(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    repeat: false,
+                    ..
+                } => {
+                    speed_control.next_slow_motion_divisor();
+                    hud.show_message(format!(
+                        "Slow motion: 1/{}",
+                        speed_control.slow_motion_divisor()
+                    ));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => speed_control.fast_forward = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => speed_control.fast_forward = false,
                 Event::KeyDown {
                     keycode: Some(keycode),
                     ..
@@ -125,52 +465,162 @@ pub fn run_program() {
                 },
                 _ => {}
             }
+
+            if let Some(new_rom_path) = rom_to_open {
+                match reload_rom(
+                    &mut emulator,
+                    &new_rom_path,
+                    &rom_database,
+                    &mut rpl_flags,
+                    &mut cheats,
+                    &options,
+                ) {
+                    Ok(settings) => {
+                        rom_settings = settings;
+                        show_rom_settings(canvas.window_mut(), &mut hud, &rom_settings);
+                        if rom_watcher.is_some() {
+                            rom_watcher =
+                                Some(RomWatcher::new(&rom_loader::source_file(&new_rom_path)));
+                        }
+                        rom_path = new_rom_path;
+                    }
+                    Err(error) => hud.show_message(format!("Cannot load ROM: {}", error)),
+                }
+            }
         }
 
-        // Several cpu cycles per render cycle
-        if !emulator.waiting_for_key {
-            for _ in 0..20 {
-                emulator.process_next_instruction();
-                println!();
+        if let Some(rom_watcher) = &mut rom_watcher {
+            frames_since_rom_check += 1;
+            if frames_since_rom_check >= ROM_WATCH_PERIOD_IN_FRAMES {
+                frames_since_rom_check = 0;
+                if rom_watcher.has_changed() {
+                    match reload_rom(
+                        &mut emulator,
+                        &rom_path,
+                        &rom_database,
+                        &mut rpl_flags,
+                        &mut cheats,
+                        &options,
+                    ) {
+                        Ok(settings) => {
+                            rom_settings = settings;
+                            hud.show_message("ROM changed on disk, restarted".to_string());
+                        }
+                        Err(error) => hud.show_message(format!("Cannot reload ROM: {}", error)),
+                    }
+                }
             }
         }
 
-        if emulator.system_clock > 0 {
-            emulator.system_clock -= 1;
+        let frame_start = Instant::now();
+        let frame_budget = if rom_browser.is_some() {
+            FrameBudget::Frames(0)
+        } else {
+            speed_control.next_frame_budget()
+        };
+        let instructions_executed = run_frame_budget(
+            &mut emulator,
+            &cheats,
+            &mut script,
+            frame_budget,
+            rom_settings.instructions_per_frame,
+            frame_start,
+        );
+        hud.record_frame(instructions_executed);
+        if let Some(script) = &mut script {
+            for message in script
+                .take_messages()
+                .into_iter()
+                .chain(script.take_error())
+            {
+                hud.show_message(message);
+            }
+            if script.finished() {
+                break 'running;
+            }
+        }
+        if let Err(error) = rpl_flags.save_if_written(&mut emulator) {
+            hud.show_message(format!("Cannot save RPL user flags: {}", error));
         }
 
-        if emulator.sound_clock > 0 {
+        if emulator.sound_clock > 0 && !speed_control.paused && rom_browser.is_none() {
             device.resume();
-            emulator.sound_clock -= 1;
         } else {
             device.pause();
         }
 
-        canvas.set_draw_color(Color::WHITE);
-        for i in 0..CHIP8_SCREEN_WIDTH {
-            for j in 0..CHIP8_SCREEN_HEIGHT {
-                match emulator.screen[j * CHIP8_SCREEN_WIDTH + i] {
-                    emulator::PixelStatus::Black => (),
-                    emulator::PixelStatus::White => {
-                        let i = i as i32;
-                        let j = j as i32;
-                        let pixel_size_ratio = PIXEL_SIZE_RATIO as i32;
-                        let white_pixel = sdl2::rect::Rect::new(
-                            i * pixel_size_ratio,
-                            j * pixel_size_ratio,
-                            PIXEL_SIZE_RATIO as u32,
-                            PIXEL_SIZE_RATIO as u32,
-                        );
-                        canvas.fill_rect(white_pixel).unwrap();
+        // Only upload the screen when it is not the same as the previous one
+        if emulator.screen_changed || display_filter.needs_update() {
+            let intensities = display_filter.apply(&emulator.screen);
+            let palette = rom_settings.palette;
+            screen_texture
+                .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                    for j in 0..CHIP8_SCREEN_HEIGHT {
+                        for i in 0..CHIP8_SCREEN_WIDTH {
+                            let intensity = intensities[j * CHIP8_SCREEN_WIDTH + i] as u32;
+                            let offset = j * pitch + i * 3;
+                            for c in 0..3 {
+                                let background = palette.background[c] as u32;
+                                let foreground = palette.foreground[c] as u32;
+                                buffer[offset + c] =
+                                    ((background * (255 - intensity) + foreground * intensity)
+                                        / 255) as u8;
+                            }
+                        }
                     }
-                }
+                })
+                .unwrap();
+            emulator.screen_changed = false;
+        }
+
+        let (output_width, output_height) = canvas.output_size().unwrap();
+        let program_counter = emulator.program_counter();
+        let heatmap_size = match &mut emulator.heatmap {
+            Some(heatmap) => {
+                let size = output_height.min(output_width / 2);
+                draw_heatmap(
+                    &mut canvas,
+                    &mut heatmap_texture,
+                    heatmap,
+                    program_counter,
+                    Rect::new(
+                        (output_width - size) as i32,
+                        ((output_height - size) / 2) as i32,
+                        size.max(1),
+                        size.max(1),
+                    ),
+                );
+                heatmap.fade();
+                size
             }
+            None => 0,
+        };
+        let screen_rect =
+            letterbox_rect(output_width - heatmap_size, output_height, integer_scaling);
+        canvas
+            .copy(&screen_texture, None, Some(screen_rect))
+            .unwrap();
+
+        let indicator = match &cheat_console {
+            Some(command) => Some(format!("CHEAT> {}_", command)),
+            None => speed_control.indicator().or(if emulator.waiting_for_key {
+                Some("WAITING FOR KEY".to_string())
+            } else {
+                None
+            }),
+        };
+        hud.draw(&mut canvas, indicator.as_deref());
+
+        if let Some(browser) = &mut rom_browser {
+            browser.draw(&mut canvas);
         }
 
         // Display new screen
         canvas.present();
 
         // About 60Hz of refresh time
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        ::std::thread::sleep(FRAME_DURATION.saturating_sub(frame_start.elapsed()));
     }
+
+    save_at_exit(&mut emulator, &mut rpl_flags, &rom_settings, &options);
 }
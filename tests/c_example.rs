@@ -0,0 +1,75 @@
+// Builds examples/c/example.c against the shared library and runs it. Extra linker flags, such
+// as library paths, come from the LDFLAGS environment variable.
+mod common;
+
+use common::library_directory;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+// Compiled once for all the tests
+fn build_example() -> PathBuf {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let library_directory = library_directory();
+    let example = Path::new(env!("CARGO_TARGET_TMPDIR")).join("c_example");
+
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(root.join("examples/c/example.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-L")
+        .arg(&library_directory)
+        .arg(format!("-Wl,-rpath,{}", library_directory.display()))
+        .arg("-lchip8")
+        .args(
+            std::env::var("LDFLAGS")
+                .unwrap_or_default()
+                .split_whitespace(),
+        )
+        .arg("-o")
+        .arg(&example)
+        .status()
+        .expect("Unable to run the C compiler");
+    assert!(status.success());
+    example
+}
+
+fn run_example(arguments: &[&str]) -> String {
+    static EXAMPLE: OnceLock<PathBuf> = OnceLock::new();
+    let example = EXAMPLE.get_or_init(build_example);
+    let output = Command::new(example).args(arguments).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_c_example() {
+    let output = run_example(&[]);
+    assert_eq!(
+        output,
+        "instructions: 20\n\
+         sound: on\n\
+         ................................................................\n\
+         ................................................................\n\
+         ..####..........................................................\n\
+         ..#.............................................................\n\
+         ..####..........................................................\n\
+         .....#..........................................................\n\
+         ..####..........................................................\n\
+         ................................................................\n\
+         lit after restore: 0\n"
+    );
+}
+
+#[test]
+fn test_c_example_rom() {
+    let rom = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms/MAZE");
+    let output = run_example(&[rom.to_str().unwrap(), "60"]);
+    assert!(output.starts_with("instructions: "));
+    assert!(output.contains('#'));
+    assert!(output.ends_with("lit after restore: 0\n"));
+}
@@ -0,0 +1,45 @@
+// Shared library of the crate for the tests loading it from C. cargo test only builds the
+// test executables, so the library is built with cargo, in the same target directory and
+// profile and with the same features.
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+// The directory of libchip8.so, next to the deps directory of the test executable
+pub fn library_directory() -> PathBuf {
+    static DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+    DIRECTORY.get_or_init(build_library).clone()
+}
+
+fn build_library() -> PathBuf {
+    let executable = std::env::current_exe().unwrap();
+    let directory = executable.parent().unwrap().parent().unwrap().to_path_buf();
+    let profile = match directory.file_name().unwrap().to_str().unwrap() {
+        "debug" => "dev",
+        profile => profile,
+    };
+    let mut features = Vec::new();
+    if cfg!(feature = "sdl") {
+        features.push("sdl");
+    }
+    if cfg!(feature = "python") {
+        features.push("python");
+    }
+
+    let status = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .arg("build")
+        .arg("--lib")
+        .arg("--manifest-path")
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+        .arg("--target-dir")
+        .arg(directory.parent().unwrap())
+        .arg("--profile")
+        .arg(profile)
+        .arg("--no-default-features")
+        .arg("--features")
+        .arg(features.join(","))
+        .status()
+        .expect("Unable to run cargo");
+    assert!(status.success());
+    directory
+}
@@ -0,0 +1,254 @@
+/*
+ * Minimal libretro frontend loading a core with dlopen, to test it without RetroArch:
+ *
+ *     harness CORE ROM FRAMES BUTTONS [KEY=VALUE...]
+ *
+ * BUTTONS is the mask of the RetroPad buttons held, bit N for button id N, and the options
+ * not given are left to their defaults.
+ */
+#include <dlfcn.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define ENVIRONMENT_SET_PIXEL_FORMAT 10
+#define ENVIRONMENT_SET_INPUT_DESCRIPTORS 11
+#define ENVIRONMENT_GET_VARIABLE 15
+#define ENVIRONMENT_SET_VARIABLES 16
+#define ENVIRONMENT_GET_VARIABLE_UPDATE 17
+#define DEVICE_JOYPAD 1
+#define MEMORY_SYSTEM_RAM 2
+#define COUNTER_ADDRESS 0x302
+
+struct system_info {
+    const char *library_name;
+    const char *library_version;
+    const char *valid_extensions;
+    bool need_fullpath;
+    bool block_extract;
+};
+
+struct system_av_info {
+    unsigned base_width, base_height, max_width, max_height;
+    float aspect_ratio;
+    double fps, sample_rate;
+};
+
+struct game_info {
+    const char *path;
+    const void *data;
+    size_t size;
+    const char *meta;
+};
+
+struct variable {
+    const char *key;
+    const char *value;
+};
+
+struct input_descriptor {
+    unsigned port, device, index, id;
+    const char *description;
+};
+
+static char **options;
+static int options_count;
+static unsigned buttons;
+
+static const uint32_t *video;
+static unsigned video_width, video_height;
+static size_t audio_frames, loud_frames;
+
+static bool environment(unsigned command, void *data) {
+    switch (command) {
+    case ENVIRONMENT_SET_PIXEL_FORMAT:
+        printf("pixel format: %u\n", *(unsigned *)data);
+        return true;
+    case ENVIRONMENT_SET_INPUT_DESCRIPTORS: {
+        int count = 0;
+        for (struct input_descriptor *descriptor = data; descriptor->description; descriptor++) {
+            count++;
+        }
+        printf("descriptors: %d\n", count);
+        return true;
+    }
+    case ENVIRONMENT_SET_VARIABLES:
+        printf("options:");
+        for (struct variable *variable = data; variable->key; variable++) {
+            printf(" %s", variable->key);
+        }
+        printf("\n");
+        return true;
+    case ENVIRONMENT_GET_VARIABLE: {
+        struct variable *variable = data;
+        size_t length = strlen(variable->key);
+        for (int i = 0; i < options_count; i++) {
+            if (strncmp(options[i], variable->key, length) == 0 && options[i][length] == '=') {
+                variable->value = options[i] + length + 1;
+                return true;
+            }
+        }
+        return false;
+    }
+    case ENVIRONMENT_GET_VARIABLE_UPDATE:
+        *(bool *)data = false;
+        return true;
+    default:
+        return false;
+    }
+}
+
+static void video_refresh(const void *data, unsigned width, unsigned height, size_t pitch) {
+    (void)pitch;
+    video = data;
+    video_width = width;
+    video_height = height;
+}
+
+static void audio_sample(int16_t left, int16_t right) {
+    (void)left;
+    (void)right;
+}
+
+static size_t audio_sample_batch(const int16_t *data, size_t frames) {
+    audio_frames += frames;
+    for (size_t i = 0; i < frames * 2; i++) {
+        if (data[i] != 0) {
+            loud_frames++;
+            break;
+        }
+    }
+    return frames;
+}
+
+static void input_poll(void) {}
+
+static int16_t input_state(unsigned port, unsigned device, unsigned index, unsigned id) {
+    (void)index;
+    return port == 0 && device == DEVICE_JOYPAD && (buttons >> id & 1);
+}
+
+static int lit_pixels(void) {
+    int lit = 0;
+    for (unsigned pixel = 0; pixel < video_width * video_height; pixel++) {
+        lit += video[pixel] != video[0];
+    }
+    return lit;
+}
+
+#define SYMBOL(name) name##_t name = (name##_t)symbol(core, #name)
+
+typedef unsigned (*retro_api_version_t)(void);
+typedef void (*retro_get_system_info_t)(struct system_info *);
+typedef void (*retro_get_system_av_info_t)(struct system_av_info *);
+typedef void (*retro_set_environment_t)(bool (*)(unsigned, void *));
+typedef void (*retro_set_video_refresh_t)(void (*)(const void *, unsigned, unsigned, size_t));
+typedef void (*retro_set_audio_sample_t)(void (*)(int16_t, int16_t));
+typedef void (*retro_set_audio_sample_batch_t)(size_t (*)(const int16_t *, size_t));
+typedef void (*retro_set_input_poll_t)(void (*)(void));
+typedef void (*retro_set_input_state_t)(int16_t (*)(unsigned, unsigned, unsigned, unsigned));
+typedef void (*retro_init_t)(void);
+typedef void (*retro_deinit_t)(void);
+typedef bool (*retro_load_game_t)(const struct game_info *);
+typedef void (*retro_unload_game_t)(void);
+typedef void (*retro_run_t)(void);
+typedef size_t (*retro_serialize_size_t)(void);
+typedef bool (*retro_serialize_t)(void *, size_t);
+typedef bool (*retro_unserialize_t)(const void *, size_t);
+typedef void *(*retro_get_memory_data_t)(unsigned);
+
+static void *symbol(void *core, const char *name) {
+    void *address = dlsym(core, name);
+    if (!address) {
+        fprintf(stderr, "Missing %s\n", name);
+        exit(1);
+    }
+    return address;
+}
+
+int main(int argc, char **argv) {
+    if (argc < 5) {
+        fprintf(stderr, "Usage: harness CORE ROM FRAMES BUTTONS [KEY=VALUE...]\n");
+        return 1;
+    }
+    void *core = dlopen(argv[1], RTLD_NOW | RTLD_LOCAL);
+    if (!core) {
+        fprintf(stderr, "%s\n", dlerror());
+        return 1;
+    }
+    int frames = atoi(argv[3]);
+    buttons = strtoul(argv[4], NULL, 0);
+    options = argv + 5;
+    options_count = argc - 5;
+
+    SYMBOL(retro_api_version);
+    SYMBOL(retro_get_system_info);
+    SYMBOL(retro_get_system_av_info);
+    SYMBOL(retro_set_environment);
+    SYMBOL(retro_set_video_refresh);
+    SYMBOL(retro_set_audio_sample);
+    SYMBOL(retro_set_audio_sample_batch);
+    SYMBOL(retro_set_input_poll);
+    SYMBOL(retro_set_input_state);
+    SYMBOL(retro_init);
+    SYMBOL(retro_deinit);
+    SYMBOL(retro_load_game);
+    SYMBOL(retro_unload_game);
+    SYMBOL(retro_run);
+    SYMBOL(retro_serialize_size);
+    SYMBOL(retro_serialize);
+    SYMBOL(retro_unserialize);
+    SYMBOL(retro_get_memory_data);
+
+    printf("api: %u\n", retro_api_version());
+    struct system_info info;
+    retro_get_system_info(&info);
+    printf("library: %s %s (%s)\n", info.library_name, info.library_version,
+           info.valid_extensions);
+
+    retro_set_environment(environment);
+    retro_set_video_refresh(video_refresh);
+    retro_set_audio_sample(audio_sample);
+    retro_set_audio_sample_batch(audio_sample_batch);
+    retro_set_input_poll(input_poll);
+    retro_set_input_state(input_state);
+    retro_init();
+
+    struct game_info game = {argv[2], NULL, 0, NULL};
+    if (!retro_load_game(&game)) {
+        fprintf(stderr, "Unable to load %s\n", argv[2]);
+        return 1;
+    }
+    struct system_av_info av_info;
+    retro_get_system_av_info(&av_info);
+    printf("av: %ux%u %g fps %g Hz\n", av_info.base_width, av_info.base_height, av_info.fps,
+           av_info.sample_rate);
+
+    for (int frame = 0; frame < frames; frame++) {
+        retro_run();
+    }
+    const uint8_t *memory = retro_get_memory_data(MEMORY_SYSTEM_RAM);
+    printf("video: %ux%u, %d lit\n", video_width, video_height, lit_pixels());
+    printf("audio: %zu frames, %zu loud\n", audio_frames, loud_frames);
+    printf("counter: %u\n", memory[COUNTER_ADDRESS]);
+
+    /* The frame after a state is the same once the state is loaded again */
+    size_t size = retro_serialize_size();
+    void *state = malloc(size);
+    bool saved = retro_serialize(state, size);
+    retro_run();
+    uint8_t counter = memory[COUNTER_ADDRESS];
+    bool loaded = retro_unserialize(state, size);
+    retro_run();
+    printf("state: %zu bytes, %s\n", size,
+           saved && loaded && memory[COUNTER_ADDRESS] == counter ? "replayed" : "different");
+
+    free(state);
+    retro_unload_game();
+    retro_deinit();
+    dlclose(core);
+    return 0;
+}
@@ -0,0 +1,102 @@
+// Loads the shared library as a libretro core in tests/libretro/harness.c, a minimal frontend
+mod common;
+
+use common::library_directory;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::OnceLock;
+
+const BUTTON_A: u32 = 1 << 8;
+
+// Draws 8 pixels, beeps while key 5 is held and counts its loops at 0x302:
+// LD I, 0x214; LD V0, 8; DRW V0, V0, 1; LD I, 0x300; LD V1, 5;
+// SKNP V1; LD ST, V1; ADD V2, 1; LD [I], V2; JP 0x20A
+const PROGRAM: [u8; 21] = [
+    0xA2, 0x14, 0x60, 0x08, 0xD0, 0x01, 0xA3, 0x00, 0x61, 0x05, 0xE1, 0xA1, 0xF1, 0x18, 0x72, 0x01,
+    0xF2, 0x55, 0x12, 0x0A, 0xFF,
+];
+
+// Compiled once for all the tests, with the ROM next to it
+fn build_harness() -> (PathBuf, PathBuf) {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let harness = directory.join("libretro_harness");
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/libretro/harness.c"))
+        .arg("-ldl")
+        .arg("-o")
+        .arg(&harness)
+        .status()
+        .expect("Unable to run the C compiler");
+    assert!(status.success());
+
+    let rom = directory.join("libretro_test.ch8");
+    std::fs::write(&rom, PROGRAM).unwrap();
+    (harness, rom)
+}
+
+fn harness_output(rom: Option<&Path>, frames: u32, buttons: u32, options: &[&str]) -> Output {
+    static HARNESS: OnceLock<(PathBuf, PathBuf)> = OnceLock::new();
+    let (harness, test_rom) = HARNESS.get_or_init(build_harness);
+    Command::new(harness)
+        .arg(library_directory().join("libchip8.so"))
+        .arg(rom.unwrap_or(test_rom))
+        .arg(frames.to_string())
+        .arg(buttons.to_string())
+        .args(options)
+        .output()
+        .unwrap()
+}
+
+fn run_harness(frames: u32, buttons: u32, options: &[&str]) -> String {
+    let output = harness_output(None, frames, buttons, options);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_core() {
+    let output = run_harness(2, 0, &[]);
+    assert_eq!(
+        output,
+        concat!(
+            "api: 1\n",
+            "library: CHIP-8 ",
+            env!("CARGO_PKG_VERSION"),
+            " (ch8|c8|sc8|xo8|gif|zip)\n",
+            "options: chip8_quirks chip8_speed\n",
+            "pixel format: 1\n",
+            "descriptors: 16\n",
+            "av: 64x32 60 fps 44100 Hz\n",
+            "video: 64x32, 8 lit\n",
+            "audio: 1470 frames, 0 loud\n",
+            "counter: 9\n",
+            "state: 6305 bytes, replayed\n",
+        )
+    );
+}
+
+#[test]
+fn test_input_and_options() {
+    let output = run_harness(2, BUTTON_A, &["chip8_speed=100"]);
+    assert!(output.contains("audio: 1470 frames, 2 loud\n"));
+    assert!(output.contains("counter: 39\n"));
+
+    let output = run_harness(2, BUTTON_A, &["chip8_speed=auto", "chip8_quirks=vip"]);
+    // I moves after every store with the quirks of the COSMAC VIP, so only the first count
+    // is stored at 0x302
+    assert!(output.contains("counter: 1\n"), "{}", output);
+}
+
+#[test]
+fn test_oversized_rom() {
+    let rom = Path::new(env!("CARGO_TARGET_TMPDIR")).join("libretro_oversized.ch8");
+    std::fs::write(&rom, [0x12; 5000]).unwrap();
+    let output = harness_output(Some(&rom), 1, 0, &[]);
+    // The core refuses the game instead of aborting the frontend
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Unable to load"));
+}